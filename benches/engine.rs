@@ -0,0 +1,110 @@
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use p_engine::{
+    engine::{
+        objects::TransactionDTO,
+        processor::{ProcessorConfig, ProcessorImpl},
+        router::{RouterConfig, run_scaled},
+    },
+    generator::{WorkloadConfig, WorkloadGenerator},
+    input::{format_input_line, parse_input_line},
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+fn workload(transactions: u64) -> Vec<TransactionDTO> {
+    WorkloadGenerator::new(WorkloadConfig {
+        clients: 10_000,
+        transactions,
+        ..Default::default()
+    })
+    .collect()
+}
+
+fn queued(transactions: &[TransactionDTO]) -> UnboundedReceiver<TransactionDTO> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    for tx in transactions {
+        _ = sender.send(tx.clone());
+    }
+    receiver
+}
+
+fn parsing(c: &mut Criterion) {
+    let lines: Vec<String> = workload(10_000).iter().map(format_input_line).collect();
+
+    let mut group = c.benchmark_group("parsing");
+    group.throughput(Throughput::Elements(lines.len() as u64));
+    group.bench_function("parse_input_line", |b| {
+        b.iter_batched(
+            || lines.clone(),
+            |lines| {
+                lines
+                    .into_iter()
+                    .filter_map(|line| parse_input_line(line).ok())
+                    .count()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn routing(c: &mut Criterion) {
+    let transactions = workload(100_000);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("run_scaled");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(transactions.len() as u64));
+    for shards in [1u16, 2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(shards),
+            &shards,
+            |b, &shards| {
+                b.iter_batched(
+                    || queued(&transactions),
+                    |receiver| {
+                        runtime.block_on(run_scaled(
+                            shards,
+                            receiver,
+                            None,
+                            ProcessorConfig::default(),
+                            RouterConfig::default(),
+                        ))
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn shard_processing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("processor");
+    group.sample_size(10);
+    // growing history shows how resolver's maps behave as they fill up
+    for size in [10_000u64, 100_000, 1_000_000] {
+        let transactions = workload(size);
+        group.throughput(Throughput::Elements(size));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &transactions,
+            |b, txs| {
+                b.iter_batched(
+                    || txs.clone(),
+                    |txs| {
+                        let mut processor = ProcessorImpl::new(0, ProcessorConfig::default());
+                        for tx in txs {
+                            _ = processor.process(tx);
+                        }
+                        processor
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parsing, routing, shard_processing);
+criterion_main!(benches);
@@ -0,0 +1,383 @@
+//! Processors spread over several engine processes (nodes), each owning a range of clients.
+//! Router forwards transactions to nodes over TCP, one input line per transaction; a node
+//! processes its share like a standalone run and, once its input ends, replies with
+//! final accounts of its clients and processors which didn't finish.
+
+use std::{error::Error, io, net::SocketAddr, ops::RangeInclusive};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+
+use crate::{
+    engine::{
+        Account,
+        admin::AdminCommand,
+        batch::{BatchAssembler, InputUnit},
+        clock::Timestamp,
+        objects::{ClientId, RawClientId, TransactionDTO},
+        processor::ProcessorConfig,
+        router::{RouterConfig, RunOutcome, run_scaled},
+    },
+    input::{format_input_line, parse_input_line},
+};
+
+/// Node and clients it owns.
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    pub address: SocketAddr,
+    pub clients: RangeInclusive<RawClientId>,
+}
+
+impl NodeConfig {
+    /// Reads node from `<address>=<first client>-<last client>`, e.g. `127.0.0.1:7001=0-999`.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let (address, clients) = text.split_once('=').ok_or("client range is missing")?;
+        let (first, last) = clients.split_once('-').ok_or("client range is invalid")?;
+        let clients = first.trim().parse()?..=last.trim().parse()?;
+        if clients.is_empty() {
+            return Err("client range is empty".into());
+        }
+        Ok(Self {
+            address: address.trim().parse()?,
+            clients,
+        })
+    }
+}
+
+/// Indexes of two nodes whose client ranges overlap, if there are any.
+pub fn overlapping(nodes: &[NodeConfig]) -> Option<(usize, usize)> {
+    (0..nodes.len()).find_map(|a| {
+        (a + 1..nodes.len())
+            .find(|b| {
+                let (a, b) = (&nodes[a].clients, &nodes[*b].clients);
+                a.start() <= b.end() && b.start() <= a.end()
+            })
+            .map(|b| (a, b))
+    })
+}
+
+pub struct ClusterOutcome {
+    /// Final state of accounts reported by nodes, ordered by client id.
+    pub accounts: Vec<Account>,
+    /// Nodes which couldn't be reached, failed or had processors which didn't finish.
+    pub failed: Vec<SocketAddr>,
+}
+
+impl ClusterOutcome {
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+// what a node replies with once its input ends
+#[derive(Default)]
+struct NodeReport {
+    accounts: Vec<Account>,
+    unfinished: Vec<u16>,
+    // reply ended with `end`, otherwise the node failed part way
+    complete: bool,
+}
+
+/// Forwards transactions to nodes owning their clients and gathers their final accounts.
+/// Transactions of clients no node owns are dropped with a report. Batch is applied
+/// atomically by the node owning all of its members; batch spanning nodes is dropped whole.
+pub async fn run_cluster(
+    mut rx: UnboundedReceiver<TransactionDTO>,
+    nodes: &[NodeConfig],
+) -> ClusterOutcome {
+    let mut senders = Vec::new();
+    let mut forwarders = Vec::new();
+    for node in nodes {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        senders.push(sender);
+        forwarders.push(tokio::spawn(forward(node.address, receiver)));
+    }
+    let owner = |tx: &TransactionDTO| {
+        nodes
+            .iter()
+            .position(|node| node.clients.contains(&*tx.client_id))
+    };
+    let mut batches = BatchAssembler::default();
+    let mut position = 0;
+    while let Some(transaction) = rx.recv().await {
+        position += 1;
+        for unit in batches.push(transaction, position) {
+            route(&senders, unit, owner);
+        }
+    }
+    if let Some(unit) = batches.finish() {
+        route(&senders, unit, owner);
+    }
+    // closing channels ends input of every node
+    drop(senders);
+
+    let mut accounts = Vec::new();
+    let mut failed = Vec::new();
+    for (node, forwarder) in nodes.iter().zip(forwarders) {
+        let report = forwarder.await.unwrap_or_default();
+        if !report.unfinished.is_empty() {
+            eprintln!(
+                "node {}: processors {:?} didn't finish",
+                node.address, report.unfinished
+            );
+        }
+        if !report.complete {
+            eprintln!("node {} didn't report its final state", node.address);
+        }
+        if !report.complete || !report.unfinished.is_empty() {
+            failed.push(node.address);
+        }
+        accounts.extend(report.accounts);
+    }
+    accounts.sort_by_key(|account| account.client_id.0);
+    ClusterOutcome { accounts, failed }
+}
+
+fn route(
+    senders: &[UnboundedSender<TransactionDTO>],
+    unit: InputUnit,
+    owner: impl Fn(&TransactionDTO) -> Option<usize>,
+) {
+    match unit {
+        InputUnit::Transaction(transaction, _) => match owner(&transaction) {
+            Some(node) => _ = senders[node].send(transaction),
+            None => eprintln!(
+                "no node owns client {}, transaction {} dropped",
+                *transaction.client_id,
+                transaction.id.to_u64()
+            ),
+        },
+        InputUnit::Batch(batch_id, members) => {
+            let node = owner(&members[0].0)
+                .filter(|node| members.iter().all(|(tx, _)| owner(tx) == Some(*node)));
+            let Some(node) = node else {
+                eprintln!("batch {batch_id} doesn't belong to a single node, dropped");
+                return;
+            };
+            for (transaction, _) in members {
+                _ = senders[node].send(transaction);
+            }
+        }
+    }
+}
+
+// sends transactions to the node until they run out, then reads its report
+async fn forward(
+    address: SocketAddr,
+    mut transactions: UnboundedReceiver<TransactionDTO>,
+) -> NodeReport {
+    let stream = match TcpStream::connect(address).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!(
+                "node {address} is unreachable: {e}, transactions of its clients are dropped"
+            );
+            return NodeReport::default();
+        }
+    };
+    let (reader, writer) = stream.into_split();
+    let mut writer = BufWriter::new(writer);
+    while let Some(transaction) = transactions.recv().await {
+        let mut line = format_input_line(&transaction);
+        line.push('\n');
+        let mut sent = writer.write_all(line.as_bytes()).await;
+        // buffered lines go out whenever router has nothing more for the node at the moment
+        if sent.is_ok() && transactions.is_empty() {
+            sent = writer.flush().await;
+        }
+        if let Err(e) = sent {
+            eprintln!("node {address}: connection failed: {e}");
+            return NodeReport::default();
+        }
+    }
+    if let Err(e) = writer.shutdown().await {
+        eprintln!("node {address}: connection failed: {e}");
+        return NodeReport::default();
+    }
+    let mut report = NodeReport::default();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match line.split_once(',') {
+            Some(("account", fields)) => match parse_account(fields) {
+                Some(account) => report.accounts.push(account),
+                None => eprintln!("node {address}: invalid account `{fields}`"),
+            },
+            Some(("unfinished", instance_id)) => {
+                report.unfinished.extend(instance_id.parse::<u16>().ok())
+            }
+            _ if line == "end" => report.complete = true,
+            _ => eprintln!("node {address}: unexpected reply `{line}`"),
+        }
+    }
+    report
+}
+
+/// Serves a single router connection: processes transactions it forwards over
+/// `instance_count` processors, then replies with final accounts and unfinished processors.
+/// Admin commands are taken from a source of the node's own, like in a standalone run.
+/// Shutdown stops taking transactions from the router, accounts are still reported.
+pub async fn serve_node(
+    listener: TcpListener,
+    instance_count: u16,
+    admin: Option<UnboundedReceiver<AdminCommand>>,
+    config: ProcessorConfig,
+    router: RouterConfig,
+) -> io::Result<RunOutcome> {
+    let (stream, peer) = listener.accept().await?;
+    eprintln!("router {peer} connected");
+    let (reader, mut writer) = stream.into_split();
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let intake = router.shutdown.clone();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if intake.is_requested() {
+                break;
+            }
+            match parse_input_line(line) {
+                Ok(transaction) => _ = sender.send(transaction),
+                Err(e) => eprintln!("router sent invalid transaction: {e}"),
+            }
+        }
+    });
+    let outcome = run_scaled(instance_count, receiver, admin, config, router).await;
+    let mut reply = String::new();
+    for account in &outcome.accounts {
+        reply.push_str(&format!("account,{}\n", format_account(account)));
+    }
+    for instance_id in &outcome.unfinished {
+        reply.push_str(&format!("unfinished,{instance_id}\n"));
+    }
+    reply.push_str("end\n");
+    writer.write_all(reply.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(outcome)
+}
+
+// every field of the account, written so that it reads back exactly
+pub(crate) fn format_account(account: &Account) -> String {
+    format!(
+        "{},{},{},{},{},{},{},{}",
+        *account.client_id,
+        account.available,
+        account.held,
+        account.reserved,
+        account.locked,
+        account.fees,
+        account.last_activity,
+        account.dormant
+    )
+}
+
+pub(crate) fn parse_account(fields: &str) -> Option<Account> {
+    let fields: Vec<&str> = fields.split(',').collect();
+    let [
+        client_id,
+        available,
+        held,
+        reserved,
+        locked,
+        fees,
+        last_activity,
+        dormant,
+    ] = fields.as_slice()
+    else {
+        return None;
+    };
+    Some(Account {
+        client_id: ClientId(client_id.parse().ok()?),
+        available: available.parse().ok()?,
+        held: held.parse().ok()?,
+        reserved: reserved.parse().ok()?,
+        locked: locked.parse().ok()?,
+        fees: fees.parse().ok()?,
+        last_activity: last_activity.parse::<Timestamp>().ok()?,
+        dormant: dormant.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use crate::engine::{
+        objects::{TransactionDTO, TxKind},
+        processor::ProcessorConfig,
+        router::RouterConfig,
+    };
+
+    use super::{NodeConfig, overlapping, run_cluster, serve_node};
+
+    #[tokio::test]
+    async fn nodes_process_their_clients() {
+        let mut nodes = Vec::new();
+        let mut served = Vec::new();
+        for clients in ["1-2", "3-4"] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            nodes.push(NodeConfig::parse(&format!("{address}={clients}")).unwrap());
+            served.push(tokio::spawn(serve_node(
+                listener,
+                2,
+                None,
+                ProcessorConfig::default(),
+                RouterConfig::default(),
+            )));
+        }
+        assert!(overlapping(&nodes).is_none());
+
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let tx = |id, client_id, kind, amount, batch_id| TransactionDTO {
+            batch_id,
+            ..TransactionDTO::new(id, client_id, kind, amount)
+        };
+        for transaction in [
+            tx(1, 1, TxKind::Deposit, Some(10.0), None),
+            tx(2, 3, TxKind::Deposit, Some(20.5), None),
+            tx(3, 4, TxKind::Deposit, Some(5.0), None),
+            tx(3, 4, TxKind::Dispute, None, None),
+            // no node owns client 5
+            tx(4, 5, TxKind::Deposit, Some(1.0), None),
+            // batch spanning both nodes is dropped
+            tx(5, 2, TxKind::Deposit, Some(1.0), Some(1)),
+            tx(6, 3, TxKind::Deposit, Some(1.0), Some(1)),
+            tx(7, 1, TxKind::Withdrawal, Some(4.0), Some(2)),
+            tx(8, 2, TxKind::Deposit, Some(4.0), Some(2)),
+        ] {
+            sender.send(transaction).unwrap();
+        }
+        drop(sender);
+        let outcome = run_cluster(receiver, &nodes).await;
+
+        assert!(outcome.is_complete());
+        let clients: Vec<_> = outcome
+            .accounts
+            .iter()
+            .map(|account| {
+                (
+                    *account.client_id,
+                    account.available.to_f64(),
+                    account.held.to_f64(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            clients,
+            vec![(1, 6.0, 0.0), (2, 4.0, 0.0), (3, 20.5, 0.0), (4, 0.0, 5.0)]
+        );
+        for served in served {
+            assert!(served.await.unwrap().unwrap().is_complete());
+        }
+        assert_eq!(
+            overlapping(&[
+                NodeConfig::parse("127.0.0.1:1=0-10").unwrap(),
+                NodeConfig::parse("127.0.0.1:2=20-30").unwrap(),
+                NodeConfig::parse("127.0.0.1:3=10-15").unwrap(),
+            ]),
+            Some((0, 2))
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use std::{error::Error, fmt};
+
+use super::{
+    clock::Timestamp,
+    core::account::Account,
+    objects::{Authorization, ClientId, DisputeClaim, ResolutionKind, TransactionId, TxAmount},
+};
+
+/// Privileged intervention in engine state, issued outside of transaction input.
+/// Every command is recorded in the audit log of the processor executing it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdminCommand {
+    /// Adds `amount` to available funds, takes it away when negative. Works on locked accounts.
+    CorrectBalance {
+        client_id: ClientId,
+        amount: TxAmount,
+        reason: String,
+    },
+    /// Closes open dispute with given outcome, even on locked account.
+    ForceCloseDispute {
+        client_id: ClientId,
+        tx_id: TransactionId,
+        resolution: ResolutionKind,
+        reason: String,
+    },
+    /// Locks account, so its transactions are rejected.
+    Freeze {
+        client_id: ClientId,
+        reason: String,
+    },
+    Unfreeze {
+        client_id: ClientId,
+        reason: String,
+    },
+    /// Processes transaction held for review, skipping the screen.
+    ApproveReview {
+        client_id: ClientId,
+        tx_id: TransactionId,
+        reason: String,
+    },
+    /// Drops transaction held for review without processing it.
+    RejectReview {
+        client_id: ClientId,
+        tx_id: TransactionId,
+        reason: String,
+    },
+    /// Lists transactions held for review by processor `shard`.
+    PendingReview {
+        shard: u16,
+    },
+    /// Drops every transaction held for review by processor `shard`, listing them.
+    DrainReviewQueue {
+        shard: u16,
+        reason: String,
+    },
+    /// Replies with state of processor `shard`.
+    DumpShard {
+        shard: u16,
+    },
+    /// Changes number of processors, moving clients to processors they now belong to.
+    /// Executed by the router, not by a processor.
+    Reshard {
+        shards: u16,
+    },
+}
+
+/// Executed admin command with its outcome.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEvent {
+    pub at: Timestamp,
+    pub command: String, // as parsed by `AdminCommand::parse`
+    pub outcome: String, // "ok" or rejection reason
+}
+
+/// State of a processor at the time of dump.
+#[derive(Clone)]
+pub struct ShardDump {
+    pub shard: u16,
+    pub last_checkpoint: Option<u64>,
+    pub accounts: Vec<Account>,
+    pub disputes: Vec<DisputeClaim>,
+    pub authorizations: Vec<Authorization>,
+    pub scheduled: usize,
+    pub under_review: usize,
+}
+
+impl AdminCommand {
+    /// Reads command from line `correct, <client>, <amount>, <reason>`,
+    /// `close_dispute, <client>, <tx>, resolve|chargeback, <reason>`, `freeze, <client>, <reason>`,
+    /// `unfreeze, <client>, <reason>`, `approve|reject, <client>, <tx>, <reason>`,
+    /// `pending, <shard>`, `drain_review, <shard>, <reason>`, `dump, <shard>`
+    /// or `reshard, <processors>`.
+    /// Reason code can't be empty.
+    pub fn parse(line: &str) -> Result<Self, Box<dyn Error>> {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let reason = |reason: &str| match reason.is_empty() {
+            true => Err("reason code is missing"),
+            false => Ok(reason.to_string()),
+        };
+        Ok(match fields.as_slice() {
+            ["correct", client_id, amount, reason_code] => AdminCommand::CorrectBalance {
+                client_id: ClientId(client_id.parse()?),
+                amount: amount.parse()?,
+                reason: reason(reason_code)?,
+            },
+            ["close_dispute", client_id, tx_id, resolution, reason_code] => {
+                AdminCommand::ForceCloseDispute {
+                    client_id: ClientId(client_id.parse()?),
+                    tx_id: TransactionId(tx_id.parse()?),
+                    resolution: match *resolution {
+                        "resolve" => ResolutionKind::Resolve,
+                        "chargeback" => ResolutionKind::Chargeback,
+                        _ => return Err("dispute is closed with resolve or chargeback".into()),
+                    },
+                    reason: reason(reason_code)?,
+                }
+            }
+            ["freeze", client_id, reason_code] => AdminCommand::Freeze {
+                client_id: ClientId(client_id.parse()?),
+                reason: reason(reason_code)?,
+            },
+            ["unfreeze", client_id, reason_code] => AdminCommand::Unfreeze {
+                client_id: ClientId(client_id.parse()?),
+                reason: reason(reason_code)?,
+            },
+            ["approve", client_id, tx_id, reason_code] => AdminCommand::ApproveReview {
+                client_id: ClientId(client_id.parse()?),
+                tx_id: TransactionId(tx_id.parse()?),
+                reason: reason(reason_code)?,
+            },
+            ["reject", client_id, tx_id, reason_code] => AdminCommand::RejectReview {
+                client_id: ClientId(client_id.parse()?),
+                tx_id: TransactionId(tx_id.parse()?),
+                reason: reason(reason_code)?,
+            },
+            ["pending", shard] => AdminCommand::PendingReview {
+                shard: shard.parse()?,
+            },
+            ["drain_review", shard, reason_code] => AdminCommand::DrainReviewQueue {
+                shard: shard.parse()?,
+                reason: reason(reason_code)?,
+            },
+            ["dump", shard] => AdminCommand::DumpShard {
+                shard: shard.parse()?,
+            },
+            ["reshard", shards] => AdminCommand::Reshard {
+                shards: shards.parse()?,
+            },
+            _ => return Err("unknown admin command".into()),
+        })
+    }
+
+    /// Client whose processor executes the command, none for commands naming their shard.
+    pub fn client_id(&self) -> Option<ClientId> {
+        match self {
+            AdminCommand::CorrectBalance { client_id, .. }
+            | AdminCommand::ForceCloseDispute { client_id, .. }
+            | AdminCommand::Freeze { client_id, .. }
+            | AdminCommand::Unfreeze { client_id, .. }
+            | AdminCommand::ApproveReview { client_id, .. }
+            | AdminCommand::RejectReview { client_id, .. } => Some(*client_id),
+            _ => None,
+        }
+    }
+
+    /// Processor named by the command, none for commands of a client and resharding.
+    pub fn shard(&self) -> Option<u16> {
+        match self {
+            AdminCommand::PendingReview { shard }
+            | AdminCommand::DrainReviewQueue { shard, .. }
+            | AdminCommand::DumpShard { shard } => Some(*shard),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AdminCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminCommand::CorrectBalance {
+                client_id,
+                amount,
+                reason,
+            } => write!(f, "correct, {}, {amount}, {reason}", **client_id),
+            AdminCommand::ForceCloseDispute {
+                client_id,
+                tx_id,
+                resolution,
+                reason,
+            } => {
+                let resolution = match resolution {
+                    ResolutionKind::Resolve => "resolve",
+                    ResolutionKind::Chargeback => "chargeback",
+                };
+                write!(
+                    f,
+                    "close_dispute, {}, {}, {resolution}, {reason}",
+                    **client_id, **tx_id
+                )
+            }
+            AdminCommand::Freeze { client_id, reason } => {
+                write!(f, "freeze, {}, {reason}", **client_id)
+            }
+            AdminCommand::Unfreeze { client_id, reason } => {
+                write!(f, "unfreeze, {}, {reason}", **client_id)
+            }
+            AdminCommand::ApproveReview {
+                client_id,
+                tx_id,
+                reason,
+            } => write!(f, "approve, {}, {}, {reason}", **client_id, **tx_id),
+            AdminCommand::RejectReview {
+                client_id,
+                tx_id,
+                reason,
+            } => write!(f, "reject, {}, {}, {reason}", **client_id, **tx_id),
+            AdminCommand::PendingReview { shard } => write!(f, "pending, {shard}"),
+            AdminCommand::DrainReviewQueue { shard, reason } => {
+                write!(f, "drain_review, {shard}, {reason}")
+            }
+            AdminCommand::DumpShard { shard } => write!(f, "dump, {shard}"),
+            AdminCommand::Reshard { shards } => write!(f, "reshard, {shards}"),
+        }
+    }
+}
+
+impl ShardDump {
+    pub fn to_lines(&self) -> Vec<String> {
+        let checkpoint = self
+            .last_checkpoint
+            .map_or("none".to_string(), |position| position.to_string());
+        let mut lines = vec![format!(
+            "shard {}: checkpoint {checkpoint}, {} scheduled, {} under review",
+            self.shard, self.scheduled, self.under_review
+        )];
+        lines.extend(
+            self.accounts
+                .iter()
+                .map(|account| format!("account,{}", account.to_csv(true))),
+        );
+        lines.extend(self.disputes.iter().map(|claim| {
+            format!(
+                "dispute,{},{},{}",
+                *claim.client_id, claim.amount, claim.fee
+            )
+        }));
+        lines.extend(self.authorizations.iter().map(|authorization| {
+            format!(
+                "authorization,{},{},{}",
+                *authorization.client_id, authorization.amount, authorization.authorized_at
+            )
+        }));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        EngineError,
+        objects::{ClientId, TransactionDTO, TxAmount, TxKind},
+        processor::{ProcessorConfig, ProcessorImpl},
+    };
+
+    use super::AdminCommand;
+
+    #[test]
+    fn admin_commands_are_audited() {
+        let config = ProcessorConfig {
+            double_entry: true,
+            ..Default::default()
+        };
+        let mut processor = ProcessorImpl::new(0, config);
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+        let admin = |line| AdminCommand::parse(line).unwrap();
+
+        assert!(
+            processor
+                .process(tx(1, TxKind::Deposit, Some(100.0)))
+                .is_ok()
+        );
+        assert!(processor.process(tx(1, TxKind::Dispute, None)).is_ok());
+        assert!(
+            processor
+                .administer(admin("freeze, 1, fraud-review"))
+                .is_ok()
+        );
+        assert!(matches!(
+            processor.process(tx(2, TxKind::Deposit, Some(5.0))),
+            Err(EngineError::Account_AccountLocked)
+        ));
+        assert!(
+            processor
+                .administer(admin("close_dispute, 1, 1, resolve, fraud-cleared"))
+                .is_ok()
+        );
+        assert!(
+            processor
+                .administer(admin("correct, 1, -150, typo"))
+                .is_err()
+        );
+        assert!(
+            processor
+                .administer(admin("correct, 1, -12.5, duplicate-credit"))
+                .is_ok()
+        );
+        assert!(processor.administer(admin("unfreeze, 1, cleared")).is_ok());
+        let dump = processor.administer(admin("dump, 0")).unwrap().unwrap();
+        assert_eq!(
+            dump.to_lines()[1],
+            "account,1,87.5000,0.0000,87.5000,false,0.0000"
+        );
+        assert!(processor.reconcile().unwrap().is_empty());
+
+        let audit = processor.audit_log().unwrap();
+        assert_eq!(audit.len(), 6);
+        assert_eq!(audit[2].command, "correct, 1, -150.0000, typo");
+        assert_eq!(audit[2].outcome, "Account_NotEnoughFunds");
+        assert!(AdminCommand::parse("freeze, 1,").is_err());
+    }
+
+    #[test]
+    fn charged_back_transaction_cant_be_disputed_after_unfreeze() {
+        let config = ProcessorConfig {
+            double_entry: true,
+            ..Default::default()
+        };
+        let mut processor = ProcessorImpl::new(0, config);
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+
+        for (id, amount) in [(1, 100.0), (2, 50.0)] {
+            assert!(
+                processor
+                    .process(tx(id, TxKind::Deposit, Some(amount)))
+                    .is_ok()
+            );
+        }
+        for kind in [TxKind::Dispute, TxKind::Chargeback] {
+            assert!(processor.process(tx(1, kind, None)).is_ok());
+        }
+        assert!(
+            processor
+                .administer(AdminCommand::parse("unfreeze, 1, ops").unwrap())
+                .is_ok()
+        );
+        assert!(matches!(
+            processor.process(tx(1, TxKind::Dispute, None)),
+            Err(EngineError::Resolver_TransactionAlreadyChargedBack)
+        ));
+
+        let account = processor.account(&ClientId(1)).unwrap().unwrap();
+        assert_eq!(
+            (account.available, account.held, account.locked),
+            (TxAmount::from_f64(50.0), TxAmount::ZERO, false)
+        );
+        assert!(processor.reconcile().unwrap().is_empty());
+    }
+}
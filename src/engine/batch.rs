@@ -0,0 +1,59 @@
+use super::objects::TransactionDTO;
+
+pub type BatchId = u64;
+
+/// Transaction with its position (1-based ordinal) in input stream.
+pub type PositionedTransaction = (TransactionDTO, u64);
+
+/// Piece of input processed as a whole.
+#[derive(Clone)]
+pub enum InputUnit {
+    Transaction(TransactionDTO, u64),
+    /// Members of a batch, applied all or none.
+    Batch(BatchId, Vec<PositionedTransaction>),
+}
+
+/// Groups consecutive input transactions of the same batch. Batch is complete once
+/// a transaction without its batch id follows, or input ends.
+#[derive(Default)]
+pub struct BatchAssembler {
+    pending: Option<(BatchId, Vec<PositionedTransaction>)>,
+}
+
+impl BatchAssembler {
+    /// Takes next input transaction, returning units it completes, in input order.
+    pub fn push(&mut self, tx: TransactionDTO, position: u64) -> Vec<InputUnit> {
+        let mut units = Vec::new();
+        if let Some((batch_id, members)) = &mut self.pending {
+            if tx.batch_id == Some(*batch_id) {
+                members.push((tx, position));
+                return units;
+            }
+            units.extend(self.finish());
+        }
+        match tx.batch_id {
+            Some(batch_id) => self.pending = Some((batch_id, vec![(tx, position)])),
+            None => units.push(InputUnit::Transaction(tx, position)),
+        }
+        units
+    }
+
+    /// Completes batch still being assembled, when input ends.
+    pub fn finish(&mut self) -> Option<InputUnit> {
+        self.pending
+            .take()
+            .map(|(batch_id, members)| InputUnit::Batch(batch_id, members))
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Input position of the first member of batch still being assembled.
+    pub fn first_pending(&self) -> Option<u64> {
+        self.pending
+            .as_ref()
+            .and_then(|(_, members)| members.first())
+            .map(|(_, position)| *position)
+    }
+}
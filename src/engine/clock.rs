@@ -0,0 +1,62 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// simulated time can move at any moment, so waiting for it means polling
+const SIMULATED_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Seconds since Unix epoch.
+pub type Timestamp = u64;
+
+/// Source of current time for time-dependent rules. Simulated clock only moves
+/// when told to, so time-based behavior can be tested and replayed.
+#[derive(Clone, Debug, Default)]
+pub enum Clock {
+    #[default]
+    System,
+    Simulated(Arc<AtomicU64>),
+}
+
+impl Clock {
+    pub fn simulated(start: Timestamp) -> Self {
+        Clock::Simulated(Arc::new(AtomicU64::new(start)))
+    }
+
+    pub fn now(&self) -> Timestamp {
+        match self {
+            Clock::System => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            Clock::Simulated(now) => now.load(Ordering::Relaxed),
+        }
+    }
+
+    /// How long to wait before checking whether time `at` has come.
+    pub fn until(&self, at: Timestamp) -> Duration {
+        let now = self.now();
+        match self {
+            _ if at <= now => Duration::ZERO,
+            Clock::System => Duration::from_secs(at - now),
+            Clock::Simulated(_) => SIMULATED_POLL_INTERVAL,
+        }
+    }
+
+    /// Moves simulated clock forward, system clock can't be moved.
+    pub fn advance(&self, seconds: u64) {
+        if let Clock::Simulated(now) = self {
+            now.fetch_add(seconds, Ordering::Relaxed);
+        }
+    }
+
+    /// Moves simulated clock to `at`, e.g. to follow time of another processor.
+    pub fn set(&self, at: Timestamp) {
+        if let Clock::Simulated(now) = self {
+            now.store(at, Ordering::Relaxed);
+        }
+    }
+}
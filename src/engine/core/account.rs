@@ -1,56 +1,77 @@
-use std::ops::Deref;
-
 use crate::engine::{
     EngineError,
+    clock::Timestamp,
     objects::{
-        Adjustment, AdjustmentKind, ClientId, DisputeClaim, ResolutionKind, TransactionDTO,
-        TransactionId,
+        Adjustment, AdjustmentKind, Authorization, ClientId, DisputeClaim, ResolutionKind,
+        TransactionDTO, TransactionId, TxAmount, TxDetails,
     },
 };
 
-
+#[derive(Clone)]
 pub struct Account {
     pub client_id: ClientId,
-    pub available: f32, // for trading
-    pub held: f32,      // for disputes
+    pub available: TxAmount, // for trading
+    pub held: TxAmount,      // for disputes
+    pub reserved: TxAmount,  // for authorizations
     pub locked: bool,
+    pub fees: TxAmount, // collected from this client
+    pub last_activity: Timestamp,
+    pub dormant: bool, // flagged by dormancy job, cleared by next transaction
 }
 
 impl Account {
     pub fn new(client_id: ClientId) -> Self {
         Account {
             client_id,
-            available: 0.0,
-            held: 0.0,
+            available: TxAmount::ZERO,
+            held: TxAmount::ZERO,
+            reserved: TxAmount::ZERO,
             locked: false,
+            fees: TxAmount::ZERO,
+            last_activity: 0,
+            dormant: false,
         }
     }
 
-    pub fn to_csv(&self) -> String {
-        let total = self.available + self.held;
-        format!(
-            "{},{:.4},{:.4},{:.4},{}",
+    /// `client,available,held,total,locked`, followed by `reserved` when `with_reserved`.
+    pub fn to_csv(&self, with_reserved: bool) -> String {
+        let total = self.available + self.held + self.reserved;
+        let line = format!(
+            "{},{},{},{},{}",
             *self.client_id, self.available, self.held, total, self.locked
-        )
+        );
+        match with_reserved {
+            true => format!("{line},{}", self.reserved),
+            false => line,
+        }
     }
 
-    pub fn apply_adjustment(&mut self, tx: TransactionDTO) -> Result<Adjustment, EngineError> {
+    /// Applies deposit or withdrawal, charging `fee` on top of it.
+    pub fn apply_adjustment(
+        &mut self,
+        tx: TransactionDTO,
+        fee: TxAmount,
+    ) -> Result<Adjustment, EngineError> {
         self.check_account_lock()?;
-        let adjustment: Adjustment = tx.try_into()?;
-        let amount = adjustment.amount.deref();
+        let adjustment = Adjustment {
+            fee,
+            ..tx.try_into()?
+        };
+        let amount = adjustment.amount;
         match adjustment.category {
             AdjustmentKind::Deposit => {
-                self.available += amount;
+                self.available += amount - fee;
             }
             AdjustmentKind::Withdrawal => {
-                let new_balance = self.available - amount;
-                if new_balance >= 0.0f32 {
+                let new_balance = self.available - amount - fee;
+                if new_balance >= TxAmount::ZERO {
                     self.available = new_balance;
                 } else {
                     return Err(EngineError::Account_NotEnoughFunds);
                 }
             }
         }
+        self.fees += fee;
         Ok(adjustment)
     }
 
@@ -63,22 +84,17 @@ impl Account {
             return Err(EngineError::Account_DisputeReferencesDifferentClient_OnCreation);
         }
 
-        let amount = disputed_adjustment.amount.deref();
-        match disputed_adjustment.category {
-            AdjustmentKind::Deposit => {
-                self.available -= amount;
-                self.held += amount;
-            }
-            AdjustmentKind::Withdrawal => {
-                // not giving money in advance - no provisional refund here ;)
-            }
-        }
-
-        Ok(DisputeClaim {
+        let claim = DisputeClaim {
             client_id: disputed_adjustment.details.client_id,
             kind: disputed_adjustment.category,
             amount: disputed_adjustment.amount,
-        })
+            fee: disputed_adjustment.fee,
+        };
+        // not giving money in advance for withdrawals - no provisional refund here ;)
+        self.available -= claim.held();
+        self.held += claim.held();
+
+        Ok(claim)
     }
 
     pub fn resolve_dispute(
@@ -90,6 +106,8 @@ impl Account {
     ) -> Result<TransactionId, EngineError> {
         self.check_account_lock()?;
         let amount = claim.amount;
+        let fee = claim.fee;
+        let held = claim.held();
 
         if &claim.client_id != tx_client_id {
             return Err(EngineError::Account_DisputeReferencesDifferentClient_OnResolution);
@@ -97,19 +115,21 @@ impl Account {
 
         match (claim.kind, resolution_category) {
             (AdjustmentKind::Deposit, ResolutionKind::Resolve) => {
-                self.available += *amount;
-                self.held -= *amount;
+                self.available += held;
+                self.held -= held;
             }
             (AdjustmentKind::Deposit, ResolutionKind::Chargeback) => {
-                let total = self.held + self.available;
-                if *amount > total {
+                if held > self.held + self.available {
                     return Err(EngineError::Account_NotEnoughFunds);
                 }
-                self.held -= *amount;
+                // deposit is reverted together with its fee
+                self.held -= held;
+                self.fees -= fee;
                 self.locked = true;
             }
             (AdjustmentKind::Withdrawal, ResolutionKind::Chargeback) => {
-                self.available += *amount;
+                self.available += amount + fee;
+                self.fees -= fee;
             }
             (AdjustmentKind::Withdrawal, ResolutionKind::Resolve) => {
                 // no provisional refunds were made when opening a dispute, so it's a no-op
@@ -119,6 +139,144 @@ impl Account {
         Ok(*tx_id)
     }
 
+    /// Undoes adjustment together with its fee.
+    pub fn reverse(
+        &mut self,
+        adjustment: &Adjustment,
+        tx_client_id: &ClientId,
+    ) -> Result<(), EngineError> {
+        self.check_account_lock()?;
+        if &adjustment.details.client_id != tx_client_id {
+            return Err(EngineError::Account_ReversalReferencesDifferentClient);
+        }
+        let (amount, fee) = (adjustment.amount, adjustment.fee);
+        match adjustment.category {
+            AdjustmentKind::Deposit => {
+                if self.available - (amount - fee) < TxAmount::ZERO {
+                    return Err(EngineError::Account_NotEnoughFunds);
+                }
+                self.available -= amount - fee;
+            }
+            AdjustmentKind::Withdrawal => self.available += amount + fee,
+        }
+        self.fees -= fee;
+        Ok(())
+    }
+
+    /// Moves authorized amount from available to reserved funds.
+    pub fn authorize(
+        &mut self,
+        tx: &TransactionDTO,
+        authorized_at: Timestamp,
+    ) -> Result<Authorization, EngineError> {
+        self.check_account_lock()?;
+        let amount = tx
+            .amount
+            .ok_or(EngineError::Parsing_MissingAmountFieldConstructingAuthorization)?;
+        if amount <= TxAmount::ZERO {
+            return Err(EngineError::Parsing_NonPositiveAmount);
+        }
+        if self.available - amount < TxAmount::ZERO {
+            return Err(EngineError::Account_NotEnoughFunds);
+        }
+        self.available -= amount;
+        self.reserved += amount;
+        Ok(Authorization {
+            client_id: tx.client_id,
+            amount,
+            authorized_at,
+        })
+    }
+
+    /// Withdraws reserved funds of `authorization`, charging withdrawal `fee` on top of it
+    /// from available funds, and returns the withdrawal.
+    pub fn capture(
+        &mut self,
+        authorization: &Authorization,
+        tx_id: &TransactionId,
+        tx_client_id: &ClientId,
+        fee: TxAmount,
+    ) -> Result<Adjustment, EngineError> {
+        self.check_account_lock()?;
+        if &authorization.client_id != tx_client_id {
+            return Err(EngineError::Account_AuthorizationReferencesDifferentClient);
+        }
+        // captured amount is already reserved, only the fee needs funds
+        if fee > TxAmount::ZERO && self.available < fee {
+            return Err(EngineError::Account_NotEnoughFunds);
+        }
+        self.reserved -= authorization.amount;
+        self.available -= fee;
+        self.fees += fee;
+        Ok(Adjustment {
+            category: AdjustmentKind::Withdrawal,
+            details: TxDetails {
+                id: *tx_id,
+                client_id: authorization.client_id,
+            },
+            amount: authorization.amount,
+            fee,
+        })
+    }
+
+    pub fn void(
+        &mut self,
+        authorization: &Authorization,
+        tx_client_id: &ClientId,
+    ) -> Result<(), EngineError> {
+        self.check_account_lock()?;
+        if &authorization.client_id != tx_client_id {
+            return Err(EngineError::Account_AuthorizationReferencesDifferentClient);
+        }
+        self.release(authorization);
+        Ok(())
+    }
+
+    /// Returns reserved funds of expired authorization. Works on locked accounts too.
+    pub fn release(&mut self, authorization: &Authorization) {
+        self.reserved -= authorization.amount;
+        self.available += authorization.amount;
+    }
+
+    /// Credits interest at `rate` percent of available funds, rounded to 4 decimal places.
+    /// Returns credited amount, nothing is credited to locked accounts.
+    pub fn accrue_interest(&mut self, rate: f64) -> TxAmount {
+        if self.locked || self.available <= TxAmount::ZERO {
+            return TxAmount::ZERO;
+        }
+        let interest = self.available.percent(rate);
+        self.available += interest;
+        interest
+    }
+
+    /// Charges maintenance fee, at most what's available. Returns charged amount,
+    /// locked accounts aren't charged.
+    pub fn charge_maintenance_fee(&mut self, fee: TxAmount) -> TxAmount {
+        if self.locked {
+            return TxAmount::ZERO;
+        }
+        self.charge_fee(fee)
+    }
+
+    /// Adds `amount` to available funds, or takes it away when negative, as long as funds suffice.
+    /// Works on locked accounts too.
+    pub fn correct(&mut self, amount: TxAmount) -> Result<(), EngineError> {
+        if self.available + amount < TxAmount::ZERO {
+            return Err(EngineError::Account_NotEnoughFunds);
+        }
+        self.available += amount;
+        Ok(())
+    }
+
+    /// Charges fee not tied to an adjustment, e.g. for a chargeback, as far as available funds
+    /// cover it, returning the fee charged. Works on locked accounts too.
+    pub fn charge_fee(&mut self, fee: TxAmount) -> TxAmount {
+        let fee = fee.min(self.available).max(TxAmount::ZERO);
+        self.available -= fee;
+        self.fees += fee;
+        fee
+    }
+
     fn check_account_lock(&self) -> Result<(), EngineError> {
         if self.locked {
             Err(EngineError::Account_AccountLocked)
@@ -130,26 +288,48 @@ impl Account {
 
 #[cfg(test)]
 mod tests {
-    use crate::engine::objects::{
-        AdjustmentKind, ClientId, DisputeClaim, TransactionDTO, TransactionId, TxAmount, TxKind,
+    use crate::engine::{
+        EngineError,
+        objects::{AdjustmentKind, ClientId, DisputeClaim, TransactionDTO, TxAmount, TxKind},
     };
 
     use super::Account;
 
+    #[test]
+    fn non_positive_amounts_are_rejected() {
+        let mut account = Account {
+            available: TxAmount::from_f64(100.0),
+            ..Account::new(ClientId(1))
+        };
+
+        for (kind, amount) in [
+            (TxKind::Deposit, -100.0),
+            (TxKind::Withdrawal, -100.0),
+            (TxKind::Deposit, 0.0),
+        ] {
+            let tx = TransactionDTO::new(1, 1, kind, Some(amount));
+            assert!(matches!(
+                account.apply_adjustment(tx, TxAmount::ZERO),
+                Err(EngineError::Parsing_NonPositiveAmount)
+            ));
+        }
+        let authorization = TransactionDTO::new(2, 1, TxKind::Authorize, Some(-10.0));
+        assert!(matches!(
+            account.authorize(&authorization, 0),
+            Err(EngineError::Parsing_NonPositiveAmount)
+        ));
+        assert_eq!(account.available, TxAmount::from_f64(100.0));
+    }
+
     #[test]
     fn adjustment_fails_on_locked_account() {
         let mut account = Account {
             locked: true,
             ..Account::new(ClientId(1))
         };
-        let tx = TransactionDTO {
-            id: TransactionId(1),
-            client_id: ClientId(1),
-            kind: TxKind::Deposit,
-            amount: Some(100.0),
-        };
+        let tx = TransactionDTO::new(1, 1, TxKind::Deposit, Some(100.0));
 
-        let res = account.apply_adjustment(tx);
+        let res = account.apply_adjustment(tx, TxAmount::ZERO);
 
         assert!(res.is_err())
     }
@@ -157,131 +337,103 @@ mod tests {
     #[test]
     fn withdrawal_fails_when_insufficient_funds() {
         let mut account = Account {
-            available: 100.0,
+            available: TxAmount::from_f64(100.0),
             ..Account::new(ClientId(1))
         };
-        let tx = TransactionDTO {
-            id: TransactionId(1),
-            client_id: ClientId(1),
-            kind: TxKind::Withdrawal,
-            amount: Some(110.0),
-        };
+        let tx = TransactionDTO::new(1, 1, TxKind::Withdrawal, Some(110.0));
 
-        let res = account.apply_adjustment(tx);
+        let res = account.apply_adjustment(tx, TxAmount::ZERO);
 
         assert!(res.is_err());
-        assert_eq!(account.available, 100.0);
-        assert_eq!(account.held, 0.0);
+        assert_eq!(account.available, TxAmount::from_f64(100.0));
+        assert_eq!(account.held, TxAmount::ZERO);
     }
 
     #[test]
     fn dispute_on_deposit_blocks_funds() {
         let mut account = Account {
-            available: 100.0,
+            available: TxAmount::from_f64(100.0),
             ..Account::new(ClientId(1))
         };
-        let tx = TransactionDTO {
-            id: TransactionId(1),
-            client_id: ClientId(1),
-            kind: TxKind::Deposit,
-            amount: Some(50.0),
-        };
+        let tx = TransactionDTO::new(1, 1, TxKind::Deposit, Some(50.0));
 
-        let adjustment = account.apply_adjustment(tx).unwrap();
+        let adjustment = account.apply_adjustment(tx, TxAmount::ZERO).unwrap();
 
-        assert_eq!(account.available, 150.0);
-        assert_eq!(account.held, 0.0);
+        assert_eq!(account.available, TxAmount::from_f64(150.0));
+        assert_eq!(account.held, TxAmount::ZERO);
 
         let res = account.open_dispute(&adjustment);
 
         assert!(res.is_ok());
-        assert_eq!(account.available, 100.0);
-        assert_eq!(account.held, 50.0);
+        assert_eq!(account.available, TxAmount::from_f64(100.0));
+        assert_eq!(account.held, TxAmount::from_f64(50.0));
     }
 
     #[test]
     fn resolution_on_disputed_deposit_unblocks_funds() {
         let mut account = Account {
-            available: 100.0,
-            held: 100.0,
+            available: TxAmount::from_f64(100.0),
+            held: TxAmount::from_f64(100.0),
             ..Account::new(ClientId(1))
         };
         let claim = DisputeClaim {
             client_id: ClientId(1),
             kind: AdjustmentKind::Deposit,
-            amount: TxAmount(50.0),
-        };
-        let tx = TransactionDTO {
-            id: TransactionId(0),
-            client_id: ClientId(1),
-            kind: TxKind::Resolve,
-            amount: None,
+            amount: TxAmount::from_f64(50.0),
+            fee: TxAmount::ZERO,
         };
+        let tx = TransactionDTO::new(0, 1, TxKind::Resolve, None);
 
         let res =
             account.resolve_dispute(&claim, &tx.id, &tx.client_id, &tx.kind.try_into().unwrap());
 
         assert!(res.is_ok());
-        assert_eq!(account.available, 150.0);
-        assert_eq!(account.held, 50.0);
+        assert_eq!(account.available, TxAmount::from_f64(150.0));
+        assert_eq!(account.held, TxAmount::from_f64(50.0));
     }
 
     #[test]
     fn chargeback_on_disputed_deposit_decreases_funds() {
         let mut account = Account {
-            available: 100.0,
-            held: 100.0,
+            available: TxAmount::from_f64(100.0),
+            held: TxAmount::from_f64(100.0),
             ..Account::new(ClientId(1))
         };
         let claim = DisputeClaim {
             client_id: ClientId(1),
             kind: AdjustmentKind::Deposit,
-            amount: TxAmount(50.0),
+            amount: TxAmount::from_f64(50.0),
+            fee: TxAmount::ZERO,
         };
 
-        let tx = TransactionDTO {
-            id: TransactionId(0),
-            client_id: ClientId(1),
-            kind: TxKind::Chargeback,
-            amount: None,
-        };
+        let tx = TransactionDTO::new(0, 1, TxKind::Chargeback, None);
 
         let res =
             account.resolve_dispute(&claim, &tx.id, &tx.client_id, &tx.kind.try_into().unwrap());
 
         assert!(res.is_ok());
         assert!(account.locked);
-        assert_eq!(account.available, 100.0);
-        assert_eq!(account.held, 50.0);
+        assert_eq!(account.available, TxAmount::from_f64(100.0));
+        assert_eq!(account.held, TxAmount::from_f64(50.0));
     }
 
     #[test]
     fn deposit_and_withdraw_are_processed_succesfully() {
         let mut account = Account {
-            available: 100.0,
+            available: TxAmount::from_f64(100.0),
             ..Account::new(ClientId(1))
         };
-        let tx0 = TransactionDTO {
-            id: TransactionId(1),
-            client_id: ClientId(1),
-            kind: TxKind::Deposit,
-            amount: Some(50.0),
-        };
-        let tx1 = TransactionDTO {
-            id: TransactionId(2),
-            client_id: ClientId(1),
-            kind: TxKind::Withdrawal,
-            amount: Some(50.0),
-        };
+        let tx0 = TransactionDTO::new(1, 1, TxKind::Deposit, Some(50.0));
+        let tx1 = TransactionDTO::new(2, 1, TxKind::Withdrawal, Some(50.0));
 
-        let _adjustment = account.apply_adjustment(tx0).unwrap();
+        let _adjustment = account.apply_adjustment(tx0, TxAmount::ZERO).unwrap();
 
-        assert_eq!(account.available, 150.0);
-        assert_eq!(account.held, 0.0);
+        assert_eq!(account.available, TxAmount::from_f64(150.0));
+        assert_eq!(account.held, TxAmount::ZERO);
 
-        let _adjustment = account.apply_adjustment(tx1).unwrap();
+        let _adjustment = account.apply_adjustment(tx1, TxAmount::ZERO).unwrap();
 
-        assert_eq!(account.available, 100.0);
-        assert_eq!(account.held, 0.0);
+        assert_eq!(account.available, TxAmount::from_f64(100.0));
+        assert_eq!(account.held, TxAmount::ZERO);
     }
 }
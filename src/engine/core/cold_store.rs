@@ -0,0 +1,294 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::engine::objects::{
+    Adjustment, AdjustmentKind, ClientId, RawClientId, RawTransactionId, TransactionId, TxAmount,
+    TxDetails,
+};
+
+// slot layout: flags, tx id, client id, adjustment kind, amount, fee
+const OCCUPIED: u8 = 1;
+const REVERSED: u8 = 2;
+const ID_AT: usize = 1;
+const CLIENT_AT: usize = ID_AT + size_of::<RawTransactionId>();
+const KIND_AT: usize = CLIENT_AT + size_of::<RawClientId>();
+const AMOUNT_AT: usize = KIND_AT + 1;
+const FEE_AT: usize = AMOUNT_AT + size_of::<i64>();
+const SLOT_SIZE: usize = FEE_AT + size_of::<i64>();
+const INITIAL_SLOTS: u64 = 1024;
+const ITER_CHUNK_SLOTS: u64 = 256;
+
+/// On-disk hash table of adjustments and their reversal flags, keyed by transaction id.
+/// Uses open addressing with linear probing over fixed-size slots, so nothing but
+/// the file handle and a couple of counters is kept in memory.
+pub struct ColdStore {
+    path: PathBuf,
+    file: File,
+    slots: u64,
+    len: u64,
+}
+
+impl ColdStore {
+    /// Creates an empty store, replacing file at `path` if it exists.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::with_slots(path, INITIAL_SLOTS)
+    }
+
+    fn with_slots(path: &Path, slots: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(slots * SLOT_SIZE as u64)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            slots,
+            len: 0,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn put(&mut self, adjustment: &Adjustment, reversed: bool) -> io::Result<()> {
+        // keep load factor at most 1/2 so probe sequences stay short
+        if (self.len + 1) * 2 > self.slots {
+            self.grow()?;
+        }
+        let (slot, existing) = self.find_slot(&adjustment.details.id)?;
+        self.write_slot(slot, adjustment, reversed)?;
+        if existing.is_none() {
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, tx_id: &TransactionId) -> io::Result<Option<Adjustment>> {
+        self.find_slot(tx_id)
+            .map(|(_, existing)| existing.map(|(adjustment, _)| adjustment))
+    }
+
+    pub fn is_reversed(&self, tx_id: &TransactionId) -> io::Result<bool> {
+        self.find_slot(tx_id)
+            .map(|(_, existing)| existing.is_some_and(|(_, reversed)| reversed))
+    }
+
+    /// Flags stored adjustment as reversed, returns false when there is no such adjustment.
+    pub fn mark_reversed(&mut self, tx_id: &TransactionId) -> io::Result<bool> {
+        match self.find_slot(tx_id)? {
+            (slot, Some((adjustment, _))) => self.write_slot(slot, &adjustment, true).map(|_| true),
+            (_, None) => Ok(false),
+        }
+    }
+
+    /// Removes adjustment, moving entries further along its probe sequence back into the freed
+    /// slot so they can still be found.
+    pub fn remove(&mut self, tx_id: &TransactionId) -> io::Result<()> {
+        let (mut free, existing) = self.find_slot(tx_id)?;
+        if existing.is_none() {
+            return Ok(());
+        }
+        let mut slot = free;
+        loop {
+            slot = (slot + 1) % self.slots;
+            let Some((adjustment, reversed)) = self.read_slot(slot)? else {
+                break;
+            };
+            // entry stays when its home slot lies cyclically within (free, slot]
+            let home = self.home_slot(&adjustment.details.id);
+            let stays = match free <= slot {
+                true => free < home && home <= slot,
+                false => free < home || home <= slot,
+            };
+            if !stays {
+                self.write_slot(free, &adjustment, reversed)?;
+                free = slot;
+            }
+        }
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(free * SLOT_SIZE as u64))?;
+        file.write_all(&[0u8; SLOT_SIZE])?;
+        self.len -= 1;
+        Ok(())
+    }
+
+    /// Reads stored adjustments with their reversal flags a chunk of slots at a time, in file order.
+    /// Every chunk is read from its own offset, so lookups made while iterating don't disturb it.
+    pub fn iter(&self) -> impl Iterator<Item = io::Result<(Adjustment, bool)>> + '_ {
+        (0..self.slots)
+            .step_by(ITER_CHUNK_SLOTS as usize)
+            .flat_map(move |first| match self.read_chunk(first) {
+                Ok(stored) => stored.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            })
+    }
+
+    fn home_slot(&self, tx_id: &TransactionId) -> u64 {
+        (tx_id.to_u64().wrapping_mul(0x9E37_79B9_7F4A_7C15)) % self.slots
+    }
+
+    fn find_slot(&self, tx_id: &TransactionId) -> io::Result<(u64, Option<(Adjustment, bool)>)> {
+        let mut slot = self.home_slot(tx_id);
+        loop {
+            match self.read_slot(slot)? {
+                None => return Ok((slot, None)),
+                Some(stored) if stored.0.details.id == *tx_id => {
+                    return Ok((slot, Some(stored)));
+                }
+                Some(_) => slot = (slot + 1) % self.slots,
+            }
+        }
+    }
+
+    fn read_slot(&self, slot: u64) -> io::Result<Option<(Adjustment, bool)>> {
+        let mut buf = [0u8; SLOT_SIZE];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(slot * SLOT_SIZE as u64))?;
+        file.read_exact(&mut buf)?;
+        Ok(decode(&buf))
+    }
+
+    fn read_chunk(&self, first: u64) -> io::Result<Vec<(Adjustment, bool)>> {
+        let count = ITER_CHUNK_SLOTS.min(self.slots - first) as usize;
+        let mut buf = vec![0u8; count * SLOT_SIZE];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(first * SLOT_SIZE as u64))?;
+        file.read_exact(&mut buf)?;
+        Ok(buf
+            .chunks_exact(SLOT_SIZE)
+            .filter_map(|slot| decode(slot.try_into().ok()?))
+            .collect())
+    }
+
+    fn write_slot(&self, slot: u64, adjustment: &Adjustment, reversed: bool) -> io::Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(slot * SLOT_SIZE as u64))?;
+        file.write_all(&encode(adjustment, reversed))
+    }
+
+    /// Rehashes all entries into a file twice the size, which then replaces current one.
+    fn grow(&mut self) -> io::Result<()> {
+        let grown_path = self.path.with_extension("grow");
+        let mut grown = Self::with_slots(&grown_path, self.slots * 2)?;
+        for stored in self.iter() {
+            let (adjustment, reversed) = stored?;
+            grown.put(&adjustment, reversed)?;
+        }
+        fs::rename(&grown_path, &self.path)?;
+        grown.path = self.path.clone();
+        *self = grown;
+        Ok(())
+    }
+}
+
+fn encode(adjustment: &Adjustment, reversed: bool) -> [u8; SLOT_SIZE] {
+    let mut buf = [0u8; SLOT_SIZE];
+    buf[0] = match reversed {
+        true => OCCUPIED | REVERSED,
+        false => OCCUPIED,
+    };
+    buf[ID_AT..CLIENT_AT].copy_from_slice(&adjustment.details.id.0.to_le_bytes());
+    buf[CLIENT_AT..KIND_AT].copy_from_slice(&adjustment.details.client_id.0.to_le_bytes());
+    buf[KIND_AT] = match adjustment.category {
+        AdjustmentKind::Deposit => 0,
+        AdjustmentKind::Withdrawal => 1,
+    };
+    buf[AMOUNT_AT..FEE_AT].copy_from_slice(&adjustment.amount.0.to_le_bytes());
+    buf[FEE_AT..].copy_from_slice(&adjustment.fee.0.to_le_bytes());
+    buf
+}
+
+fn decode(buf: &[u8; SLOT_SIZE]) -> Option<(Adjustment, bool)> {
+    if buf[0] & OCCUPIED == 0 {
+        return None;
+    }
+    let adjustment = Adjustment {
+        category: match buf[KIND_AT] {
+            0 => AdjustmentKind::Deposit,
+            _ => AdjustmentKind::Withdrawal,
+        },
+        details: TxDetails {
+            id: TransactionId(RawTransactionId::from_le_bytes(
+                buf[ID_AT..CLIENT_AT].try_into().ok()?,
+            )),
+            client_id: ClientId(RawClientId::from_le_bytes(
+                buf[CLIENT_AT..KIND_AT].try_into().ok()?,
+            )),
+        },
+        amount: TxAmount(i64::from_le_bytes(buf[AMOUNT_AT..FEE_AT].try_into().ok()?)),
+        fee: TxAmount(i64::from_le_bytes(buf[FEE_AT..].try_into().ok()?)),
+    };
+    Some((adjustment, buf[0] & REVERSED != 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        objects::{
+            Adjustment, AdjustmentKind, ClientId, RawTransactionId, TransactionId, TxAmount,
+            TxDetails,
+        },
+        temp_path::TempPath,
+    };
+
+    use super::ColdStore;
+
+    fn adjustment(id: RawTransactionId) -> Adjustment {
+        Adjustment {
+            category: AdjustmentKind::Deposit,
+            details: TxDetails {
+                id: TransactionId(id),
+                client_id: ClientId(7),
+            },
+            amount: TxAmount(id as i64),
+            fee: TxAmount::ZERO,
+        }
+    }
+
+    #[test]
+    fn stored_adjustments_are_found_after_growing() {
+        let path = TempPath::new("cold-store");
+        let mut store = ColdStore::create(&path).unwrap();
+
+        for id in 0..3000 {
+            store.put(&adjustment(id), false).unwrap();
+            if id == 5 {
+                assert!(store.mark_reversed(&TransactionId(id)).unwrap());
+            }
+        }
+        // overwriting keeps single entry
+        store.put(&adjustment(10), false).unwrap();
+
+        assert_eq!(store.len(), 3000);
+        assert_eq!(store.iter().count(), 3000);
+        // reversal flag moves with the entry when rehashing
+        assert!(store.is_reversed(&TransactionId(5)).unwrap());
+        assert!(!store.is_reversed(&TransactionId(4)).unwrap());
+        assert!(!store.mark_reversed(&TransactionId(5000)).unwrap());
+        let found = store.get(&TransactionId(2999)).unwrap().unwrap();
+        assert_eq!(found.amount, TxAmount(2999));
+        assert_eq!(found.details.client_id, ClientId(7));
+        assert!(store.get(&TransactionId(5000)).unwrap().is_none());
+
+        // entries probed past removed ones stay reachable
+        for id in (0..3000).step_by(3) {
+            store.remove(&TransactionId(id)).unwrap();
+        }
+        assert_eq!(store.len(), 2000);
+        assert!(store.get(&TransactionId(2997)).unwrap().is_none());
+        assert!((1..3000).filter(|id| id % 3 != 0).all(|id| {
+            store
+                .get(&TransactionId(id))
+                .unwrap()
+                .is_some_and(|found| found.amount == TxAmount(id as i64))
+        }));
+    }
+}
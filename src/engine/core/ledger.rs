@@ -0,0 +1,511 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::engine::{
+    EngineError,
+    objects::{
+        Adjustment, AdjustmentKind, Authorization, ClientId, DisputeClaim, ResolutionKind, TxAmount,
+    },
+};
+
+use super::account::Account;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum LedgerAccount {
+    Available(ClientId),
+    Held(ClientId),
+    Reserved(ClientId), // authorized, not yet captured
+    Settlement,         // external world - money enters and leaves the books through it
+    Fees(ClientId),     // income from fees charged to the client
+    Interest,           // expense of interest credited to clients
+    Corrections,        // manual balance corrections made by operators
+    Migrated,           // balances of clients moved between processors by resharding
+    Opening,            // balances of accounts carried over from a previous run
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TrialBalance {
+    pub total_debits: TxAmount,
+    pub total_credits: TxAmount,
+}
+
+/// Double-entry books mirroring balance changes made on `Account`s.
+/// Every operation moves money between two ledger accounts, so the sum of all balances is zero.
+pub struct Ledger {
+    balances: HashMap<LedgerAccount, TxAmount>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self {
+            balances: Default::default(),
+        }
+    }
+
+    /// Books opening with balances of `accounts` loaded from storage, as only balances
+    /// are kept between runs.
+    pub fn opening(accounts: &[Account]) -> Self {
+        let mut ledger = Self::new();
+        for account in accounts {
+            let balances = [
+                account.available,
+                account.held,
+                account.reserved,
+                account.fees,
+            ];
+            for (leg, balance) in Self::client_legs(account.client_id)
+                .into_iter()
+                .zip(balances)
+            {
+                ledger.post(leg, LedgerAccount::Opening, balance);
+            }
+        }
+        ledger
+    }
+
+    pub fn post_adjustment(&mut self, adjustment: &Adjustment) {
+        let client_id = adjustment.details.client_id;
+        let (debit, credit) = match adjustment.category {
+            AdjustmentKind::Deposit => (
+                LedgerAccount::Available(client_id),
+                LedgerAccount::Settlement,
+            ),
+            AdjustmentKind::Withdrawal => (
+                LedgerAccount::Settlement,
+                LedgerAccount::Available(client_id),
+            ),
+        };
+        self.post(debit, credit, adjustment.amount);
+        self.post_fee(client_id, adjustment.fee);
+    }
+
+    /// Reversed adjustment, posted with its fee in opposite direction.
+    pub fn post_reversal(&mut self, adjustment: &Adjustment) {
+        let client_id = adjustment.details.client_id;
+        let (debit, credit) = match adjustment.category {
+            AdjustmentKind::Deposit => (
+                LedgerAccount::Settlement,
+                LedgerAccount::Available(client_id),
+            ),
+            AdjustmentKind::Withdrawal => (
+                LedgerAccount::Available(client_id),
+                LedgerAccount::Settlement,
+            ),
+        };
+        self.post(debit, credit, adjustment.amount);
+        if adjustment.fee != TxAmount::ZERO {
+            self.post(
+                LedgerAccount::Available(client_id),
+                LedgerAccount::Fees(client_id),
+                adjustment.fee,
+            );
+        }
+    }
+
+    pub fn post_fee(&mut self, client_id: ClientId, fee: TxAmount) {
+        if fee != TxAmount::ZERO {
+            self.post(
+                LedgerAccount::Fees(client_id),
+                LedgerAccount::Available(client_id),
+                fee,
+            );
+        }
+    }
+
+    pub fn post_interest(&mut self, client_id: ClientId, interest: TxAmount) {
+        if interest != TxAmount::ZERO {
+            self.post(
+                LedgerAccount::Available(client_id),
+                LedgerAccount::Interest,
+                interest,
+            );
+        }
+    }
+
+    /// Correction of available funds, negative one takes funds away.
+    pub fn post_correction(&mut self, client_id: ClientId, amount: TxAmount) {
+        match amount < TxAmount::ZERO {
+            true => self.post(
+                LedgerAccount::Corrections,
+                LedgerAccount::Available(client_id),
+                -amount,
+            ),
+            false => self.post(
+                LedgerAccount::Available(client_id),
+                LedgerAccount::Corrections,
+                amount,
+            ),
+        }
+    }
+
+    pub fn post_authorized(&mut self, authorization: &Authorization) {
+        let client_id = authorization.client_id;
+        self.post(
+            LedgerAccount::Reserved(client_id),
+            LedgerAccount::Available(client_id),
+            authorization.amount,
+        );
+    }
+
+    pub fn post_captured(&mut self, authorization: &Authorization) {
+        self.post(
+            LedgerAccount::Settlement,
+            LedgerAccount::Reserved(authorization.client_id),
+            authorization.amount,
+        );
+    }
+
+    /// Voided or expired authorization.
+    pub fn post_released(&mut self, authorization: &Authorization) {
+        let client_id = authorization.client_id;
+        self.post(
+            LedgerAccount::Available(client_id),
+            LedgerAccount::Reserved(client_id),
+            authorization.amount,
+        );
+    }
+
+    pub fn post_dispute_opened(&mut self, claim: &DisputeClaim) {
+        match claim.kind {
+            AdjustmentKind::Deposit => self.post(
+                LedgerAccount::Held(claim.client_id),
+                LedgerAccount::Available(claim.client_id),
+                claim.held(),
+            ),
+            AdjustmentKind::Withdrawal => {
+                // no funds are moved when withdrawal gets disputed
+            }
+        }
+    }
+
+    pub fn post_dispute_closed(&mut self, claim: &DisputeClaim, resolution: ResolutionKind) {
+        let client_id = claim.client_id;
+        let legs = match (claim.kind, resolution) {
+            (AdjustmentKind::Deposit, ResolutionKind::Resolve) => Some((
+                LedgerAccount::Available(client_id),
+                LedgerAccount::Held(client_id),
+                claim.held(),
+            )),
+            (AdjustmentKind::Deposit, ResolutionKind::Chargeback) => Some((
+                LedgerAccount::Settlement,
+                LedgerAccount::Held(client_id),
+                claim.held(),
+            )),
+            (AdjustmentKind::Withdrawal, ResolutionKind::Chargeback) => Some((
+                LedgerAccount::Available(client_id),
+                LedgerAccount::Settlement,
+                claim.amount,
+            )),
+            (AdjustmentKind::Withdrawal, ResolutionKind::Resolve) => None,
+        };
+        if let Some((debit, credit, amount)) = legs {
+            self.post(debit, credit, amount);
+        }
+        // chargeback reverts fee of the disputed adjustment, deposit one was never credited
+        if let ResolutionKind::Chargeback = resolution
+            && claim.fee != TxAmount::ZERO
+        {
+            let debit = match claim.kind {
+                AdjustmentKind::Deposit => LedgerAccount::Settlement,
+                AdjustmentKind::Withdrawal => LedgerAccount::Available(client_id),
+            };
+            self.post(debit, LedgerAccount::Fees(client_id), claim.fee);
+        }
+    }
+
+    /// Moves balances of client leaving for another processor to `Migrated`,
+    /// returning its available, held, reserved and fee legs.
+    pub fn post_migrated_out(&mut self, client_id: ClientId) -> [TxAmount; 4] {
+        let legs = Self::client_legs(client_id);
+        let balances = legs.map(|leg| self.balance(leg));
+        for (leg, balance) in legs.into_iter().zip(balances) {
+            self.post(LedgerAccount::Migrated, leg, balance);
+        }
+        balances
+    }
+
+    /// Takes over balances of client coming from another processor.
+    pub fn post_migrated_in(&mut self, client_id: ClientId, balances: [TxAmount; 4]) {
+        for (leg, balance) in Self::client_legs(client_id).into_iter().zip(balances) {
+            self.post(leg, LedgerAccount::Migrated, balance);
+        }
+    }
+
+    fn client_legs(client_id: ClientId) -> [LedgerAccount; 4] {
+        [
+            LedgerAccount::Available(client_id),
+            LedgerAccount::Held(client_id),
+            LedgerAccount::Reserved(client_id),
+            LedgerAccount::Fees(client_id),
+        ]
+    }
+
+    pub fn balance(&self, account: LedgerAccount) -> TxAmount {
+        self.balances.get(&account).copied().unwrap_or_default()
+    }
+
+    pub fn trial_balance(&self) -> TrialBalance {
+        self.balances.values().fold(
+            TrialBalance {
+                total_debits: TxAmount::ZERO,
+                total_credits: TxAmount::ZERO,
+            },
+            |mut acc, balance| {
+                if *balance >= TxAmount::ZERO {
+                    acc.total_debits += *balance;
+                } else {
+                    acc.total_credits -= *balance;
+                }
+                acc
+            },
+        )
+    }
+
+    /// Checks that client's ledger legs agree with balances kept on the account.
+    pub fn verify_account(&self, account: &Account) -> Result<(), EngineError> {
+        let [available, held, reserved, fees] =
+            Self::client_legs(account.client_id).map(|leg| self.balance(leg));
+        if available != account.available
+            || held != account.held
+            || reserved != account.reserved
+            || fees != account.fees
+        {
+            return Err(EngineError::Ledger_AccountOutOfBalance);
+        }
+        Ok(())
+    }
+
+    /// Checks the books against account state: every account has to agree with its legs,
+    /// and clients without an account can't have anything left in the books.
+    pub fn verify(&self, accounts: &[Account]) -> Vec<(ClientId, EngineError)> {
+        let mut failures: Vec<_> = accounts
+            .iter()
+            .filter_map(|account| {
+                let e = self.verify_account(account).err()?;
+                Some((account.client_id, e))
+            })
+            .collect();
+        let known: HashSet<_> = accounts.iter().map(|account| account.client_id).collect();
+        let mut orphaned: Vec<_> = self
+            .balances
+            .iter()
+            .filter(|(_, balance)| **balance != TxAmount::ZERO)
+            .filter_map(|(leg, _)| leg.client_id())
+            .filter(|client_id| !known.contains(client_id))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        orphaned.sort_by_key(|client_id| **client_id);
+        failures.extend(
+            orphaned
+                .into_iter()
+                .map(|client_id| (client_id, EngineError::Ledger_BalanceWithoutAccount)),
+        );
+        failures
+    }
+
+    fn post(&mut self, debit: LedgerAccount, credit: LedgerAccount, amount: TxAmount) {
+        *self.balances.entry(debit).or_default() += amount;
+        *self.balances.entry(credit).or_default() -= amount;
+    }
+}
+
+impl TrialBalance {
+    pub fn is_balanced(&self) -> bool {
+        self.total_debits == self.total_credits
+    }
+}
+
+impl LedgerAccount {
+    /// Client the account belongs to, none for the books' own accounts.
+    fn client_id(&self) -> Option<ClientId> {
+        match self {
+            Self::Available(client_id)
+            | Self::Held(client_id)
+            | Self::Reserved(client_id)
+            | Self::Fees(client_id) => Some(*client_id),
+            Self::Settlement
+            | Self::Interest
+            | Self::Corrections
+            | Self::Migrated
+            | Self::Opening => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        core::{account::Account, reconciliation::reconcile, tx_resolver::TxResolver},
+        fees::FeeSchedule,
+        objects::{ClientId, RawTransactionId, TransactionDTO, TransactionId, TxAmount, TxKind},
+    };
+
+    use super::{Ledger, LedgerAccount};
+
+    fn tx(id: RawTransactionId, kind: TxKind, amount: Option<f64>) -> TransactionDTO {
+        TransactionDTO::new(id, 1, kind, amount)
+    }
+
+    #[test]
+    fn every_operation_keeps_books_balanced() {
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new().with_ledger(Ledger::new());
+
+        assert!(
+            resolver
+                .apply_adjustment(tx(1, TxKind::Deposit, Some(100.0)), &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .apply_adjustment(tx(2, TxKind::Withdrawal, Some(30.0)), &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .apply_adjustment(tx(3, TxKind::Deposit, Some(50.0)), &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .open_dispute(&TransactionId(1), &mut account)
+                .is_ok()
+        );
+
+        let ledger = resolver.ledger().unwrap();
+        assert!(ledger.trial_balance().is_balanced());
+        assert!(ledger.verify_account(&account).is_ok());
+        assert_eq!(
+            ledger.balance(LedgerAccount::Held(ClientId(1))),
+            TxAmount::from_f64(100.0)
+        );
+        assert_eq!(
+            ledger.balance(LedgerAccount::Settlement),
+            TxAmount::from_f64(-120.0)
+        );
+
+        assert!(
+            resolver
+                .close_dispute(tx(1, TxKind::Chargeback, None), &mut account)
+                .is_ok()
+        );
+
+        let ledger = resolver.ledger().unwrap();
+        assert!(ledger.trial_balance().is_balanced());
+        assert!(ledger.verify_account(&account).is_ok());
+        assert_eq!(
+            ledger.balance(LedgerAccount::Settlement),
+            TxAmount::from_f64(-20.0)
+        );
+    }
+
+    #[test]
+    fn rejected_operations_are_not_posted() {
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new().with_ledger(Ledger::new());
+
+        assert!(
+            resolver
+                .apply_adjustment(tx(1, TxKind::Withdrawal, Some(10.0)), &mut account)
+                .is_err()
+        );
+
+        let ledger = resolver.ledger().unwrap();
+        assert_eq!(ledger.trial_balance().total_debits, TxAmount::ZERO);
+        assert!(ledger.verify_account(&account).is_ok());
+    }
+
+    #[test]
+    fn verification_detects_balance_changed_outside_of_ledger() {
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new().with_ledger(Ledger::new());
+
+        assert!(
+            resolver
+                .apply_adjustment(tx(1, TxKind::Deposit, Some(10.0)), &mut account)
+                .is_ok()
+        );
+        account.available += TxAmount::from_f64(5.0);
+
+        assert!(resolver.ledger().unwrap().verify_account(&account).is_err());
+    }
+
+    #[test]
+    fn chargeback_reverts_adjustment_fee_and_charges_its_own() {
+        let fees = FeeSchedule::parse(
+            "fee, default, deposit, 1, 0
+            fee, default, chargeback, 5, 10",
+        )
+        .unwrap();
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new().with_ledger(Ledger::new()).with_fees(fees);
+
+        for tx in [
+            tx(1, TxKind::Deposit, Some(100.0)),
+            tx(2, TxKind::Deposit, Some(50.0)),
+        ] {
+            assert!(resolver.apply_adjustment(tx, &mut account).is_ok());
+        }
+        assert_eq!(account.available, TxAmount::from_f64(148.0));
+        assert_eq!(account.fees, TxAmount::from_f64(2.0));
+
+        assert!(
+            resolver
+                .open_dispute(&TransactionId(1), &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .close_dispute(tx(1, TxKind::Chargeback, None), &mut account)
+                .is_ok()
+        );
+
+        // deposit fee refunded, chargeback fee of 5 + 10% charged
+        assert_eq!(account.available + account.held, TxAmount::from_f64(34.0));
+        assert_eq!(account.fees, TxAmount::from_f64(16.0));
+        let ledger = resolver.ledger().unwrap();
+        assert!(ledger.trial_balance().is_balanced());
+        assert!(ledger.verify_account(&account).is_ok());
+        assert_eq!(
+            ledger.balance(LedgerAccount::Fees(ClientId(1))),
+            TxAmount::from_f64(16.0)
+        );
+        assert!(reconcile(&resolver, [&account]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn fractional_amounts_keep_books_balanced_exactly() {
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new().with_ledger(Ledger::new());
+
+        for id in 1..=2_000 {
+            let kind = match id % 3 {
+                0 => TxKind::Withdrawal,
+                _ => TxKind::Deposit,
+            };
+            let amount = 76_000.0 + id as f64 / 10_000.0;
+            let _ = resolver.apply_adjustment(tx(id, kind, Some(amount)), &mut account);
+        }
+
+        let ledger = resolver.ledger().unwrap();
+        let trial_balance = ledger.trial_balance();
+        assert_eq!(trial_balance.total_debits, trial_balance.total_credits);
+        assert!(ledger.verify(std::slice::from_ref(&account)).is_empty());
+    }
+
+    #[test]
+    fn verification_detects_balance_of_client_without_account() {
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new().with_ledger(Ledger::new());
+
+        assert!(
+            resolver
+                .apply_adjustment(tx(1, TxKind::Deposit, Some(10.0)), &mut account)
+                .is_ok()
+        );
+
+        let failures = resolver.ledger().unwrap().verify(&[]);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, ClientId(1));
+    }
+}
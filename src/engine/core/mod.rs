@@ -1,2 +1,6 @@
 pub(crate) mod account;
+pub(crate) mod cold_store;
+pub(crate) mod ledger;
+pub(crate) mod reconciliation;
+pub(crate) mod tx_log;
 pub(crate) mod tx_resolver;
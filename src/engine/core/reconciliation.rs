@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use crate::engine::{
+    EngineError,
+    objects::{AdjustmentKind, ClientId, TxAmount},
+};
+
+use super::{account::Account, tx_resolver::TxResolver};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Balances {
+    pub total: TxAmount,
+    pub held: TxAmount,
+    pub reserved: TxAmount,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Discrepancy {
+    pub client_id: ClientId,
+    pub expected: Balances,
+    pub actual: Balances,
+}
+
+/// Recomputes balances of every client from accepted adjustments, chargebacks, reversals,
+/// periodic jobs, balance corrections, chargeback fees, open disputes and pending authorizations.
+pub fn expected_balances(
+    resolver: &TxResolver,
+) -> Result<HashMap<ClientId, Balances>, EngineError> {
+    let mut expected: HashMap<ClientId, Balances> = HashMap::new();
+
+    resolver.for_each_adjustment(|adjustment| {
+        let balances = expected.entry(adjustment.details.client_id).or_default();
+        let amount = adjustment.amount;
+        let fee = adjustment.fee;
+        if resolver.is_reversed(&adjustment.details.id)? {
+            // reversal undoes the adjustment with its fee
+            return Ok(());
+        }
+        let charged_back = resolver.is_charged_back(&adjustment.details.id)?;
+        match (adjustment.category, charged_back) {
+            (AdjustmentKind::Deposit, false) => balances.total += amount - fee,
+            (AdjustmentKind::Withdrawal, false) => balances.total -= amount + fee,
+            // chargeback reverts the adjustment with its fee, its own fee is among accruals
+            (_, true) => {}
+        }
+        Ok(())
+    })?;
+
+    for (client_id, amount) in resolver.accruals()? {
+        expected.entry(client_id).or_default().total += amount;
+    }
+
+    for claim in resolver.active_disputes()? {
+        if let AdjustmentKind::Deposit = claim.kind {
+            expected.entry(claim.client_id).or_default().held += claim.held();
+        }
+    }
+
+    for authorization in resolver.active_authorizations()? {
+        expected
+            .entry(authorization.client_id)
+            .or_default()
+            .reserved += authorization.amount;
+    }
+
+    Ok(expected)
+}
+
+/// Reports every account whose balances diverge from ones recomputed from resolver's history.
+pub fn reconcile<'a>(
+    resolver: &TxResolver,
+    accounts: impl IntoIterator<Item = &'a Account>,
+) -> Result<Vec<Discrepancy>, EngineError> {
+    let expected = expected_balances(resolver)?;
+    Ok(accounts
+        .into_iter()
+        .filter_map(|account| {
+            let expected = expected
+                .get(&account.client_id)
+                .copied()
+                .unwrap_or_default();
+            let actual = Balances {
+                total: account.available + account.held + account.reserved,
+                held: account.held,
+                reserved: account.reserved,
+            };
+            (expected != actual).then_some(Discrepancy {
+                client_id: account.client_id,
+                expected,
+                actual,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        core::{account::Account, tx_resolver::TxResolver},
+        objects::{ClientId, RawTransactionId, TransactionDTO, TransactionId, TxAmount, TxKind},
+    };
+
+    use super::reconcile;
+
+    fn tx(id: RawTransactionId, kind: TxKind, amount: Option<f64>) -> TransactionDTO {
+        TransactionDTO::new(id, 1, kind, amount)
+    }
+
+    #[test]
+    fn balances_built_through_resolver_reconcile() {
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new();
+
+        let _ = resolver.apply_adjustment(tx(1, TxKind::Deposit, Some(100.0)), &mut account);
+        let _ = resolver.apply_adjustment(tx(2, TxKind::Deposit, Some(20.0)), &mut account);
+        let _ = resolver.apply_adjustment(tx(3, TxKind::Withdrawal, Some(30.0)), &mut account);
+        let _ = resolver.open_dispute(&TransactionId(1), &mut account);
+        let _ = resolver.open_dispute(&TransactionId(2), &mut account);
+        let _ = resolver.close_dispute(tx(2, TxKind::Chargeback, None), &mut account);
+
+        assert_eq!(account.held, TxAmount::from_f64(100.0));
+        assert!(reconcile(&resolver, [&account]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn diverging_account_is_reported() {
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new();
+
+        let _ = resolver.apply_adjustment(tx(1, TxKind::Deposit, Some(100.0)), &mut account);
+        account.held += TxAmount::from_f64(10.0);
+
+        let discrepancies = reconcile(&resolver, [&account]).unwrap();
+
+        assert_eq!(discrepancies.len(), 1);
+        assert_eq!(discrepancies[0].expected.total, TxAmount::from_f64(100.0));
+        assert_eq!(discrepancies[0].actual.total, TxAmount::from_f64(110.0));
+        assert_eq!(discrepancies[0].actual.held, TxAmount::from_f64(10.0));
+    }
+
+    #[test]
+    fn small_divergence_of_large_balance_is_reported() {
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new();
+
+        let _ = resolver.apply_adjustment(tx(1, TxKind::Deposit, Some(1_000_000.0)), &mut account);
+        account.available += TxAmount::from_f64(10.0);
+
+        assert_eq!(reconcile(&resolver, [&account]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn fractional_amounts_of_large_balances_reconcile_exactly() {
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new();
+
+        for id in 1..=20_000 {
+            let kind = match id % 4 {
+                0 => TxKind::Withdrawal,
+                _ => TxKind::Deposit,
+            };
+            let amount = (id % 997) as f64 + (id % 9_973) as f64 / 10_000.0;
+            let _ = resolver.apply_adjustment(tx(id, kind, Some(amount)), &mut account);
+        }
+        let _ = resolver.open_dispute(&TransactionId(5), &mut account);
+
+        assert!(account.available > TxAmount::from_f64(76_000.0));
+        assert!(reconcile(&resolver, [&account]).unwrap().is_empty());
+
+        account.available += TxAmount(1);
+
+        assert_eq!(reconcile(&resolver, [&account]).unwrap().len(), 1);
+    }
+}
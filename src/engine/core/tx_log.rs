@@ -0,0 +1,232 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    path::Path,
+};
+
+use crate::engine::{
+    EngineError,
+    objects::{Adjustment, TransactionId},
+    storage::TransactionLogStore,
+};
+
+use super::cold_store::ColdStore;
+
+/// Applied adjustments, kept for disputes.
+/// Recent adjustments stay in memory (hot tier). With cold tier configured, hot tier is
+/// bounded and the oldest adjustments get moved to a file on disk, where they can still be found.
+pub struct TransactionLog {
+    hot: HashMap<TransactionId, Adjustment>,
+    hot_order: VecDeque<TransactionId>,
+    cold: Option<ColdTier>,
+    // reversed adjustments of hot tier, cold tier keeps the flag next to the adjustment
+    reversed: HashSet<TransactionId>,
+}
+
+struct ColdTier {
+    store: ColdStore,
+    hot_capacity: usize,
+}
+
+impl TransactionLog {
+    pub fn in_memory() -> Self {
+        Self {
+            hot: Default::default(),
+            hot_order: Default::default(),
+            cold: None,
+            reversed: Default::default(),
+        }
+    }
+
+    /// Log keeping at most `hot_capacity` adjustments in memory, rest goes to file at `path`.
+    pub fn tiered(path: &Path, hot_capacity: usize) -> io::Result<Self> {
+        Ok(Self {
+            cold: Some(ColdTier {
+                store: ColdStore::create(path)?,
+                hot_capacity,
+            }),
+            ..Self::in_memory()
+        })
+    }
+
+    fn evict(&mut self) {
+        let Some(cold) = self.cold.as_mut() else {
+            return;
+        };
+        while self.hot.len() > cold.hot_capacity {
+            let Some(oldest) = self.hot_order.pop_front() else {
+                return;
+            };
+            let Some(adjustment) = self.hot.remove(&oldest) else {
+                continue;
+            };
+            let reversed = self.reversed.remove(&oldest);
+            if let Err(e) = cold.store.put(&adjustment, reversed) {
+                // keep it in memory rather than lose it, eviction is retried on next insert
+                eprintln!("transaction log: moving {} to disk failed: {e}", *oldest);
+                if reversed {
+                    self.reversed.insert(oldest);
+                }
+                self.hot.insert(oldest, adjustment);
+                self.hot_order.push_front(oldest);
+                return;
+            }
+        }
+    }
+}
+
+impl TransactionLogStore for TransactionLog {
+    fn insert(&mut self, adjustment: Adjustment) -> Result<(), EngineError> {
+        let tx_id = adjustment.details.id;
+        if self.hot.insert(tx_id, adjustment).is_none() && self.cold.is_some() {
+            self.hot_order.push_back(tx_id);
+        }
+        self.evict();
+        Ok(())
+    }
+
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<Adjustment>, EngineError> {
+        if let Some(adjustment) = self.hot.get(tx_id) {
+            return Ok(Some(adjustment.clone()));
+        }
+        match &self.cold {
+            Some(cold) => cold
+                .store
+                .get(tx_id)
+                .map_err(|_| EngineError::Storage_ReadFailed),
+            None => Ok(None),
+        }
+    }
+
+    /// Visits adjustments of both tiers, reading cold tier from disk one at a time.
+    fn for_each(
+        &self,
+        visit: &mut dyn FnMut(Adjustment) -> Result<(), EngineError>,
+    ) -> Result<(), EngineError> {
+        if let Some(cold) = &self.cold {
+            for entry in cold.store.iter() {
+                let (adjustment, _) = entry.map_err(|_| EngineError::Storage_ReadFailed)?;
+                // hot tier holds newer version of re-inserted adjustments
+                if !self.hot.contains_key(&adjustment.details.id) {
+                    visit(adjustment)?;
+                }
+            }
+        }
+        self.hot
+            .values()
+            .try_for_each(|adjustment| visit(adjustment.clone()))
+    }
+
+    fn mark_reversed(&mut self, tx_id: &TransactionId) -> Result<(), EngineError> {
+        let in_hot = self.hot.contains_key(tx_id);
+        if let Some(cold) = self.cold.as_mut().filter(|_| !in_hot) {
+            let stored = cold
+                .store
+                .mark_reversed(tx_id)
+                .map_err(|_| EngineError::Storage_WriteFailed)?;
+            if stored {
+                return Ok(());
+            }
+        }
+        self.reversed.insert(*tx_id);
+        Ok(())
+    }
+
+    fn is_reversed(&self, tx_id: &TransactionId) -> Result<bool, EngineError> {
+        if self.reversed.contains(tx_id) || self.hot.contains_key(tx_id) {
+            return Ok(self.reversed.contains(tx_id));
+        }
+        match &self.cold {
+            Some(cold) => cold
+                .store
+                .is_reversed(tx_id)
+                .map_err(|_| EngineError::Storage_ReadFailed),
+            None => Ok(false),
+        }
+    }
+
+    fn remove(&mut self, tx_id: &TransactionId) -> Result<(), EngineError> {
+        self.reversed.remove(tx_id);
+        // eviction order skips ids no longer in hot tier, older version may be in cold tier
+        self.hot.remove(tx_id);
+        match self.cold.as_mut() {
+            Some(cold) => cold
+                .store
+                .remove(tx_id)
+                .map_err(|_| EngineError::Storage_WriteFailed),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        core::{account::Account, tx_resolver::TxResolver},
+        objects::{ClientId, TransactionDTO, TransactionId, TxAmount, TxKind},
+        storage::TransactionLogStore,
+        temp_path::TempPath,
+    };
+
+    use super::TransactionLog;
+
+    #[test]
+    fn old_transactions_can_be_disputed_after_moving_to_disk() {
+        let path = TempPath::new("tx-log");
+        let log = TransactionLog::tiered(&path, 10).unwrap();
+        let mut resolver = TxResolver::new().with_transaction_log(Box::new(log));
+        let mut account = Account::new(ClientId(1));
+
+        for id in 0..100 {
+            let tx = TransactionDTO::new(id, 1, TxKind::Deposit, Some(1.0));
+            assert!(resolver.apply_adjustment(tx, &mut account).is_ok());
+        }
+
+        assert!(
+            resolver
+                .open_dispute(&TransactionId(0), &mut account)
+                .is_ok()
+        );
+        assert_eq!(account.held, TxAmount::from_f64(1.0));
+        assert!(
+            resolver
+                .open_dispute(&TransactionId(100), &mut account)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn entries_come_from_both_tiers() {
+        let path = TempPath::new("tx-log-entries");
+        let mut log = TransactionLog::tiered(&path, 3).unwrap();
+        let mut account = Account::new(ClientId(1));
+
+        for id in 0..8 {
+            let tx = TransactionDTO::new(id, 1, TxKind::Deposit, Some(1.0));
+            log.insert(account.apply_adjustment(tx, TxAmount::ZERO).unwrap())
+                .unwrap();
+        }
+
+        log.mark_reversed(&TransactionId(0)).unwrap();
+        log.mark_reversed(&TransactionId(7)).unwrap();
+
+        // lookups while visiting, as reconciliation does, must not disturb the iteration
+        let (mut entries, mut reversed) = (0, 0);
+        log.for_each(&mut |adjustment| {
+            entries += 1;
+            if log.is_reversed(&adjustment.details.id)? {
+                reversed += 1;
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(log.hot.len(), 3);
+        assert_eq!(entries, 8);
+        assert_eq!(reversed, 2);
+        // reversal of evicted adjustment is kept on disk only
+        assert_eq!(log.reversed.len(), 1);
+        assert!(log.is_reversed(&TransactionId(0)).unwrap());
+        assert!(log.is_reversed(&TransactionId(7)).unwrap());
+        assert!(!log.is_reversed(&TransactionId(1)).unwrap());
+    }
+}
@@ -1,36 +1,393 @@
 use std::collections::HashMap;
 
-
 use crate::engine::{
     EngineError,
-    objects::{Adjustment, DisputeClaim, TransactionDTO, TransactionId},
+    clock::{Clock, Timestamp},
+    fees::FeeSchedule,
+    objects::{
+        Adjustment, AdjustmentKind, Authorization, ClientId, DisputeClaim, ResolutionKind,
+        TransactionDTO, TransactionId, TxAmount, TxKind,
+    },
+    resharding::{ClientState, MigratedAdjustment},
+    risk::{RiskLimits, RiskMonitor},
+    storage::{
+        AuthorizationStore, DisputeStore, JobStore, TransactionLogStore,
+        memory::{InMemoryAuthorizationStore, InMemoryDisputeStore, InMemoryJobStore},
+    },
 };
 
-use super::account::Account;
+use super::{account::Account, ledger::Ledger, tx_log::TransactionLog};
 
 pub struct TxResolver {
-    transaction_log: HashMap<TransactionId, Adjustment>,
-    active_disputes: HashMap<TransactionId, DisputeClaim>,
+    transaction_log: Box<dyn TransactionLogStore>,
+    disputes: Box<dyn DisputeStore>,
+    authorizations: Box<dyn AuthorizationStore>,
+    jobs: Box<dyn JobStore>,
+    ledger: Option<Ledger>,
+    fees: FeeSchedule,
+    risk: RiskMonitor,
+    clock: Clock,
 }
 
 impl TxResolver {
     pub fn new() -> Self {
         Self {
-            transaction_log: Default::default(),
-            active_disputes: Default::default(),
+            transaction_log: Box::new(TransactionLog::in_memory()),
+            disputes: Box::new(InMemoryDisputeStore::default()),
+            authorizations: Box::new(InMemoryAuthorizationStore::default()),
+            jobs: Box::new(InMemoryJobStore::default()),
+            ledger: None,
+            fees: FeeSchedule::default(),
+            risk: RiskMonitor::new(RiskLimits::default()),
+            clock: Clock::default(),
+        }
+    }
+
+    /// Posts every applied operation to double-entry `ledger`.
+    pub fn with_ledger(self, ledger: Ledger) -> Self {
+        Self {
+            ledger: Some(ledger),
+            ..self
+        }
+    }
+
+    pub fn with_fees(self, fees: FeeSchedule) -> Self {
+        Self { fees, ..self }
+    }
+
+    /// Rejects withdrawals violating any of `limits`.
+    pub fn with_risk_limits(self, limits: RiskLimits) -> Self {
+        Self {
+            risk: RiskMonitor::new(limits),
+            ..self
+        }
+    }
+
+    /// Time source for risk limit windows and authorization timestamps.
+    pub fn with_clock(self, clock: Clock) -> Self {
+        Self { clock, ..self }
+    }
+
+    pub fn with_transaction_log(self, transaction_log: Box<dyn TransactionLogStore>) -> Self {
+        Self {
+            transaction_log,
+            ..self
+        }
+    }
+
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    pub fn with_dispute_store(self, disputes: Box<dyn DisputeStore>) -> Self {
+        Self { disputes, ..self }
+    }
+
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    pub fn with_authorization_store(self, authorizations: Box<dyn AuthorizationStore>) -> Self {
+        Self {
+            authorizations,
+            ..self
+        }
+    }
+
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    pub fn with_job_store(self, jobs: Box<dyn JobStore>) -> Self {
+        Self { jobs, ..self }
+    }
+
+    pub fn ledger(&self) -> Option<&Ledger> {
+        self.ledger.as_ref()
+    }
+
+    /// Passes every logged adjustment to `visit`, one at a time.
+    pub fn for_each_adjustment(
+        &self,
+        mut visit: impl FnMut(Adjustment) -> Result<(), EngineError>,
+    ) -> Result<(), EngineError> {
+        self.transaction_log.for_each(&mut visit)
+    }
+
+    pub fn active_disputes(&self) -> Result<Vec<DisputeClaim>, EngineError> {
+        self.disputes.active()
+    }
+
+    pub fn active_authorizations(&self) -> Result<Vec<Authorization>, EngineError> {
+        Ok(self
+            .authorizations
+            .active()?
+            .into_iter()
+            .map(|(_, authorization)| authorization)
+            .collect())
+    }
+
+    pub fn is_charged_back(&self, tx_id: &TransactionId) -> Result<bool, EngineError> {
+        self.disputes.is_charged_back(tx_id)
+    }
+
+    pub fn is_reversed(&self, tx_id: &TransactionId) -> Result<bool, EngineError> {
+        self.transaction_log.is_reversed(tx_id)
+    }
+
+    /// Net balance change made by periodic jobs, corrections and chargeback fees on every client.
+    pub fn accruals(&self) -> Result<Vec<(ClientId, TxAmount)>, EngineError> {
+        self.jobs.accruals()
+    }
+
+    pub fn job_last_run(&self, job: &str) -> Result<Option<Timestamp>, EngineError> {
+        self.jobs.last_run(job)
+    }
+
+    pub fn set_job_last_run(&mut self, job: &str, at: Timestamp) -> Result<(), EngineError> {
+        self.jobs.set_last_run(job, at)
+    }
+
+    /// Credits interest at `rate` percent of available funds.
+    pub fn accrue_interest(&mut self, account: &mut Account, rate: f64) -> Result<(), EngineError> {
+        let mut updated = account.clone();
+        let interest = updated.accrue_interest(rate);
+        if interest == TxAmount::ZERO {
+            return Ok(());
+        }
+        self.jobs.record_accrual(account.client_id, interest)?;
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.post_interest(account.client_id, interest);
         }
+        *account = updated;
+        Ok(())
     }
 
+    pub fn charge_maintenance_fee(
+        &mut self,
+        account: &mut Account,
+        fee: TxAmount,
+    ) -> Result<(), EngineError> {
+        let mut updated = account.clone();
+        let fee = updated.charge_maintenance_fee(fee);
+        if fee == TxAmount::ZERO {
+            return Ok(());
+        }
+        self.jobs.record_accrual(account.client_id, -fee)?;
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.post_fee(account.client_id, fee);
+        }
+        *account = updated;
+        Ok(())
+    }
+
+    /// Corrects available funds, on behalf of an operator.
+    pub fn correct_balance(
+        &mut self,
+        account: &mut Account,
+        amount: TxAmount,
+    ) -> Result<(), EngineError> {
+        let mut updated = account.clone();
+        updated.correct(amount)?;
+        self.jobs.record_accrual(account.client_id, amount)?;
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.post_correction(account.client_id, amount);
+        }
+        *account = updated;
+        Ok(())
+    }
+
+    /// Resubmitted adjustment, identical to already applied one, is acknowledged
+    /// without applying it again. Different adjustment reusing its id is rejected.
     pub fn apply_adjustment(
         &mut self,
         tx: TransactionDTO,
         account: &mut Account,
     ) -> Result<(), EngineError> {
-        account.apply_adjustment(tx).map(|applied_adjustment| {
-            let adjustment_id = applied_adjustment.details.id;
-            self.transaction_log
-                .insert(adjustment_id, applied_adjustment);
-        })
+        if self.is_resubmitted(&tx)? {
+            return Ok(());
+        }
+        let now = self.clock.now();
+        self.risk.check(&tx, now)?;
+        let fee = self.fees.adjustment_fee(&tx);
+        let mut updated = account.clone();
+        let applied_adjustment = updated.apply_adjustment(tx, fee)?;
+        self.transaction_log.insert(applied_adjustment.clone())?;
+        self.risk.record(&applied_adjustment, now);
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.post_adjustment(&applied_adjustment);
+        }
+        *account = updated;
+        Ok(())
+    }
+
+    /// Checks adjustment as [`Self::apply_adjustment`] would, applying it to a staged copy
+    /// of `account` and `risk` only. Nothing gets recorded.
+    pub fn stage_adjustment(
+        &self,
+        tx: &TransactionDTO,
+        account: &mut Account,
+        risk: &mut RiskMonitor,
+    ) -> Result<(), EngineError> {
+        if self.is_resubmitted(tx)? {
+            return Ok(());
+        }
+        let now = self.clock.now();
+        risk.check(tx, now)?;
+        let adjustment = account.apply_adjustment(tx.clone(), self.fees.adjustment_fee(tx))?;
+        risk.record(&adjustment, now);
+        Ok(())
+    }
+
+    /// Risk monitor with recent activity of `clients`, for staging their transactions.
+    pub fn staged_risk<'a>(&self, clients: impl IntoIterator<Item = &'a ClientId>) -> RiskMonitor {
+        self.risk.staged(clients)
+    }
+
+    // resubmitted adjustment, identical to the applied one, is acknowledged without applying it
+    // again, other transactions reusing its id are rejected
+    fn is_resubmitted(&self, tx: &TransactionDTO) -> Result<bool, EngineError> {
+        if let Some(applied) = self.transaction_log.get(&tx.id)? {
+            let resubmitted = applied.details.client_id == tx.client_id
+                && AdjustmentKind::try_from(tx.kind).ok() == Some(applied.category)
+                && tx.amount == Some(applied.amount);
+            return match resubmitted {
+                true => Ok(true),
+                false => Err(EngineError::Resolver_DuplicateTransactionId),
+            };
+        }
+        if self.authorizations.get(&tx.id)?.is_some() {
+            return Err(EngineError::Resolver_DuplicateTransactionId);
+        }
+        Ok(false)
+    }
+
+    /// Reserves funds under transaction id of the authorization. Resubmitted
+    /// authorization, identical to the pending one, is acknowledged.
+    pub fn authorize(
+        &mut self,
+        tx: TransactionDTO,
+        account: &mut Account,
+    ) -> Result<(), EngineError> {
+        if let Some(pending) = self.authorizations.get(&tx.id)? {
+            return match pending.client_id == tx.client_id && tx.amount == Some(pending.amount) {
+                true => Ok(()),
+                false => Err(EngineError::Resolver_DuplicateTransactionId),
+            };
+        }
+        if self.transaction_log.get(&tx.id)?.is_some() {
+            return Err(EngineError::Resolver_DuplicateTransactionId);
+        }
+        let mut updated = account.clone();
+        let authorization = updated.authorize(&tx, self.clock.now())?;
+        self.authorizations.insert(tx.id, authorization.clone())?;
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.post_authorized(&authorization);
+        }
+        *account = updated;
+        Ok(())
+    }
+
+    /// Withdraws reserved funds. Captured authorization is logged as a withdrawal, so it can be disputed.
+    pub fn capture(
+        &mut self,
+        tx: TransactionDTO,
+        account: &mut Account,
+    ) -> Result<(), EngineError> {
+        let authorization = self
+            .authorizations
+            .get(&tx.id)?
+            .ok_or(EngineError::Resolver_AuthorizationNotFound)?;
+        // captured funds leave the account as any withdrawal does
+        let as_withdrawal = TransactionDTO {
+            kind: TxKind::Withdrawal,
+            amount: Some(authorization.amount),
+            ..tx.clone()
+        };
+        let now = self.clock.now();
+        self.risk.check(&as_withdrawal, now)?;
+        let fee = self.fees.adjustment_fee(&as_withdrawal);
+        let mut updated = account.clone();
+        let withdrawal = updated.capture(&authorization, &tx.id, &tx.client_id, fee)?;
+        self.transaction_log.insert(withdrawal.clone())?;
+        if let Err(e) = self.authorizations.remove(&tx.id) {
+            // authorization stays pending, so withdrawal mustn't be logged either
+            _ = self.transaction_log.remove(&tx.id);
+            return Err(e);
+        }
+        self.risk.record(&withdrawal, now);
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.post_captured(&authorization);
+            ledger.post_fee(tx.client_id, fee);
+        }
+        *account = updated;
+        Ok(())
+    }
+
+    pub fn void(&mut self, tx: TransactionDTO, account: &mut Account) -> Result<(), EngineError> {
+        let authorization = self
+            .authorizations
+            .get(&tx.id)?
+            .ok_or(EngineError::Resolver_AuthorizationNotFound)?;
+        let mut updated = account.clone();
+        updated.void(&authorization, &tx.client_id)?;
+        self.authorizations.remove(&tx.id)?;
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.post_released(&authorization);
+        }
+        *account = updated;
+        Ok(())
+    }
+
+    /// Authorizations made at least `expiry` seconds ago.
+    pub fn expired_authorizations(
+        &self,
+        expiry: u64,
+    ) -> Result<Vec<(TransactionId, ClientId)>, EngineError> {
+        let now = self.clock.now();
+        Ok(self
+            .authorizations
+            .active()?
+            .into_iter()
+            .filter(|(_, authorization)| now.saturating_sub(authorization.authorized_at) >= expiry)
+            .map(|(tx_id, authorization)| (tx_id, authorization.client_id))
+            .collect())
+    }
+
+    /// Releases funds of stale authorization, even on locked account.
+    pub fn expire_authorization(
+        &mut self,
+        tx_id: &TransactionId,
+        account: &mut Account,
+    ) -> Result<(), EngineError> {
+        let authorization = self
+            .authorizations
+            .get(tx_id)?
+            .ok_or(EngineError::Resolver_AuthorizationNotFound)?;
+        self.authorizations.remove(tx_id)?;
+        account.release(&authorization);
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.post_released(&authorization);
+        }
+        Ok(())
+    }
+
+    /// Undoes adjustment with its fee. Reversed adjustment can't be disputed, adjustment
+    /// under dispute or charged back can't be reversed.
+    pub fn reverse(
+        &mut self,
+        tx: TransactionDTO,
+        account: &mut Account,
+    ) -> Result<(), EngineError> {
+        let adjustment = self
+            .transaction_log
+            .get(&tx.id)?
+            .ok_or(EngineError::Resolver_TransactionNotFound)?;
+        if self.disputes.get(&tx.id)?.is_some() {
+            return Err(EngineError::Resolver_TransactionAlreadyUnderDispute);
+        }
+        if self.transaction_log.is_reversed(&tx.id)? || self.disputes.is_charged_back(&tx.id)? {
+            return Err(EngineError::Resolver_TransactionAlreadyReversed);
+        }
+        let mut updated = account.clone();
+        updated.reverse(&adjustment, &tx.client_id)?;
+        self.transaction_log.mark_reversed(&tx.id)?;
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.post_reversal(&adjustment);
+        }
+        *account = updated;
+        Ok(())
     }
 
     pub fn open_dispute(
@@ -38,15 +395,27 @@ impl TxResolver {
         tx_id: &TransactionId,
         account: &mut Account,
     ) -> Result<(), EngineError> {
-        match self.transaction_log.get(tx_id) {
+        match self.transaction_log.get(tx_id)? {
             Some(disputed_tx) => {
-                if self.active_disputes.contains_key(&disputed_tx.details.id) {
+                if self.disputes.get(&disputed_tx.details.id)?.is_some() {
                     return Err(EngineError::Resolver_TransactionAlreadyUnderDispute);
                 }
+                if self.transaction_log.is_reversed(tx_id)? {
+                    return Err(EngineError::Resolver_TransactionAlreadyReversed);
+                }
+                // unfrozen account can't claim the same funds back twice
+                if self.disputes.is_charged_back(tx_id)? {
+                    return Err(EngineError::Resolver_TransactionAlreadyChargedBack);
+                }
 
-                account.open_dispute(disputed_tx).map(|claim| {
-                    self.active_disputes.insert(disputed_tx.details.id, claim);
-                })
+                let mut updated = account.clone();
+                let claim = updated.open_dispute(&disputed_tx)?;
+                self.disputes.open(disputed_tx.details.id, claim.clone())?;
+                if let Some(ledger) = self.ledger.as_mut() {
+                    ledger.post_dispute_opened(&claim);
+                }
+                *account = updated;
+                Ok(())
             }
             None => Err(EngineError::Resolver_TransactionNotFound),
         }
@@ -57,22 +426,151 @@ impl TxResolver {
         tx: TransactionDTO,
         account: &mut Account,
     ) -> Result<(), EngineError> {
-        match self.active_disputes.get(&tx.id) {
-            Some(disputed_tx) => account
-                .resolve_dispute(disputed_tx, &tx.id, &tx.client_id, &tx.kind.try_into()?)
-                .map(|resolved_tx_id| {
-                    self.active_disputes.remove(&resolved_tx_id);
-                }),
+        match self.disputes.get(&tx.id)? {
+            Some(disputed_tx) => {
+                let resolution = tx.kind.try_into()?;
+                let mut updated = account.clone();
+                let resolved_tx_id =
+                    updated.resolve_dispute(&disputed_tx, &tx.id, &tx.client_id, &resolution)?;
+                let chargeback_fee = match resolution {
+                    ResolutionKind::Chargeback => self
+                        .fees
+                        .chargeback_fee(&disputed_tx.client_id, disputed_tx.amount),
+                    ResolutionKind::Resolve => TxAmount::ZERO,
+                };
+                let chargeback_fee = updated.charge_fee(chargeback_fee);
+                // fee is capped by available funds, so it's recorded as charged
+                if chargeback_fee != TxAmount::ZERO {
+                    self.jobs.record_accrual(tx.client_id, -chargeback_fee)?;
+                }
+                if let Err(e) = self.disputes.close(&resolved_tx_id, resolution) {
+                    if chargeback_fee != TxAmount::ZERO {
+                        _ = self.jobs.record_accrual(tx.client_id, chargeback_fee);
+                    }
+                    return Err(e);
+                }
+                if let Some(ledger) = self.ledger.as_mut() {
+                    ledger.post_dispute_closed(&disputed_tx, resolution);
+                    ledger.post_fee(disputed_tx.client_id, chargeback_fee);
+                }
+                *account = updated;
+                Ok(())
+            }
             None => Err(EngineError::Resolver_TransactionNotUnderDispute),
         }
     }
+
+    /// Takes adjustments, disputes, authorizations, accruals, ledger balances and risk
+    /// activity of clients in `states` out of the resolver, into their states.
+    pub fn take_clients(
+        &mut self,
+        states: &mut HashMap<ClientId, ClientState>,
+    ) -> Result<(), EngineError> {
+        let mut migrated = Vec::new();
+        self.transaction_log.for_each(&mut |adjustment| {
+            if states.contains_key(&adjustment.details.client_id) {
+                migrated.push(adjustment);
+            }
+            Ok(())
+        })?;
+        for adjustment in migrated {
+            let tx_id = adjustment.details.id;
+            let Some(state) = states.get_mut(&adjustment.details.client_id) else {
+                continue;
+            };
+            state.adjustments.push(MigratedAdjustment {
+                dispute: self.disputes.get(&tx_id)?,
+                reversed: self.transaction_log.is_reversed(&tx_id)?,
+                charged_back: self.disputes.is_charged_back(&tx_id)?,
+                adjustment,
+            });
+            self.disputes.remove(&tx_id)?;
+            self.transaction_log.remove(&tx_id)?;
+        }
+        for (tx_id, authorization) in self.authorizations.active()? {
+            if let Some(state) = states.get_mut(&authorization.client_id) {
+                self.authorizations.remove(&tx_id)?;
+                state.authorizations.push((tx_id, authorization));
+            }
+        }
+        for (client_id, state) in states.iter_mut() {
+            state.accrual = self.jobs.take_accrual(client_id)?;
+            state.ledger = self
+                .ledger
+                .as_mut()
+                .map(|ledger| ledger.post_migrated_out(*client_id));
+            state.risk = self.risk.take(client_id);
+        }
+        Ok(())
+    }
+
+    /// Takes over what [`Self::take_clients`] took out of resolver of another processor.
+    pub fn restore_client(&mut self, state: &mut ClientState) -> Result<(), EngineError> {
+        let client_id = state.client_id;
+        for migrated in std::mem::take(&mut state.adjustments) {
+            let tx_id = migrated.adjustment.details.id;
+            self.transaction_log.insert(migrated.adjustment)?;
+            if migrated.reversed {
+                self.transaction_log.mark_reversed(&tx_id)?;
+            }
+            if let Some(claim) = migrated.dispute {
+                self.disputes.open(tx_id, claim)?;
+            }
+            if migrated.charged_back {
+                self.disputes.close(&tx_id, ResolutionKind::Chargeback)?;
+            }
+        }
+        for (tx_id, authorization) in std::mem::take(&mut state.authorizations) {
+            self.authorizations.insert(tx_id, authorization)?;
+        }
+        if state.accrual != TxAmount::ZERO {
+            self.jobs.record_accrual(client_id, state.accrual)?;
+        }
+        if let (Some(ledger), Some(balances)) = (self.ledger.as_mut(), state.ledger) {
+            ledger.post_migrated_in(client_id, balances);
+        }
+        if let Some(activity) = state.risk.take() {
+            self.risk.restore(client_id, activity);
+        }
+        Ok(())
+    }
+
+    /// Closes dispute on behalf of an operator, even on locked account.
+    pub fn force_close_dispute(
+        &mut self,
+        tx_id: TransactionId,
+        resolution: ResolutionKind,
+        account: &mut Account,
+    ) -> Result<(), EngineError> {
+        let tx = TransactionDTO {
+            id: tx_id,
+            client_id: account.client_id,
+            kind: match resolution {
+                ResolutionKind::Resolve => TxKind::Resolve,
+                ResolutionKind::Chargeback => TxKind::Chargeback,
+            },
+            amount: None,
+            effective_at: None,
+            sequence_number: None,
+            batch_id: None,
+        };
+        // lock is lifted for the time of closing only, chargeback locks the account anyway
+        let locked = std::mem::replace(&mut account.locked, false);
+        let result = self.close_dispute(tx, account);
+        account.locked |= locked;
+        result
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::engine::{
-        core::account::Account,
-        objects::{ClientId, TransactionDTO, TransactionId, TxKind},
+        EngineError,
+        core::{account::Account, ledger::Ledger, reconciliation::reconcile},
+        fees::FeeSchedule,
+        objects::{Adjustment, ClientId, TransactionDTO, TransactionId, TxAmount, TxKind},
+        risk::RiskLimits,
+        storage::TransactionLogStore,
     };
 
     use super::TxResolver;
@@ -80,15 +578,10 @@ mod tests {
     #[test]
     fn opening_dispute_for_missing_transaction_fails() {
         let mut account = Account {
-            available: 100.0,
+            available: TxAmount::from_f64(100.0),
             ..Account::new(ClientId(1))
         };
-        let tx = TransactionDTO {
-            id: TransactionId(1),
-            client_id: ClientId(1),
-            kind: TxKind::Dispute,
-            amount: None,
-        };
+        let tx = TransactionDTO::new(1, 1, TxKind::Dispute, None);
         let mut resolver = TxResolver::new();
         let res = resolver.open_dispute(&tx.id, &mut account);
         assert!(res.is_err())
@@ -97,21 +590,11 @@ mod tests {
     #[test]
     fn opening_new_dispute_for_already_disputed_transaction_fails() {
         let mut account = Account {
-            available: 100.0,
+            available: TxAmount::from_f64(100.0),
             ..Account::new(ClientId(1))
         };
-        let tx0 = TransactionDTO {
-            id: TransactionId(1),
-            client_id: ClientId(1),
-            kind: TxKind::Deposit,
-            amount: Some(100.0),
-        };
-        let tx1 = TransactionDTO {
-            id: TransactionId(1),
-            client_id: ClientId(1),
-            kind: TxKind::Dispute,
-            amount: None,
-        };
+        let tx0 = TransactionDTO::new(1, 1, TxKind::Deposit, Some(100.0));
+        let tx1 = TransactionDTO::new(1, 1, TxKind::Dispute, None);
         let mut resolver = TxResolver::new();
 
         assert!(resolver.apply_adjustment(tx0, &mut account).is_ok());
@@ -122,21 +605,11 @@ mod tests {
     #[test]
     fn closing_not_disputed_transaction_fails() {
         let mut account = Account {
-            available: 100.0,
+            available: TxAmount::from_f64(100.0),
             ..Account::new(ClientId(1))
         };
-        let tx0 = TransactionDTO {
-            id: TransactionId(1),
-            client_id: ClientId(1),
-            kind: TxKind::Deposit,
-            amount: Some(100.0),
-        };
-        let tx1 = TransactionDTO {
-            id: TransactionId(1),
-            client_id: ClientId(1),
-            kind: TxKind::Dispute,
-            amount: None,
-        };
+        let tx0 = TransactionDTO::new(1, 1, TxKind::Deposit, Some(100.0));
+        let tx1 = TransactionDTO::new(1, 1, TxKind::Dispute, None);
         let mut resolver = TxResolver::new();
 
         let res = resolver.apply_adjustment(tx0, &mut account);
@@ -150,38 +623,18 @@ mod tests {
     fn open_and_close_dispute_succesfully() {
         let mut account = Account::new(ClientId(1));
         let mut resolver = TxResolver::new();
-        let tx0 = TransactionDTO {
-            id: TransactionId(1),
-            client_id: ClientId(1),
-            kind: TxKind::Deposit,
-            amount: Some(100.0),
-        };
-        let tx1 = TransactionDTO {
-            id: TransactionId(2),
-            client_id: ClientId(1),
-            kind: TxKind::Withdrawal,
-            amount: Some(30.0),
-        };
-        let tx2 = TransactionDTO {
-            id: TransactionId(2),
-            client_id: ClientId(1),
-            kind: TxKind::Deposit,
-            amount: Some(60.0),
-        };
-        let tx0_chargeback = TransactionDTO {
-            id: TransactionId(1),
-            client_id: ClientId(1),
-            kind: TxKind::Chargeback,
-            amount: None,
-        };
+        let tx0 = TransactionDTO::new(1, 1, TxKind::Deposit, Some(100.0));
+        let tx1 = TransactionDTO::new(2, 1, TxKind::Withdrawal, Some(30.0));
+        let tx2 = TransactionDTO::new(3, 1, TxKind::Deposit, Some(60.0));
+        let tx0_chargeback = TransactionDTO::new(1, 1, TxKind::Chargeback, None);
 
         assert!(resolver.apply_adjustment(tx0.clone(), &mut account).is_ok());
         assert!(resolver.apply_adjustment(tx1, &mut account).is_ok());
 
         // opening dispute regardless of account balance
         assert!(resolver.open_dispute(&tx0.id, &mut account).is_ok());
-        assert_eq!(account.available, -30.0);
-        assert_eq!(account.held, 100.0);
+        assert_eq!(account.available, TxAmount::from_f64(-30.0));
+        assert_eq!(account.held, TxAmount::from_f64(100.0));
 
         // can't chargeback dispute due to insufficient funds
         assert!(
@@ -195,7 +648,235 @@ mod tests {
 
         // dispute can be charged back when funds are available
         assert!(resolver.close_dispute(tx0_chargeback, &mut account).is_ok());
-        assert_eq!(account.available, 30.0);
-        assert_eq!(account.held, 0.0);
+        assert_eq!(account.available, TxAmount::from_f64(30.0));
+        assert_eq!(account.held, TxAmount::ZERO);
+    }
+
+    #[test]
+    fn resubmitted_adjustment_is_applied_once() {
+        let mut account = Account::new(ClientId(1));
+        let deposit = TransactionDTO::new(1, 1, TxKind::Deposit, Some(100.0));
+        let conflicting = TransactionDTO {
+            amount: Some(TxAmount::from_f64(50.0)),
+            ..deposit.clone()
+        };
+        let mut resolver = TxResolver::new();
+
+        assert!(
+            resolver
+                .apply_adjustment(deposit.clone(), &mut account)
+                .is_ok()
+        );
+        assert!(resolver.apply_adjustment(deposit, &mut account).is_ok());
+        assert!(
+            resolver
+                .apply_adjustment(conflicting, &mut account)
+                .is_err()
+        );
+        assert_eq!(account.available, TxAmount::from_f64(100.0));
+    }
+
+    #[test]
+    fn capture_is_charged_and_limited_as_withdrawal() {
+        let fees = FeeSchedule::parse("fee, default, withdrawal, 2, 0").unwrap();
+        let limits = RiskLimits::parse("withdrawal_cap, 50").unwrap();
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new()
+            .with_ledger(Ledger::new())
+            .with_fees(fees)
+            .with_risk_limits(limits);
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+
+        assert!(
+            resolver
+                .apply_adjustment(tx(1, TxKind::Deposit, Some(100.0)), &mut account)
+                .is_ok()
+        );
+        for (id, amount) in [(2, 40.0), (3, 60.0)] {
+            assert!(
+                resolver
+                    .authorize(tx(id, TxKind::Authorize, Some(amount)), &mut account)
+                    .is_ok()
+            );
+        }
+        assert!(matches!(
+            resolver.capture(tx(3, TxKind::Capture, None), &mut account),
+            Err(EngineError::Risk_WithdrawalCapExceeded)
+        ));
+        assert!(
+            resolver
+                .void(tx(3, TxKind::Void, None), &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .capture(tx(2, TxKind::Capture, None), &mut account)
+                .is_ok()
+        );
+        assert_eq!(
+            (account.available, account.reserved, account.fees),
+            (
+                TxAmount::from_f64(58.0),
+                TxAmount::ZERO,
+                TxAmount::from_f64(2.0)
+            )
+        );
+        assert!(reconcile(&resolver, [&account]).unwrap().is_empty());
+        assert!(resolver.ledger().unwrap().verify_account(&account).is_ok());
+    }
+
+    #[test]
+    fn authorizations_reserve_funds_until_captured_or_voided() {
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new().with_ledger(Ledger::new());
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+
+        assert!(
+            resolver
+                .apply_adjustment(tx(1, TxKind::Deposit, Some(100.0)), &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .authorize(tx(2, TxKind::Authorize, Some(60.0)), &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .authorize(tx(3, TxKind::Authorize, Some(30.0)), &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .authorize(tx(4, TxKind::Authorize, Some(20.0)), &mut account)
+                .is_err()
+        );
+        assert_eq!(account.available, TxAmount::from_f64(10.0));
+        assert_eq!(account.reserved, TxAmount::from_f64(90.0));
+        assert!(
+            resolver
+                .capture(tx(2, TxKind::Capture, None), &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .void(tx(3, TxKind::Void, None), &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .void(tx(3, TxKind::Void, None), &mut account)
+                .is_err()
+        );
+        assert_eq!(account.available, TxAmount::from_f64(40.0));
+        assert_eq!(account.reserved, TxAmount::ZERO);
+
+        // captured authorization can be disputed like a withdrawal
+        assert!(
+            resolver
+                .open_dispute(&TransactionId(2), &mut account)
+                .is_ok()
+        );
+        let ledger = resolver.ledger().unwrap();
+        assert!(ledger.trial_balance().is_balanced());
+        assert!(ledger.verify_account(&account).is_ok());
+        assert!(reconcile(&resolver, [&account]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reversed_adjustment_cant_be_disputed() {
+        let mut account = Account::new(ClientId(1));
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+        let mut resolver = TxResolver::new().with_ledger(Ledger::new());
+
+        for (id, kind) in [(1, TxKind::Deposit), (2, TxKind::Withdrawal)] {
+            assert!(
+                resolver
+                    .apply_adjustment(tx(id, kind, Some(40.0)), &mut account)
+                    .is_ok()
+            );
+        }
+        assert!(
+            resolver
+                .open_dispute(&TransactionId(2), &mut account)
+                .is_ok()
+        );
+        // disputed adjustment has to be resolved first
+        assert!(matches!(
+            resolver.reverse(tx(2, TxKind::Reversal, None), &mut account),
+            Err(EngineError::Resolver_TransactionAlreadyUnderDispute)
+        ));
+        assert!(
+            resolver
+                .reverse(tx(1, TxKind::Reversal, None), &mut account)
+                .is_err()
+        );
+        let resolve = tx(2, TxKind::Resolve, None);
+        assert!(resolver.close_dispute(resolve, &mut account).is_ok());
+        assert!(
+            resolver
+                .reverse(tx(2, TxKind::Reversal, None), &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .reverse(tx(1, TxKind::Reversal, None), &mut account)
+                .is_ok()
+        );
+
+        assert_eq!(account.available, TxAmount::ZERO);
+        assert!(!account.locked);
+        assert!(matches!(
+            resolver.open_dispute(&TransactionId(1), &mut account),
+            Err(EngineError::Resolver_TransactionAlreadyReversed)
+        ));
+        assert!(matches!(
+            resolver.reverse(tx(1, TxKind::Reversal, None), &mut account),
+            Err(EngineError::Resolver_TransactionAlreadyReversed)
+        ));
+        assert!(reconcile(&resolver, [&account]).unwrap().is_empty());
+        assert!(resolver.ledger().unwrap().verify_account(&account).is_ok());
+    }
+
+    struct FailingLog;
+
+    impl TransactionLogStore for FailingLog {
+        fn insert(&mut self, _: Adjustment) -> Result<(), EngineError> {
+            Err(EngineError::Storage_WriteFailed)
+        }
+        fn get(&self, _: &TransactionId) -> Result<Option<Adjustment>, EngineError> {
+            Ok(None)
+        }
+        fn for_each(
+            &self,
+            _: &mut dyn FnMut(Adjustment) -> Result<(), EngineError>,
+        ) -> Result<(), EngineError> {
+            Ok(())
+        }
+        fn mark_reversed(&mut self, _: &TransactionId) -> Result<(), EngineError> {
+            Err(EngineError::Storage_WriteFailed)
+        }
+        fn is_reversed(&self, _: &TransactionId) -> Result<bool, EngineError> {
+            Ok(false)
+        }
+        fn remove(&mut self, _: &TransactionId) -> Result<(), EngineError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_store_write_leaves_account_and_ledger_untouched() {
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new()
+            .with_ledger(Ledger::new())
+            .with_transaction_log(Box::new(FailingLog));
+        let deposit = TransactionDTO::new(1, 1, TxKind::Deposit, Some(10.0));
+
+        assert!(matches!(
+            resolver.apply_adjustment(deposit, &mut account),
+            Err(EngineError::Storage_WriteFailed)
+        ));
+        assert_eq!(account.available, TxAmount::ZERO);
+        assert!(resolver.ledger().unwrap().verify_account(&account).is_ok());
     }
 }
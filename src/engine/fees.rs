@@ -0,0 +1,286 @@
+use std::{collections::HashMap, error::Error, str::FromStr};
+
+use super::objects::{ClientId, TransactionDTO, TxAmount, TxKind};
+
+pub const DEFAULT_TIER: &str = "default";
+
+/// Fixed part plus percentage of transaction amount.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Fee {
+    pub fixed: TxAmount,
+    pub percent: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "camelCase")]
+pub enum FeeKind {
+    Deposit,
+    Withdrawal,
+    Chargeback,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TierFees {
+    pub deposit: Fee,
+    pub withdrawal: Fee,
+    pub chargeback: Fee,
+}
+
+/// Fees charged per transaction kind, depending on client's tier. Clients without
+/// assigned tier pay fees of `default` tier, or nothing if there is no such tier.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FeeSchedule {
+    tiers: HashMap<String, TierFees>,
+    client_tiers: HashMap<ClientId, String>,
+}
+
+impl Fee {
+    fn of(&self, amount: TxAmount) -> TxAmount {
+        self.fixed + amount.percent(self.percent)
+    }
+}
+
+impl TierFees {
+    fn fee_mut(&mut self, kind: FeeKind) -> &mut Fee {
+        match kind {
+            FeeKind::Deposit => &mut self.deposit,
+            FeeKind::Withdrawal => &mut self.withdrawal,
+            FeeKind::Chargeback => &mut self.chargeback,
+        }
+    }
+}
+
+impl FeeSchedule {
+    pub fn set_fee(&mut self, tier: &str, kind: FeeKind, fee: Fee) {
+        *self
+            .tiers
+            .entry(tier.to_string())
+            .or_default()
+            .fee_mut(kind) = fee;
+    }
+
+    pub fn assign_tier(&mut self, client_id: ClientId, tier: &str) {
+        self.client_tiers.insert(client_id, tier.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiers.is_empty()
+    }
+
+    /// Fee for deposit or withdrawal. Deposit fee never exceeds the deposited amount.
+    pub fn adjustment_fee(&self, tx: &TransactionDTO) -> TxAmount {
+        let (Some(tier), Some(amount)) = (self.tier(&tx.client_id), tx.amount) else {
+            return TxAmount::ZERO;
+        };
+        match tx.kind {
+            TxKind::Deposit => tier.deposit.of(amount).clamp(TxAmount::ZERO, amount),
+            TxKind::Withdrawal => tier.withdrawal.of(amount).max(TxAmount::ZERO),
+            _ => TxAmount::ZERO,
+        }
+    }
+
+    /// Fee for charging back transaction of `amount`.
+    pub fn chargeback_fee(&self, client_id: &ClientId, amount: TxAmount) -> TxAmount {
+        match self.tier(client_id) {
+            Some(tier) => tier.chargeback.of(amount).max(TxAmount::ZERO),
+            None => TxAmount::ZERO,
+        }
+    }
+
+    fn tier(&self, client_id: &ClientId) -> Option<&TierFees> {
+        let tier = self
+            .client_tiers
+            .get(client_id)
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_TIER);
+        self.tiers.get(tier)
+    }
+
+    /// Reads schedule from lines `fee, <tier>, <kind>, <fixed>, <percent>`
+    /// and `tier, <client>, <tier>`. Empty lines and `#` comments are skipped.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut schedule = FeeSchedule::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            match fields.as_slice() {
+                ["fee", tier, kind, fixed, percent] => schedule.set_fee(
+                    tier,
+                    FeeKind::from_str(kind)?,
+                    Fee {
+                        fixed: fixed.parse()?,
+                        percent: percent.parse()?,
+                    },
+                ),
+                ["tier", client_id, tier] => {
+                    schedule.assign_tier(ClientId(client_id.parse()?), tier)
+                }
+                _ => return Err(format!("line {}: unknown fee record", number + 1).into()),
+            }
+        }
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        EngineError,
+        core::{
+            account::Account, ledger::Ledger, reconciliation::reconcile, tx_resolver::TxResolver,
+        },
+        objects::{ClientId, RawClientId, TransactionDTO, TxAmount, TxKind},
+    };
+
+    use super::FeeSchedule;
+
+    fn tx(client_id: RawClientId, kind: TxKind, amount: f64) -> TransactionDTO {
+        TransactionDTO::new(1, client_id, kind, Some(amount))
+    }
+
+    #[test]
+    fn fees_depend_on_client_tier() {
+        let schedule = FeeSchedule::parse(
+            "# tier fees
+            fee, default, deposit, 0.5, 1
+            fee, default, withdrawal, 2, 0
+            fee, premium, withdrawal, 0, 0.5
+            tier, 7, premium",
+        )
+        .unwrap();
+
+        assert_eq!(
+            schedule.adjustment_fee(&tx(1, TxKind::Deposit, 100.0)),
+            TxAmount::from_f64(1.5)
+        );
+        assert_eq!(
+            schedule.adjustment_fee(&tx(1, TxKind::Deposit, 0.2)),
+            TxAmount::from_f64(0.2)
+        );
+        assert_eq!(
+            schedule.adjustment_fee(&tx(1, TxKind::Withdrawal, 100.0)),
+            TxAmount::from_f64(2.0)
+        );
+        assert_eq!(
+            schedule.adjustment_fee(&tx(7, TxKind::Withdrawal, 100.0)),
+            TxAmount::from_f64(0.5)
+        );
+        assert_eq!(
+            schedule.adjustment_fee(&tx(7, TxKind::Deposit, 100.0)),
+            TxAmount::ZERO
+        );
+        assert!(FeeSchedule::parse("fee, default, refund, 1, 0").is_err());
+    }
+
+    #[test]
+    fn withdrawal_fee_needs_funds_and_chargeback_refunds_it() {
+        let schedule = FeeSchedule::parse(
+            "fee, default, deposit, 1, 0
+            fee, default, withdrawal, 2, 0
+            fee, default, chargeback, 5, 0",
+        )
+        .unwrap();
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new().with_fees(schedule);
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+
+        assert!(
+            resolver
+                .apply_adjustment(tx(1, TxKind::Deposit, Some(100.0)), &mut account)
+                .is_ok()
+        );
+        // 98 plus fee is more than 99 available
+        assert!(matches!(
+            resolver.apply_adjustment(tx(2, TxKind::Withdrawal, Some(98.0)), &mut account),
+            Err(EngineError::Account_NotEnoughFunds)
+        ));
+        assert_eq!(
+            (account.available, account.fees),
+            (TxAmount::from_f64(99.0), TxAmount::from_f64(1.0))
+        );
+        assert!(
+            resolver
+                .apply_adjustment(tx(3, TxKind::Withdrawal, Some(97.0)), &mut account)
+                .is_ok()
+        );
+        assert_eq!(
+            (account.available, account.fees),
+            (TxAmount::ZERO, TxAmount::from_f64(3.0))
+        );
+
+        assert!(
+            resolver
+                .open_dispute(&tx(3, TxKind::Dispute, None).id, &mut account)
+                .is_ok()
+        );
+        assert!(
+            resolver
+                .close_dispute(tx(3, TxKind::Chargeback, None), &mut account)
+                .is_ok()
+        );
+        // withdrawal and its fee come back, chargeback fee is charged
+        assert_eq!(
+            (account.available, account.fees),
+            (TxAmount::from_f64(94.0), TxAmount::from_f64(6.0))
+        );
+    }
+
+    #[test]
+    fn disputed_deposit_holds_what_it_credited_and_chargeback_fee_is_capped() {
+        let schedule = FeeSchedule::parse(
+            "fee, default, deposit, 1, 0
+            fee, default, chargeback, 15, 0",
+        )
+        .unwrap();
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new()
+            .with_fees(schedule)
+            .with_ledger(Ledger::new());
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+
+        for (id, amount) in [(1, 100.0), (2, 10.0)] {
+            assert!(
+                resolver
+                    .apply_adjustment(tx(id, TxKind::Deposit, Some(amount)), &mut account)
+                    .is_ok()
+            );
+        }
+        assert!(
+            resolver
+                .open_dispute(&tx(1, TxKind::Dispute, None).id, &mut account)
+                .is_ok()
+        );
+        assert_eq!(
+            (account.available, account.held),
+            (TxAmount::from_f64(9.0), TxAmount::from_f64(99.0))
+        );
+        assert!(
+            resolver
+                .close_dispute(tx(1, TxKind::Chargeback, None), &mut account)
+                .is_ok()
+        );
+        // deposit fee is refunded, chargeback fee takes no more than is available
+        assert_eq!(
+            (account.available, account.held, account.fees),
+            (TxAmount::ZERO, TxAmount::ZERO, TxAmount::from_f64(10.0))
+        );
+        assert!(account.locked);
+        assert!(reconcile(&resolver, [&account]).unwrap().is_empty());
+        assert!(resolver.ledger().unwrap().verify_account(&account).is_ok());
+    }
+
+    #[test]
+    fn malformed_schedule_is_rejected() {
+        for schedule in [
+            "fee, default, deposit, 1",
+            "fee, default, deposit, one, 0",
+            "tier, first, vip",
+            "discount, default, 10",
+        ] {
+            assert!(FeeSchedule::parse(schedule).is_err(), "{schedule}");
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::error::Error;
+
+use super::{clock::Timestamp, objects::TxAmount};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JobKind {
+    /// Credits `rate` percent of available funds.
+    Interest { rate: f64 },
+    /// Charges fixed fee, at most what's available.
+    MaintenanceFee { amount: TxAmount },
+    /// Flags accounts without any transaction for `after` seconds.
+    Dormancy { after: u64 },
+}
+
+/// Job run over every account of a processor once per `period` seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeriodicJob {
+    pub kind: JobKind,
+    pub period: u64,
+}
+
+/// Periodic jobs run by processors, none by default.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JobSchedule {
+    pub jobs: Vec<PeriodicJob>,
+}
+
+impl PeriodicJob {
+    /// Identifies job in the job store, so it isn't rerun for the same period after restart.
+    pub fn name(&self) -> &'static str {
+        match self.kind {
+            JobKind::Interest { .. } => "interest",
+            JobKind::MaintenanceFee { .. } => "maintenance_fee",
+            JobKind::Dormancy { .. } => "dormancy",
+        }
+    }
+
+    /// Time of the first run after `last_run`.
+    pub fn next_run(&self, last_run: Timestamp) -> Timestamp {
+        last_run + self.period
+    }
+}
+
+impl JobSchedule {
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Reads jobs from lines `interest, <percent>, <period>`, `maintenance_fee, <amount>, <period>`
+    /// and `dormancy, <inactive seconds>, <period>`, at most one of each kind.
+    /// Empty lines and `#` comments are skipped.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut schedule = JobSchedule::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let (kind, period) = match fields.as_slice() {
+                ["interest", rate, period] => (
+                    JobKind::Interest {
+                        rate: rate.parse()?,
+                    },
+                    period,
+                ),
+                ["maintenance_fee", amount, period] => (
+                    JobKind::MaintenanceFee {
+                        amount: amount.parse()?,
+                    },
+                    period,
+                ),
+                ["dormancy", after, period] => (
+                    JobKind::Dormancy {
+                        after: after.parse()?,
+                    },
+                    period,
+                ),
+                _ => return Err(format!("line {}: unknown job record", number + 1).into()),
+            };
+            let job = PeriodicJob {
+                kind,
+                period: period.parse()?,
+            };
+            if job.period == 0 {
+                return Err(format!("line {}: job period must be positive", number + 1).into());
+            }
+            if schedule.jobs.iter().any(|other| other.name() == job.name()) {
+                return Err(format!("line {}: {} job is set twice", number + 1, job.name()).into());
+            }
+            schedule.jobs.push(job);
+        }
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        clock::Clock,
+        objects::{ClientId, RawClientId, RawTransactionId, TransactionDTO, TxAmount, TxKind},
+        processor::{ProcessorConfig, ProcessorImpl},
+    };
+
+    use super::JobSchedule;
+
+    const DAY: u64 = 86400;
+
+    fn deposit(client_id: RawClientId, amount: f64) -> TransactionDTO {
+        TransactionDTO::new(
+            RawTransactionId::from(client_id),
+            client_id,
+            TxKind::Deposit,
+            Some(amount),
+        )
+    }
+
+    #[test]
+    fn jobs_run_once_per_period() {
+        let jobs = JobSchedule::parse(
+            "interest, 1, 86400
+            maintenance_fee, 5, 2592000
+            dormancy, 864000, 86400",
+        )
+        .unwrap();
+        let clock = Clock::simulated(0);
+        let config = ProcessorConfig {
+            clock: clock.clone(),
+            jobs,
+            double_entry: true,
+            ..Default::default()
+        };
+        let mut processor = ProcessorImpl::new(0, config);
+
+        assert!(processor.process(deposit(1, 100.0)).is_ok());
+        clock.advance(DAY / 2);
+        assert!(processor.run_jobs().is_ok());
+        assert_eq!(
+            processor.account(&ClientId(1)).unwrap().unwrap().available,
+            TxAmount::from_f64(100.0)
+        );
+        clock.advance(DAY * 3 / 2);
+        assert!(processor.run_jobs().is_ok());
+        // interest compounds daily
+        assert_eq!(
+            processor.account(&ClientId(1)).unwrap().unwrap().available,
+            TxAmount::from_f64(102.01)
+        );
+
+        assert!(processor.process(deposit(2, 3.0)).is_ok());
+        clock.advance(28 * DAY);
+        assert!(processor.run_jobs().is_ok());
+        let second = processor.account(&ClientId(2)).unwrap().unwrap();
+        // fee takes at most what's available
+        assert_eq!(second.available, TxAmount::ZERO);
+        assert!(second.dormant);
+        assert!(processor.reconcile().unwrap().is_empty());
+
+        // only applied transaction is activity
+        assert!(processor.process(deposit(2, 1.0)).is_err());
+        assert!(processor.account(&ClientId(2)).unwrap().unwrap().dormant);
+        let next = TransactionDTO::new(20, 2, TxKind::Deposit, Some(1.0));
+        assert!(processor.process(next).is_ok());
+        assert!(!processor.account(&ClientId(2)).unwrap().unwrap().dormant);
+        assert!(processor.account(&ClientId(1)).unwrap().unwrap().dormant);
+        assert!(JobSchedule::parse("interest, 1, 0").is_err());
+    }
+
+    #[test]
+    fn locked_accounts_skip_interest_and_fees() {
+        let jobs = JobSchedule::parse(
+            "interest, 10, 86400
+            maintenance_fee, 1, 86400",
+        )
+        .unwrap();
+        let clock = Clock::simulated(0);
+        let config = ProcessorConfig {
+            clock: clock.clone(),
+            jobs,
+            ..Default::default()
+        };
+        let mut processor = ProcessorImpl::new(0, config);
+        let tx = |kind| TransactionDTO::new(1, 1, kind, None);
+
+        assert!(processor.process(deposit(1, 100.0)).is_ok());
+        assert!(processor.process(deposit(2, 100.0)).is_ok());
+        for kind in [TxKind::Dispute, TxKind::Chargeback] {
+            assert!(processor.process(tx(kind)).is_ok());
+        }
+        clock.advance(DAY);
+        assert!(processor.run_jobs().is_ok());
+
+        let locked = processor.account(&ClientId(1)).unwrap().unwrap();
+        assert!(locked.locked);
+        assert_eq!(
+            (locked.available, locked.fees),
+            (TxAmount::ZERO, TxAmount::ZERO)
+        );
+        // interest runs first, then the fee
+        let open = processor.account(&ClientId(2)).unwrap().unwrap();
+        assert_eq!(
+            (open.available, open.fees),
+            (TxAmount::from_f64(109.0), TxAmount::from_f64(1.0))
+        );
+    }
+
+    #[test]
+    fn malformed_schedule_is_rejected() {
+        for jobs in [
+            "interest, 1",
+            "interest, high, 86400",
+            "dormancy, 100, 0",
+            "interest, 1, 86400\ninterest, 2, 86400",
+            "payout, 1, 86400",
+        ] {
+            assert!(JobSchedule::parse(jobs).is_err(), "{jobs}");
+        }
+    }
+}
@@ -1,6 +1,30 @@
+pub mod admin;
+pub mod batch;
+pub mod clock;
 mod core;
+pub mod fees;
+pub mod jobs;
+#[cfg(test)]
+mod model_tests;
 pub mod objects;
 pub mod processor;
+pub mod references;
+pub mod replay;
+pub mod replication;
+pub mod resharding;
+pub mod risk;
+pub mod router;
+pub mod schedule;
+pub mod screening;
+pub mod sequencing;
+pub mod shutdown;
+pub mod statement;
+pub mod storage;
+mod supervisor;
+#[cfg(test)]
+mod temp_path;
+
+pub use core::account::Account;
 
 #[allow(non_camel_case_types)]
 #[derive(Clone, Copy, Debug)]
@@ -8,13 +32,50 @@ pub enum EngineError {
     Resolver_TransactionNotFound,
     Resolver_TransactionNotUnderDispute,
     Resolver_TransactionAlreadyUnderDispute,
+    Resolver_DuplicateTransactionId,
+    Resolver_AuthorizationNotFound,
+    Resolver_TransactionAlreadyReversed,
+    Resolver_TransactionAlreadyChargedBack,
 
     Account_DisputeReferencesDifferentClient_OnCreation,
     Account_DisputeReferencesDifferentClient_OnResolution,
+    Account_AuthorizationReferencesDifferentClient,
+    Account_ReversalReferencesDifferentClient,
     Account_AccountLocked,
     Account_NotEnoughFunds,
 
+    Ledger_AccountOutOfBalance,
+    Ledger_BalanceWithoutAccount,
+
+    Risk_WithdrawalCapExceeded,
+    Risk_WithdrawalVolumeExceeded,
+    Risk_WithdrawalCountExceeded,
+    Risk_WithdrawalHeldAfterLargeDeposit,
+
+    Schedule_TransactionNotScheduled,
+
+    Sequence_DuplicateSequenceNumber,
+
+    Batch_UnsupportedTransaction,
+    Batch_RolledBack,
+
+    Screening_Rejected(&'static str),
+    Screening_HeldForReview,
+    Screening_TransactionNotUnderReview,
+
+    Admin_NotForProcessor,
+
+    Storage_ReadFailed,
+    Storage_WriteFailed,
+
+    Checkpoint_DifferentInput,
+    Checkpoint_DifferentShards,
+
     Parsing_MissingAmountFieldConstructingAdjustment,
+    Parsing_MissingAmountFieldConstructingAuthorization,
     Parsing_TryingToConstructAdjustmentFromIncompatibileTransaction,
     Parsing_TryingToConstructDisputeFromIncompatibileTransaction,
+    Parsing_TransactionIdTakenByReference,
+    Parsing_NoTransactionIdLeftForReference,
+    Parsing_NonPositiveAmount,
 }
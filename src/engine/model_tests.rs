@@ -0,0 +1,309 @@
+//! Model-based tests: random transaction sequences are run through `ProcessorImpl`
+//! and checked against a straightforward reference model of the engine rules.
+
+use std::collections::{HashMap, HashSet};
+
+use proptest::prelude::*;
+
+use super::{
+    objects::{RawClientId, RawTransactionId, TransactionDTO, TxKind},
+    processor::{ProcessorConfig, ProcessorImpl},
+    router::{RouterConfig, run_scaled},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+// amounts are in ten-thousandths
+struct ModelAccount {
+    available: i64,
+    held: i64,
+    reserved: i64,
+    locked: bool,
+}
+
+#[derive(Clone, Copy)]
+struct ModelEntry {
+    client_id: RawClientId,
+    deposit: bool,
+    amount: i64,
+}
+
+/// Reference implementation of engine rules, written without any of engine's types.
+#[derive(Default)]
+struct Model {
+    accounts: HashMap<RawClientId, ModelAccount>,
+    log: HashMap<RawTransactionId, ModelEntry>,
+    disputes: HashMap<RawTransactionId, ModelEntry>,
+    authorizations: HashMap<RawTransactionId, ModelEntry>,
+    charged_back: HashSet<RawTransactionId>,
+    reversed: HashSet<RawTransactionId>,
+}
+
+impl Model {
+    fn apply(&mut self, tx: &TransactionDTO) -> bool {
+        let client_id = tx.client_id.0;
+        let account = self.accounts.entry(client_id).or_default();
+        match tx.kind {
+            TxKind::Deposit | TxKind::Withdrawal => {
+                let Some(amount) = tx.amount.map(|amount| amount.0) else {
+                    return false;
+                };
+                let deposit = matches!(tx.kind, TxKind::Deposit);
+                if let Some(applied) = self.log.get(&tx.id.0) {
+                    // identical resubmission is acknowledged, conflicting one rejected
+                    return applied.client_id == client_id
+                        && applied.deposit == deposit
+                        && applied.amount == amount;
+                }
+                if self.authorizations.contains_key(&tx.id.0)
+                    || account.locked
+                    || (!deposit && account.available - amount < 0)
+                {
+                    return false;
+                }
+                match deposit {
+                    true => account.available += amount,
+                    false => account.available -= amount,
+                }
+                self.log.insert(
+                    tx.id.0,
+                    ModelEntry {
+                        client_id,
+                        deposit,
+                        amount,
+                    },
+                );
+                true
+            }
+            TxKind::Dispute => {
+                let Some(entry) = self.log.get(&tx.id.0).copied() else {
+                    return false;
+                };
+                if self.disputes.contains_key(&tx.id.0)
+                    || self.reversed.contains(&tx.id.0)
+                    || account.locked
+                    || entry.client_id != client_id
+                {
+                    return false;
+                }
+                if entry.deposit {
+                    account.available -= entry.amount;
+                    account.held += entry.amount;
+                }
+                self.disputes.insert(tx.id.0, entry);
+                true
+            }
+            TxKind::Resolve | TxKind::Chargeback => {
+                let Some(entry) = self.disputes.get(&tx.id.0).copied() else {
+                    return false;
+                };
+                if account.locked || entry.client_id != client_id {
+                    return false;
+                }
+                let chargeback = matches!(tx.kind, TxKind::Chargeback);
+                match (entry.deposit, chargeback) {
+                    (true, false) => {
+                        account.available += entry.amount;
+                        account.held -= entry.amount;
+                    }
+                    (true, true) => {
+                        if entry.amount > account.available + account.held {
+                            return false;
+                        }
+                        account.held -= entry.amount;
+                        account.locked = true;
+                    }
+                    (false, true) => account.available += entry.amount,
+                    (false, false) => {}
+                }
+                if chargeback {
+                    self.charged_back.insert(tx.id.0);
+                }
+                self.disputes.remove(&tx.id.0);
+                true
+            }
+            TxKind::Reversal => {
+                let Some(entry) = self.log.get(&tx.id.0).copied() else {
+                    return false;
+                };
+                if self.disputes.contains_key(&tx.id.0)
+                    || self.reversed.contains(&tx.id.0)
+                    || self.charged_back.contains(&tx.id.0)
+                    || account.locked
+                    || entry.client_id != client_id
+                    || (entry.deposit && account.available - entry.amount < 0)
+                {
+                    return false;
+                }
+                match entry.deposit {
+                    true => account.available -= entry.amount,
+                    false => account.available += entry.amount,
+                }
+                self.reversed.insert(tx.id.0);
+                true
+            }
+            TxKind::Authorize => {
+                let Some(amount) = tx.amount.map(|amount| amount.0) else {
+                    return false;
+                };
+                if let Some(pending) = self.authorizations.get(&tx.id.0) {
+                    return pending.client_id == client_id && pending.amount == amount;
+                }
+                if self.log.contains_key(&tx.id.0)
+                    || account.locked
+                    || account.available - amount < 0
+                {
+                    return false;
+                }
+                account.available -= amount;
+                account.reserved += amount;
+                self.authorizations.insert(
+                    tx.id.0,
+                    ModelEntry {
+                        client_id,
+                        deposit: false,
+                        amount,
+                    },
+                );
+                true
+            }
+            TxKind::Capture | TxKind::Void => {
+                let Some(entry) = self.authorizations.get(&tx.id.0).copied() else {
+                    return false;
+                };
+                if account.locked || entry.client_id != client_id {
+                    return false;
+                }
+                account.reserved -= entry.amount;
+                match tx.kind {
+                    // captured authorization is an ordinary withdrawal from now on
+                    TxKind::Capture => _ = self.log.insert(tx.id.0, entry),
+                    _ => account.available += entry.amount,
+                }
+                self.authorizations.remove(&tx.id.0);
+                true
+            }
+            // model transactions are never scheduled, so there is nothing to cancel
+            TxKind::Cancel => false,
+        }
+    }
+}
+
+fn tx_kind() -> impl Strategy<Value = TxKind> {
+    prop_oneof![
+        4 => Just(TxKind::Deposit),
+        3 => Just(TxKind::Withdrawal),
+        2 => Just(TxKind::Dispute),
+        1 => Just(TxKind::Resolve),
+        1 => Just(TxKind::Chargeback),
+        2 => Just(TxKind::Authorize),
+        1 => Just(TxKind::Capture),
+        1 => Just(TxKind::Void),
+        1 => Just(TxKind::Cancel),
+        1 => Just(TxKind::Reversal),
+    ]
+}
+
+/// Mix of valid and invalid transactions: ids repeat, disputes may reference unknown
+/// or other client's transactions and some adjustments are missing their amount.
+/// Adjustment ids are partitioned by client, as two clients never share a transaction.
+/// Amounts have up to four decimal places, the precision engine keeps them with.
+fn transaction() -> impl Strategy<Value = TransactionDTO> {
+    (
+        0..5 as RawClientId,
+        prop::option::weighted(0.1, 0..5 as RawClientId),
+        0..40 as RawTransactionId,
+        tx_kind(),
+        prop::option::weighted(0.95, 1u32..5_000_000),
+    )
+        .prop_map(|(client_id, foreign_owner, id, kind, amount)| {
+            let owner = match kind {
+                TxKind::Deposit | TxKind::Withdrawal | TxKind::Authorize => client_id,
+                _ => foreign_owner.unwrap_or(client_id),
+            };
+            TransactionDTO::new(
+                RawTransactionId::from(owner) * 40 + id,
+                client_id,
+                kind,
+                amount.map(|units| f64::from(units) / 10_000.0),
+            )
+        })
+}
+
+fn transactions() -> impl Strategy<Value = Vec<TransactionDTO>> {
+    prop::collection::vec(transaction(), 0..200)
+}
+
+async fn run_with_shards(instance_count: u16, transactions: &[TransactionDTO]) -> Vec<String> {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    for tx in transactions {
+        sender.send(tx.clone()).unwrap();
+    }
+    drop(sender);
+    run_scaled(
+        instance_count,
+        receiver,
+        None,
+        ProcessorConfig::default(),
+        RouterConfig::default(),
+    )
+    .await
+    .accounts
+    .iter()
+    .map(|account| account.to_csv(true))
+    .collect()
+}
+
+proptest! {
+    #[test]
+    fn processor_agrees_with_model(transactions in transactions()) {
+        let mut processor = ProcessorImpl::new(0, ProcessorConfig::default());
+        let mut model = Model::default();
+
+        for tx in transactions {
+            let expected = model.apply(&tx);
+            let client_id = tx.client_id;
+            let accepted = processor.process(tx).is_ok();
+            prop_assert_eq!(accepted, expected);
+
+            let account = processor.account(&client_id).unwrap().unwrap();
+            let model_account = model.accounts[&client_id.0];
+            prop_assert_eq!(account.available.0, model_account.available);
+            prop_assert_eq!(account.held.0, model_account.held);
+            prop_assert_eq!(account.reserved.0, model_account.reserved);
+            prop_assert_eq!(account.locked, model_account.locked);
+        }
+    }
+
+    #[test]
+    fn invariants_hold_for_every_step(transactions in transactions()) {
+        let mut processor = ProcessorImpl::new(0, ProcessorConfig::default());
+        let mut locked = HashSet::new();
+
+        for tx in transactions {
+            let client_id = tx.client_id;
+            let before = processor.account(&client_id).unwrap().map(|account| account.to_csv(true));
+            _ = processor.process(tx);
+            let account = processor.account(&client_id).unwrap().unwrap();
+
+            prop_assert!(account.held.0 >= 0);
+            prop_assert!(account.reserved.0 >= 0);
+            if locked.contains(&client_id) {
+                prop_assert_eq!(Some(account.to_csv(true)), before);
+            }
+            if account.locked {
+                locked.insert(client_id);
+            }
+        }
+        // balances always follow from accepted transactions
+        prop_assert!(processor.reconcile().unwrap().is_empty());
+    }
+
+    #[test]
+    fn sharded_and_single_shard_results_are_identical(transactions in transactions()) {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let single = runtime.block_on(run_with_shards(1, &transactions));
+        let sharded = runtime.block_on(run_with_shards(3, &transactions));
+
+        prop_assert_eq!(single, sharded);
+    }
+}
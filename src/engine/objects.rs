@@ -1,29 +1,82 @@
-use std::ops::{AddAssign, Deref, SubAssign};
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Deref, Neg, Sub, SubAssign},
+    str::FromStr,
+};
 
-
-use super::EngineError;
+use super::{EngineError, batch::BatchId, clock::Timestamp};
 
 #[derive(Clone, Debug)]
 pub struct TransactionDTO {
     pub id: TransactionId,
     pub client_id: ClientId,
     pub kind: TxKind,
-    pub amount: Option<f32>,
+    pub amount: Option<TxAmount>,
+    /// Transaction is held until this time, applied right away when not set.
+    pub effective_at: Option<Timestamp>,
+    /// Position among transactions of the same client, counted from 1.
+    pub sequence_number: Option<u64>,
+    /// Consecutive transactions with the same batch id are applied all or none.
+    pub batch_id: Option<BatchId>,
 }
 
+impl TransactionDTO {
+    /// Transaction applied right away, outside of any sequence or batch.
+    /// Amount is rounded to four decimal places.
+    pub fn new(
+        id: RawTransactionId,
+        client_id: RawClientId,
+        kind: TxKind,
+        amount: Option<f64>,
+    ) -> Self {
+        Self {
+            id: TransactionId(id),
+            client_id: ClientId(client_id),
+            kind,
+            amount: amount.map(TxAmount::from_f64),
+            effective_at: None,
+            sequence_number: None,
+            batch_id: None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Adjustment {
     pub category: AdjustmentKind,
     pub details: TxDetails,
     pub amount: TxAmount,
+    pub fee: TxAmount, // charged on top of amount
 }
 
+#[derive(Clone)]
 pub struct DisputeClaim {
     pub client_id: ClientId,
     pub kind: AdjustmentKind,
     pub amount: TxAmount,
+    pub fee: TxAmount, // of disputed adjustment, refunded on chargeback
+}
+
+impl DisputeClaim {
+    /// Funds held while dispute is open, i.e. what the disputed deposit credited.
+    pub fn held(&self) -> TxAmount {
+        match self.kind {
+            AdjustmentKind::Deposit => self.amount - self.fee,
+            AdjustmentKind::Withdrawal => TxAmount::ZERO,
+        }
+    }
+}
+
+/// Funds reserved until they are captured (withdrawn) or released.
+#[derive(Clone)]
+pub struct Authorization {
+    pub client_id: ClientId,
+    pub amount: TxAmount,
+    pub authorized_at: Timestamp,
 }
 
-#[derive(Clone, Copy, Debug, strum::EnumString)]
+#[derive(Clone, Copy, Debug, PartialEq, strum::Display, strum::EnumString)]
 #[strum(serialize_all = "camelCase")]
 pub enum TxKind {
     Deposit,
@@ -31,49 +84,121 @@ pub enum TxKind {
     Dispute,
     Resolve,
     Chargeback,
+    Authorize,
+    Capture,
+    Void,
+    /// Drops scheduled transactions with the same id before they are applied.
+    Cancel,
+    /// Undoes deposit or withdrawal with the same id, which can't be disputed afterwards.
+    Reversal,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum AdjustmentKind {
     Deposit,
     Withdrawal,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ResolutionKind {
     Resolve,
     Chargeback,
 }
+#[derive(Clone)]
 pub struct TxDetails {
     pub id: TransactionId,
     pub client_id: ClientId,
 }
 
+// `wide-ids` feature widens identifiers to u64, at the cost of bigger state per transaction
+#[cfg(not(feature = "wide-ids"))]
+pub type RawTransactionId = u32;
+#[cfg(not(feature = "wide-ids"))]
+pub type RawClientId = u16;
+#[cfg(feature = "wide-ids")]
+pub type RawTransactionId = u64;
+#[cfg(feature = "wide-ids")]
+pub type RawClientId = u64;
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-pub struct TransactionId(pub u32);
+pub struct TransactionId(pub RawTransactionId);
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-pub struct ClientId(pub u16);
+pub struct ClientId(pub RawClientId);
 
-#[derive(Clone, Copy)]
-pub struct TxAmount(pub f32);
+/// Amount in ten-thousandths, the precision amounts are given with, so sums are exact
+/// regardless of magnitude or order.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct TxAmount(pub i64);
 
 impl Deref for ClientId {
-    type Target = u16;
+    type Target = RawClientId;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
+impl TransactionId {
+    #[allow(clippy::useless_conversion)] // ids are u64 already with `wide-ids`
+    pub fn to_u64(self) -> u64 {
+        u64::from(self.0)
+    }
+}
+
 impl Deref for TransactionId {
-    type Target = u32;
+    type Target = RawTransactionId;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
+// ten-thousandths in one unit of currency
+const AMOUNT_SCALE: i64 = 10_000;
+
+impl TxAmount {
+    pub const ZERO: Self = Self(0);
+
+    /// Rounds to the nearest ten-thousandth.
+    pub fn from_f64(value: f64) -> Self {
+        Self((value * AMOUNT_SCALE as f64).round() as i64)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / AMOUNT_SCALE as f64
+    }
+
+    /// `percent` percent of the amount, rounded to the nearest ten-thousandth.
+    pub fn percent(self, percent: f64) -> Self {
+        Self((self.0 as f64 * percent / 100.0).round() as i64)
+    }
+}
+
+impl Add for TxAmount {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(self.0 + rhs.0)
+    }
+}
+
+impl Sub for TxAmount {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0 - rhs.0)
+    }
+}
+
+impl Neg for TxAmount {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(-self.0)
+    }
+}
+
 impl AddAssign for TxAmount {
     fn add_assign(&mut self, rhs: Self) {
         self.0 += rhs.0
@@ -86,11 +211,57 @@ impl SubAssign for TxAmount {
     }
 }
 
-impl Deref for TxAmount {
-    type Target = f32;
+impl Sum for TxAmount {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::ZERO, Add::add)
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl fmt::Display for TxAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs();
+        let scale = AMOUNT_SCALE as u64;
+        write!(f, "{sign}{}.{:04}", units / scale, units % scale)
+    }
+}
+
+/// Parses decimal amounts like `1.5` or `-0.0001`. Digits past the fourth decimal place are
+/// rounded half away from zero.
+impl FromStr for TxAmount {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        const INVALID: &str = "invalid amount";
+        let value = value.trim();
+        let (negative, digits) = match value.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, value.strip_prefix('+').unwrap_or(value)),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty() && fraction.is_empty() || !all_digits(whole) || !all_digits(fraction) {
+            return Err(INVALID);
+        }
+
+        let mut units: i64 = 0;
+        for digit in whole
+            .bytes()
+            .chain(fraction.bytes().chain(std::iter::repeat(b'0')).take(4))
+        {
+            units = units
+                .checked_mul(10)
+                .and_then(|units| units.checked_add(i64::from(digit - b'0')))
+                .ok_or(INVALID)?;
+        }
+        if fraction
+            .as_bytes()
+            .get(4)
+            .is_some_and(|digit| *digit >= b'5')
+        {
+            units = units.checked_add(1).ok_or(INVALID)?;
+        }
+        Ok(Self(if negative { -units } else { units }))
     }
 }
 
@@ -98,17 +269,21 @@ impl TryFrom<TransactionDTO> for Adjustment {
     type Error = EngineError;
 
     fn try_from(value: TransactionDTO) -> Result<Self, Self::Error> {
+        let amount = value
+            .amount
+            .ok_or(EngineError::Parsing_MissingAmountFieldConstructingAdjustment)?;
+        // negative withdrawal would add funds past the risk limits
+        if amount <= TxAmount::ZERO {
+            return Err(EngineError::Parsing_NonPositiveAmount);
+        }
         Ok(Adjustment {
             category: value.kind.try_into()?,
             details: TxDetails {
                 id: value.id,
                 client_id: value.client_id,
             },
-            amount: TxAmount(
-                value
-                    .amount
-                    .ok_or(EngineError::Parsing_MissingAmountFieldConstructingAdjustment)?,
-            ),
+            amount,
+            fee: TxAmount::ZERO,
         })
     }
 }
@@ -136,3 +311,24 @@ impl TryFrom<TxKind> for ResolutionKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::TxAmount;
+
+    #[test]
+    fn amounts_read_and_print_with_four_decimal_places() {
+        assert_eq!("1.5".parse(), Ok(TxAmount(15_000)));
+        assert_eq!(" -0.0001 ".parse(), Ok(TxAmount(-1)));
+        assert_eq!(".25".parse(), Ok(TxAmount(2_500)));
+        assert_eq!("2.00005".parse(), Ok(TxAmount(20_001)));
+        assert_eq!("76322.64849".parse(), Ok(TxAmount(763_226_485)));
+        assert!("1.2.3".parse::<TxAmount>().is_err());
+        assert!("".parse::<TxAmount>().is_err());
+        assert!("1e3".parse::<TxAmount>().is_err());
+
+        assert_eq!(TxAmount(763_226_484).to_string(), "76322.6484");
+        assert_eq!(TxAmount(-1).to_string(), "-0.0001");
+        assert_eq!(TxAmount::from_f64(0.1 + 0.2), TxAmount(3_000));
+    }
+}
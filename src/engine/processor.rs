@@ -1,15 +1,51 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet, hash_map::Entry},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use tokio::{
-    sync::mpsc::{self, UnboundedReceiver},
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
     task::JoinHandle,
 };
 
-use crate::engine::objects::{ClientId, TransactionDTO, TransactionId, TxKind};
+use crate::engine::objects::{
+    ClientId, ResolutionKind, TransactionDTO, TransactionId, TxAmount, TxKind,
+};
 
 use super::{
     EngineError,
-    core::{account::Account, tx_resolver::TxResolver},
+    admin::{AdminCommand, AuditEvent, ShardDump},
+    batch::PositionedTransaction,
+    clock::{Clock, Timestamp},
+    core::{
+        account::Account,
+        ledger::Ledger,
+        reconciliation::{self, Discrepancy},
+        tx_log::TransactionLog,
+        tx_resolver::TxResolver,
+    },
+    fees::FeeSchedule,
+    jobs::{JobKind, JobSchedule, PeriodicJob},
+    replication::{self, Divergence, Replicated, ReplicationEvent},
+    resharding::{self, ClientState},
+    risk::{RiskLimits, RiskMonitor},
+    schedule::Schedule,
+    screening::{ReviewQueue, TransactionScreen, Verdict},
+    sequencing::{Released, SequenceGap, Sequencer, SequencingConfig},
+    statement::{self, HistoryEntry, HistoryEvent, Statement},
+    storage::{
+        AccountStore, AuditStore, CheckpointStore, HistoryStore, ScheduleStore, SequenceStore,
+        StorageConfig,
+        memory::{
+            InMemoryAccountStore, InMemoryAuditStore, InMemoryCheckpointStore,
+            InMemoryHistoryStore, InMemoryScheduleStore, InMemorySequenceStore,
+        },
+    },
 };
 
 pub type TransactionError = (TransactionId, Option<EngineError>);
@@ -20,149 +56,1253 @@ pub enum ProcessingResult {
     Error(EngineError),
 }
 
+pub enum ProcessorInput {
+    /// Transaction with its position (1-based ordinal) in input stream.
+    Transaction(TransactionDTO, u64),
+    Command(ProcessorCommand),
+}
+
+/// Requests handled between transactions, in order of arrival.
+pub enum ProcessorCommand {
+    Reconcile(oneshot::Sender<Result<Vec<Discrepancy>, EngineError>>),
+    /// Persist state together with input position it covers.
+    Checkpoint(u64),
+    LastCheckpoint(oneshot::Sender<Result<Option<u64>, EngineError>>),
+    /// Records fingerprint of input with number of processors running, failing when
+    /// checkpoints were made over a different input or with a different number of processors.
+    ClaimInput(String, u16, oneshot::Sender<Result<(), EngineError>>),
+    /// Records number of processors running after resharding.
+    Resharded(u16),
+    /// Transactions held for manual review, left in the queue.
+    PendingReview(oneshot::Sender<Vec<TransactionDTO>>),
+    /// Processes transaction held for review, skipping the screen.
+    ApproveReview(TransactionId, oneshot::Sender<Result<(), EngineError>>),
+    /// Drops transaction held for review without processing it.
+    RejectReview(TransactionId, oneshot::Sender<Result<(), EngineError>>),
+    /// Takes every transaction out of the review queue without processing them.
+    DrainReviewQueue(oneshot::Sender<Vec<TransactionDTO>>),
+    /// Sequence numbers skipped so far, because they didn't arrive in time.
+    SequenceGaps(oneshot::Sender<Vec<SequenceGap>>),
+    /// Validates batch members of this processor without applying them, replying with
+    /// outcome of every member. Nothing else may be processed until commit or abort.
+    PrepareBatch(
+        Vec<PositionedTransaction>,
+        oneshot::Sender<Vec<TransactionError>>,
+    ),
+    /// Applies prepared batch members.
+    CommitBatch(oneshot::Sender<Vec<TransactionError>>),
+    /// Drops prepared batch members.
+    AbortBatch,
+    /// Executes admin command, replying with dump for `AdminCommand::DumpShard`.
+    Admin(
+        AdminCommand,
+        oneshot::Sender<Result<Option<ShardDump>, EngineError>>,
+    ),
+    /// Takes clients which no longer belong to this processor, with `shards` processors
+    /// running, out of it.
+    MigrateOut(
+        usize,
+        oneshot::Sender<Result<Vec<ClientState>, EngineError>>,
+    ),
+    /// Takes over clients migrated out of other processors.
+    MigrateIn(Vec<ClientState>, oneshot::Sender<Result<(), EngineError>>),
+}
+
+/// Checkpoint interval used with persistent storage when none is configured,
+/// so its open database transaction is committed regularly.
+pub const DEFAULT_PERSISTENT_CHECKPOINT_INTERVAL: u64 = 10_000;
+
+#[derive(Clone, Debug, Default)]
+pub struct ProcessorConfig {
+    /// Post every operation to a double-entry ledger and check the books when input ends.
+    pub double_entry: bool,
+    /// Bound transaction log kept in memory, moving older entries to disk.
+    pub tiered_log: Option<TieredLogConfig>,
+    /// Where accounts, transaction log and disputes are kept.
+    pub storage: StorageConfig,
+    /// Checkpoint state every this many input transactions, 0 checkpoints only when input ends
+    /// (or every [`DEFAULT_PERSISTENT_CHECKPOINT_INTERVAL`] with persistent storage).
+    pub checkpoint_interval: u64,
+    /// Fees charged on adjustments and chargebacks, none by default.
+    pub fees: FeeSchedule,
+    /// Limits checked before withdrawals, none by default.
+    pub risk_limits: RiskLimits,
+    /// Time source for risk limit windows and authorization expiry.
+    pub clock: Clock,
+    /// Release funds of authorizations neither captured nor voided within this many seconds.
+    pub authorization_expiry: Option<u64>,
+    /// Custom screen deciding whether transactions are processed, rejected or held for review.
+    pub screen: Option<Arc<dyn TransactionScreen>>,
+    /// How long and how many transactions to wait for a missing sequence number.
+    pub sequencing: SequencingConfig,
+    /// Jobs run over every account periodically, e.g. interest accrual.
+    pub jobs: JobSchedule,
+    /// Record every balance change, so statements can be generated.
+    pub history: bool,
+    /// Stream everything processor takes in, with time of it, so a standby can follow.
+    pub replica: Option<UnboundedSender<Replicated>>,
+}
+
+impl ProcessorConfig {
+    /// Input transactions between checkpoints, 0 when only the final one is made.
+    pub fn effective_checkpoint_interval(&self) -> u64 {
+        match self.checkpoint_interval {
+            0 if self.storage.is_persistent() => DEFAULT_PERSISTENT_CHECKPOINT_INTERVAL,
+            interval => interval,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TieredLogConfig {
+    /// Directory for cold tier files, one per processor.
+    pub directory: PathBuf,
+    /// Number of most recent adjustments kept in memory.
+    pub hot_capacity: usize,
+}
+
+// stores of a processor not owned by its resolver
+struct ShardStores {
+    accounts: Box<dyn AccountStore>,
+    schedule: Box<dyn ScheduleStore>,
+    sequences: Box<dyn SequenceStore>,
+    history: Box<dyn HistoryStore>,
+    audit: Box<dyn AuditStore>,
+    checkpoints: Box<dyn CheckpointStore>,
+}
+
 pub struct ProcessorImpl {
-    accounts: HashMap<ClientId, Account>,
+    accounts: Box<dyn AccountStore>,
     resolver: TxResolver,
-    #[allow(dead_code)]
+    checkpoints: Box<dyn CheckpointStore>,
+    // transactions up to this input position were applied before restart
+    applied_up_to: u64,
+    screen: Option<Arc<dyn TransactionScreen>>,
+    review_queue: ReviewQueue,
+    schedule: Schedule,
+    clock: Clock,
+    authorization_expiry: Option<u64>,
+    // stale authorizations are looked for at most once a second
+    last_expiry_sweep: Timestamp,
+    sequencer: Sequencer,
+    // results of transactions released by the sequencer after their own input was acknowledged
+    deferred: Vec<TransactionError>,
+    sequence_gaps: Vec<SequenceGap>,
+    // batch members validated and waiting for commit
+    prepared_batch: Vec<TransactionDTO>,
+    // periodic jobs with time of their next run
+    jobs: Vec<(PeriodicJob, Timestamp)>,
+    // balance changes, when history is kept
+    history: Option<Box<dyn HistoryStore>>,
+    audit: Box<dyn AuditStore>,
+    replica: Option<UnboundedSender<Replicated>>,
     instance_id: u16,
 }
 
 impl ProcessorImpl {
     pub fn run(
-        mut rx: UnboundedReceiver<TransactionDTO>,
+        mut rx: UnboundedReceiver<ProcessorInput>,
         instance_id: u16,
-    ) -> (UnboundedReceiver<TransactionError>, JoinHandle<()>) {
+        config: ProcessorConfig,
+    ) -> (
+        UnboundedReceiver<TransactionError>,
+        JoinHandle<Vec<Account>>,
+    ) {
         let (sender, receiver) = mpsc::unbounded_channel::<TransactionError>();
         let handle = tokio::spawn(async move {
-            let mut processor = Self {
-                accounts: Default::default(),
-                resolver: TxResolver::new(),
-                instance_id,
-            };
+            let started_at = config.clock.now();
+            let mut processor = Self::new(instance_id, config);
+            processor.replicate(started_at, || ReplicationEvent::Start);
 
-            while let Some(transaction) = rx.recv().await {
-                let tx_id = transaction.id;
-                let result = processor.process(transaction).err();
-                _ = sender.send((tx_id, result));
+            loop {
+                // scheduled transactions are applied once their time comes, even without input
+                let input = match processor.next_due_in() {
+                    Some(delay) => tokio::select! {
+                        input = rx.recv() => input,
+                        _ = tokio::time::sleep(delay) => {
+                            let at = processor.clock.now();
+                            for result in processor.apply_due() {
+                                _ = sender.send(result);
+                            }
+                            processor.replicate(at, || ReplicationEvent::Tick);
+                            continue;
+                        }
+                    },
+                    None => rx.recv().await,
+                };
+                let Some(input) = input else {
+                    break;
+                };
+                match input {
+                    ProcessorInput::Transaction(transaction, position) => {
+                        let tx_id = transaction.id;
+                        let at = processor.clock.now();
+                        let replicated = processor.replica.is_some().then(|| transaction.clone());
+                        let result = processor.process_at(transaction, position).err();
+                        if let Some(tx) = replicated {
+                            let accepted = result.is_none();
+                            processor.replicate(at, || ReplicationEvent::Transaction {
+                                tx,
+                                position,
+                                accepted,
+                            });
+                        }
+                        _ = sender.send((tx_id, result));
+                        for result in processor.deferred.drain(..) {
+                            _ = sender.send(result);
+                        }
+                    }
+                    ProcessorInput::Command(command) => processor.execute(command),
+                }
+            }
+            let at = processor.clock.now();
+            let released = processor.finish();
+            processor.replicate(at, || ReplicationEvent::Finish);
+            processor.replicate_balances(at);
+            for result in released {
+                _ = sender.send(result);
+            }
+            if !processor.schedule.is_empty() {
+                eprintln!(
+                    "processor {instance_id}: {} scheduled transactions still pending",
+                    processor.schedule.len()
+                );
             }
-            processor.print_account_balances_to_stdout();
+            processor.print_ledger_check_to_stderr();
+            processor.accounts().unwrap_or_else(|e| {
+                eprintln!("processor {instance_id}: reading accounts failed: {e:?}");
+                Vec::new()
+            })
         });
 
         (receiver, handle)
     }
 
-    fn print_account_balances_to_stdout(&self) {
-        for account in self.accounts.values() {
-            println!("{}", account.to_csv());
+    /// Processes sequenced transactions still buffered, as missing sequence numbers
+    /// can't arrive anymore once input ends.
+    pub fn finish(&mut self) -> Vec<TransactionError> {
+        let released = self.release_all_sequenced();
+        if !released.is_empty() {
+            // final checkpoint was taken before, it has to cover released transactions too
+            if let Ok(Some(position)) = self.last_checkpoint()
+                && let Err(e) = self.checkpoint(position)
+            {
+                eprintln!("processor {}: checkpoint failed: {e:?}", self.instance_id);
+            }
+        }
+        released
+    }
+
+    pub fn new(instance_id: u16, config: ProcessorConfig) -> Self {
+        let mut resolver = TxResolver::new()
+            .with_fees(config.fees.clone())
+            .with_clock(config.clock.clone())
+            .with_risk_limits(config.risk_limits.clone());
+        if let Some(tiered) = &config.tiered_log {
+            let path = tiered.directory.join(format!("shard-{instance_id}.log"));
+            let log = TransactionLog::tiered(&path, tiered.hot_capacity)
+                .expect("can't create transaction log file");
+            resolver = resolver.with_transaction_log(Box::new(log));
+        }
+        let stores = match &config.storage {
+            StorageConfig::Memory => ShardStores {
+                accounts: Box::new(InMemoryAccountStore::default()),
+                schedule: Box::new(InMemoryScheduleStore::default()),
+                sequences: Box::new(InMemorySequenceStore::default()),
+                history: Box::new(InMemoryHistoryStore::default()),
+                audit: Box::new(InMemoryAuditStore::default()),
+                checkpoints: Box::new(InMemoryCheckpointStore::default()),
+            },
+            #[cfg(feature = "sqlite")]
+            StorageConfig::Sqlite { directory } => {
+                let storage =
+                    super::storage::sqlite::SqliteStorage::open_shard(directory, instance_id)
+                        .expect("can't open processor database");
+                resolver = resolver
+                    .with_transaction_log(Box::new(storage.transaction_log()))
+                    .with_dispute_store(Box::new(storage.dispute_store()))
+                    .with_authorization_store(Box::new(storage.authorization_store()))
+                    .with_job_store(Box::new(storage.job_store()));
+                ShardStores {
+                    accounts: Box::new(storage.account_store()),
+                    schedule: Box::new(storage.schedule_store()),
+                    sequences: Box::new(storage.sequence_store()),
+                    history: Box::new(storage.history_store()),
+                    audit: Box::new(storage.audit_store()),
+                    checkpoints: Box::new(storage.checkpoint_store()),
+                }
+            }
+        };
+        let ShardStores {
+            accounts,
+            schedule,
+            sequences,
+            history,
+            audit,
+            checkpoints,
+        } = stores;
+        if config.double_entry {
+            let accounts = accounts.all().expect("can't read accounts");
+            resolver = resolver.with_ledger(Ledger::opening(&accounts));
+        }
+        let applied_up_to = checkpoints
+            .last_checkpoint()
+            .expect("can't read processor checkpoint")
+            .unwrap_or(0);
+        let sequencer = Sequencer::new(sequences, config.sequencing, config.clock.now())
+            .expect("can't read buffered transactions");
+        // job never run before is first run one period after now
+        let jobs = config
+            .jobs
+            .jobs
+            .iter()
+            .map(|job| {
+                let last_run = match resolver.job_last_run(job.name()) {
+                    Ok(Some(last_run)) => last_run,
+                    Ok(None) => {
+                        let now = config.clock.now();
+                        resolver
+                            .set_job_last_run(job.name(), now)
+                            .expect("can't record job run");
+                        now
+                    }
+                    Err(e) => panic!("can't read job runs: {e:?}"),
+                };
+                (*job, job.next_run(last_run))
+            })
+            .collect();
+
+        Self {
+            accounts,
+            resolver,
+            checkpoints,
+            applied_up_to,
+            screen: config.screen,
+            review_queue: ReviewQueue::default(),
+            schedule: Schedule::new(schedule).expect("can't read scheduled transactions"),
+            clock: config.clock,
+            authorization_expiry: config.authorization_expiry,
+            last_expiry_sweep: 0,
+            sequencer,
+            deferred: Vec::new(),
+            sequence_gaps: Vec::new(),
+            prepared_batch: Vec::new(),
+            jobs,
+            history: config.history.then_some(history),
+            audit,
+            replica: config.replica,
+            instance_id,
+        }
+    }
+
+    pub fn account(&self, client_id: &ClientId) -> Result<Option<Account>, EngineError> {
+        self.accounts.load(client_id)
+    }
+
+    pub fn accounts(&self) -> Result<Vec<Account>, EngineError> {
+        self.accounts.all()
+    }
+
+    pub fn reconcile(&self) -> Result<Vec<Discrepancy>, EngineError> {
+        reconciliation::reconcile(&self.resolver, &self.accounts()?)
+    }
+
+    pub fn last_checkpoint(&self) -> Result<Option<u64>, EngineError> {
+        self.checkpoints.last_checkpoint()
+    }
+
+    pub fn checkpoint(&mut self, position: u64) -> Result<(), EngineError> {
+        self.checkpoints.checkpoint(position)
+    }
+
+    /// Records `fingerprint` of input with number of processors running, unless checkpoints
+    /// were made over a different input or with a different number of processors, whose
+    /// clients would be spread differently.
+    pub fn claim_input(&mut self, fingerprint: &str, shards: u16) -> Result<(), EngineError> {
+        if self.last_checkpoint()?.is_some() {
+            if self
+                .checkpoints
+                .input()?
+                .is_some_and(|input| input != fingerprint)
+            {
+                return Err(EngineError::Checkpoint_DifferentInput);
+            }
+            if self
+                .checkpoints
+                .shards()?
+                .is_some_and(|stored| stored != shards)
+            {
+                return Err(EngineError::Checkpoint_DifferentShards);
+            }
         }
+        self.checkpoints.set_input(fingerprint)?;
+        self.checkpoints.set_shards(shards)
     }
 
-    fn process(&mut self, tx: TransactionDTO) -> Result<(), EngineError> {
-        // println!("Processing transaction on processor: {}", self.instance_id);
-        let account = self
+    /// Statements for period `[from, to)` of `client_id`, or of every client with history.
+    /// Empty unless history is kept.
+    pub fn statements(
+        &self,
+        client_id: Option<&ClientId>,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Result<Vec<Statement>, EngineError> {
+        match &self.history {
+            Some(history) => Ok(statement::statements(history.entries(client_id)?, from, to)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Executes admin command and records it, with its outcome, in the audit log.
+    /// Returns state of the processor for `AdminCommand::DumpShard`, nothing for other commands.
+    pub fn administer(&mut self, command: AdminCommand) -> Result<Option<ShardDump>, EngineError> {
+        let result = match &command {
+            AdminCommand::CorrectBalance {
+                client_id, amount, ..
+            } => self
+                .change_account(
+                    *client_id,
+                    None,
+                    HistoryEvent::Correction,
+                    |resolver, account| resolver.correct_balance(account, *amount),
+                )
+                .map(|()| None),
+            AdminCommand::ForceCloseDispute {
+                client_id,
+                tx_id,
+                resolution,
+                ..
+            } => {
+                let kind = match resolution {
+                    ResolutionKind::Resolve => TxKind::Resolve,
+                    ResolutionKind::Chargeback => TxKind::Chargeback,
+                };
+                self.change_account(
+                    *client_id,
+                    Some(*tx_id),
+                    HistoryEvent::Transaction(kind),
+                    |resolver, account| resolver.force_close_dispute(*tx_id, *resolution, account),
+                )
+                .map(|()| None)
+            }
+            AdminCommand::Freeze { client_id, .. } | AdminCommand::Unfreeze { client_id, .. } => {
+                let (locked, event) = match command {
+                    AdminCommand::Freeze { .. } => (true, HistoryEvent::Freeze),
+                    _ => (false, HistoryEvent::Unfreeze),
+                };
+                self.change_account(*client_id, None, event, |_, account| {
+                    account.locked = locked;
+                    Ok(())
+                })
+                .map(|()| None)
+            }
+            AdminCommand::ApproveReview {
+                client_id, tx_id, ..
+            } => self
+                .review_queue
+                .take_of(client_id, tx_id)
+                .and_then(|tx| self.apply(tx))
+                .map(|()| None),
+            AdminCommand::RejectReview {
+                client_id, tx_id, ..
+            } => self.review_queue.take_of(client_id, tx_id).map(|_| None),
+            AdminCommand::PendingReview { .. } => {
+                self.print_under_review(&self.review_queue.pending());
+                Ok(None)
+            }
+            AdminCommand::DrainReviewQueue { .. } => {
+                let drained = self.review_queue.drain();
+                self.print_under_review(&drained);
+                Ok(None)
+            }
+            AdminCommand::DumpShard { .. } => self.dump().map(Some),
+            AdminCommand::Reshard { .. } => Err(EngineError::Admin_NotForProcessor),
+        };
+        let outcome = match &result {
+            Ok(_) => "ok".to_string(),
+            Err(e) => format!("{e:?}"),
+        };
+        eprintln!(
+            "processor {}: admin `{command}` {outcome}",
+            self.instance_id
+        );
+        self.audit.record(AuditEvent {
+            at: self.clock.now(),
+            command: command.to_string(),
+            outcome,
+        })?;
+        result
+    }
+
+    fn print_under_review(&self, transactions: &[TransactionDTO]) {
+        for tx in transactions {
+            eprintln!(
+                "processor {}: under review {} client {} tx {} amount {}",
+                self.instance_id,
+                tx.kind,
+                *tx.client_id,
+                tx.id.to_u64(),
+                tx.amount.map(|a| a.to_string()).unwrap_or_default()
+            );
+        }
+    }
+
+    pub fn audit_log(&self) -> Result<Vec<AuditEvent>, EngineError> {
+        self.audit.events()
+    }
+
+    // applies admin change to account of `client_id`, recording it in history
+    fn change_account(
+        &mut self,
+        client_id: ClientId,
+        tx_id: Option<TransactionId>,
+        event: HistoryEvent,
+        change: impl FnOnce(&mut TxResolver, &mut Account) -> Result<(), EngineError>,
+    ) -> Result<(), EngineError> {
+        let mut account = self
             .accounts
-            .entry(tx.client_id)
-            .or_insert(Account::new(tx.client_id));
+            .load(&client_id)?
+            .unwrap_or_else(|| Account::new(client_id));
+        let before = account.clone();
+        change(&mut self.resolver, &mut account)?;
+        self.accounts.save(&account)?;
+        self.record_history(&before, &account, self.clock.now(), tx_id, event, None)
+    }
 
-        match tx.kind {
-            TxKind::Deposit | TxKind::Withdrawal => self.resolver.apply_adjustment(tx, account),
-            TxKind::Dispute => self.resolver.open_dispute(&tx.id, account),
-            TxKind::Resolve | TxKind::Chargeback => self.resolver.close_dispute(tx, account),
+    fn dump(&self) -> Result<ShardDump, EngineError> {
+        let mut accounts = self.accounts()?;
+        accounts.sort_by_key(|account| account.client_id.0);
+        Ok(ShardDump {
+            shard: self.instance_id,
+            last_checkpoint: self.last_checkpoint()?,
+            accounts,
+            disputes: self.resolver.active_disputes()?,
+            authorizations: self.resolver.active_authorizations()?,
+            scheduled: self.schedule.len(),
+            under_review: self.review_queue.pending().len(),
+        })
+    }
+
+    /// Takes everything kept about clients which belong to another processor, with `shards`
+    /// processors running, out of this one.
+    /// On failure, clients stay with this processor.
+    pub fn migrate_out(&mut self, shards: usize) -> Result<Vec<ClientState>, EngineError> {
+        let instance_id = self.instance_id as usize;
+        let accounts = self.accounts()?;
+        let under_review = self.review_queue.pending();
+        let mut states: HashMap<ClientId, ClientState> = accounts
+            .iter()
+            .map(|account| account.client_id)
+            .chain(self.schedule.clients())
+            .chain(self.sequencer.clients())
+            .chain(under_review.iter().map(|tx| tx.client_id))
+            .filter(|client_id| resharding::shard_of(client_id, shards) != instance_id)
+            .map(|client_id| (client_id, ClientState::new(client_id)))
+            .collect();
+        match self.take_clients(&mut states) {
+            Ok(()) => Ok(states.into_values().collect()),
+            Err(e) => {
+                // whatever was taken out of clients is put back
+                if let Err(e) = self.migrate_in(states.into_values().collect()) {
+                    eprintln!(
+                        "processor {}: restoring clients failed: {e:?}",
+                        self.instance_id
+                    );
+                }
+                Err(e)
+            }
         }
     }
+
+    fn take_clients(
+        &mut self,
+        states: &mut HashMap<ClientId, ClientState>,
+    ) -> Result<(), EngineError> {
+        self.resolver.take_clients(states)?;
+        for (client_id, state) in states.iter_mut() {
+            state.account = self.accounts.remove(client_id)?;
+            state.scheduled = self.schedule.take_client(client_id)?;
+            (state.next_sequence_number, state.sequenced) =
+                self.sequencer.take_client(client_id)?;
+            state.under_review = self.review_queue.take_client(client_id);
+            if let Some(history) = &mut self.history {
+                state.history = history.entries(Some(client_id))?;
+                history.remove_client(client_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes over clients migrated out of other processors.
+    pub fn migrate_in(&mut self, states: Vec<ClientState>) -> Result<(), EngineError> {
+        for mut state in states {
+            let client_id = state.client_id;
+            if let Some(account) = &state.account {
+                self.accounts.save(account)?;
+            }
+            self.resolver.restore_client(&mut state)?;
+            for tx in state.scheduled {
+                self.schedule.schedule(tx)?;
+            }
+            self.sequencer.restore_client(
+                client_id,
+                state.next_sequence_number,
+                state.sequenced,
+                self.clock.now(),
+            )?;
+            for tx in state.under_review {
+                self.review_queue.push(tx);
+            }
+            if let Some(history) = &mut self.history {
+                for entry in state.history {
+                    history.record(entry)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record_history(
+        &mut self,
+        before: &Account,
+        after: &Account,
+        at: Timestamp,
+        tx_id: Option<TransactionId>,
+        event: HistoryEvent,
+        amount: Option<TxAmount>,
+    ) -> Result<(), EngineError> {
+        let Some(history) = &mut self.history else {
+            return Ok(());
+        };
+        let entry = HistoryEntry::between(before, after, at, tx_id, event, amount);
+        // periodic jobs go over every account, only those they changed are recorded
+        let periodic = matches!(event, HistoryEvent::Interest | HistoryEvent::MaintenanceFee);
+        match periodic && !entry.changes_balances() {
+            true => Ok(()),
+            false => history.record(entry),
+        }
+    }
+
+    fn execute(&mut self, command: ProcessorCommand) {
+        let at = self.clock.now();
+        let replicated = match &command {
+            _ if self.replica.is_none() => None,
+            ProcessorCommand::ApproveReview(tx_id, _) => {
+                Some(ReplicationEvent::ApproveReview(*tx_id))
+            }
+            ProcessorCommand::RejectReview(tx_id, _) => {
+                Some(ReplicationEvent::RejectReview(*tx_id))
+            }
+            ProcessorCommand::DrainReviewQueue(_) => Some(ReplicationEvent::DrainReviewQueue),
+            ProcessorCommand::PrepareBatch(members, _) => {
+                Some(ReplicationEvent::PrepareBatch(members.clone()))
+            }
+            ProcessorCommand::CommitBatch(_) => Some(ReplicationEvent::CommitBatch),
+            ProcessorCommand::AbortBatch => Some(ReplicationEvent::AbortBatch),
+            ProcessorCommand::Admin(command, _) => Some(ReplicationEvent::Admin(command.clone())),
+            ProcessorCommand::Checkpoint(position) => Some(ReplicationEvent::Checkpoint(*position)),
+            ProcessorCommand::ClaimInput(fingerprint, shards, _) => {
+                Some(ReplicationEvent::Input(fingerprint.clone(), *shards))
+            }
+            // reading state doesn't change it, resharding is rejected while replicating
+            _ => None,
+        };
+        let checkpointed = matches!(command, ProcessorCommand::Checkpoint(_));
+        match command {
+            ProcessorCommand::Reconcile(reply) => {
+                _ = reply.send(self.reconcile());
+            }
+            ProcessorCommand::Checkpoint(position) => {
+                if let Err(e) = self.checkpoint(position) {
+                    eprintln!("processor {}: checkpoint failed: {e:?}", self.instance_id);
+                }
+            }
+            ProcessorCommand::LastCheckpoint(reply) => {
+                _ = reply.send(self.last_checkpoint());
+            }
+            ProcessorCommand::ClaimInput(fingerprint, shards, reply) => {
+                _ = reply.send(self.claim_input(&fingerprint, shards));
+            }
+            ProcessorCommand::Resharded(shards) => {
+                if let Err(e) = self.checkpoints.set_shards(shards) {
+                    eprintln!(
+                        "processor {}: recording number of processors failed: {e:?}",
+                        self.instance_id
+                    );
+                }
+            }
+            ProcessorCommand::PendingReview(reply) => {
+                _ = reply.send(self.review_queue.pending());
+            }
+            ProcessorCommand::ApproveReview(tx_id, reply) => {
+                let result = self.review_queue.take(&tx_id).and_then(|tx| self.apply(tx));
+                _ = reply.send(result);
+            }
+            ProcessorCommand::RejectReview(tx_id, reply) => {
+                _ = reply.send(self.review_queue.take(&tx_id).map(|_| ()));
+            }
+            ProcessorCommand::DrainReviewQueue(reply) => {
+                _ = reply.send(self.review_queue.drain());
+            }
+            ProcessorCommand::SequenceGaps(reply) => {
+                _ = reply.send(self.sequence_gaps.clone());
+            }
+            ProcessorCommand::PrepareBatch(members, reply) => {
+                _ = reply.send(self.prepare_batch(members));
+            }
+            ProcessorCommand::CommitBatch(reply) => {
+                _ = reply.send(self.commit_batch());
+            }
+            ProcessorCommand::AbortBatch => self.prepared_batch.clear(),
+            ProcessorCommand::Admin(command, reply) => {
+                _ = reply.send(self.administer(command));
+            }
+            ProcessorCommand::MigrateOut(shards, reply) => {
+                _ = reply.send(self.migrate_out(shards));
+            }
+            ProcessorCommand::MigrateIn(states, reply) => {
+                _ = reply.send(self.migrate_in(states));
+            }
+        }
+        if let Some(event) = replicated {
+            self.replicate(at, || event);
+            if checkpointed {
+                self.replicate_balances(at);
+            }
+        }
+    }
+
+    fn replicate(&self, at: Timestamp, event: impl FnOnce() -> ReplicationEvent) {
+        if let Some(replica) = &self.replica {
+            _ = replica.send(Replicated {
+                shard: self.instance_id,
+                at,
+                event: event(),
+            });
+        }
+    }
+
+    fn replicate_balances(&self, at: Timestamp) {
+        if self.replica.is_some() {
+            match self.accounts() {
+                Ok(accounts) => self.replicate(at, || ReplicationEvent::Balances(accounts)),
+                Err(e) => eprintln!(
+                    "processor {}: reading accounts failed: {e:?}",
+                    self.instance_id
+                ),
+            }
+        }
+    }
+
+    /// Takes in event of the primary this processor stands by for, at the time it
+    /// happened there. Returns where this processor disagrees with the primary.
+    pub fn follow(&mut self, at: Timestamp, event: ReplicationEvent) -> Vec<Divergence> {
+        self.clock.set(at);
+        match event {
+            // standby starts its processor over
+            ReplicationEvent::Start => {}
+            ReplicationEvent::Transaction {
+                tx,
+                position,
+                accepted,
+            } => {
+                let tx_id = tx.id;
+                let outcome = self.process_at(tx, position);
+                // results of released transactions are only reported by the primary
+                self.deferred.clear();
+                if outcome.is_ok() != accepted {
+                    return vec![Divergence::Outcome {
+                        tx_id,
+                        accepted_by_primary: accepted,
+                    }];
+                }
+            }
+            ReplicationEvent::Tick => {
+                self.apply_due();
+            }
+            ReplicationEvent::ApproveReview(tx_id) => {
+                self.execute(ProcessorCommand::ApproveReview(tx_id, oneshot::channel().0))
+            }
+            ReplicationEvent::RejectReview(tx_id) => {
+                self.execute(ProcessorCommand::RejectReview(tx_id, oneshot::channel().0))
+            }
+            ReplicationEvent::DrainReviewQueue => {
+                self.execute(ProcessorCommand::DrainReviewQueue(oneshot::channel().0))
+            }
+            ReplicationEvent::PrepareBatch(members) => self.execute(
+                ProcessorCommand::PrepareBatch(members, oneshot::channel().0),
+            ),
+            ReplicationEvent::CommitBatch => {
+                self.execute(ProcessorCommand::CommitBatch(oneshot::channel().0))
+            }
+            ReplicationEvent::AbortBatch => self.execute(ProcessorCommand::AbortBatch),
+            ReplicationEvent::Admin(command) => {
+                self.execute(ProcessorCommand::Admin(command, oneshot::channel().0))
+            }
+            ReplicationEvent::Checkpoint(position) => {
+                self.execute(ProcessorCommand::Checkpoint(position))
+            }
+            ReplicationEvent::Input(fingerprint, shards) => self.execute(
+                ProcessorCommand::ClaimInput(fingerprint, shards, oneshot::channel().0),
+            ),
+            ReplicationEvent::Finish => {
+                self.finish();
+            }
+            ReplicationEvent::Balances(accounts) => {
+                return replication::compare_balances(
+                    accounts,
+                    self.accounts().unwrap_or_default(),
+                );
+            }
+        }
+        Vec::new()
+    }
+
+    /// Returns results of transactions released by the sequencer since last call, then
+    /// runs periodic jobs and processes scheduled transactions whose time has come, in order
+    /// of their effective time, and sequenced transactions whose missing predecessors timed out.
+    pub fn apply_due(&mut self) -> Vec<TransactionError> {
+        let mut results = std::mem::take(&mut self.deferred);
+        if let Err(e) = self.run_jobs() {
+            eprintln!("processor {}: periodic job failed: {e:?}", self.instance_id);
+        }
+        loop {
+            match self.schedule.take_due(self.clock.now()) {
+                Ok(Some(tx)) => {
+                    let tx_id = tx.id;
+                    results.push((tx_id, self.process_in_order(tx).err()));
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!(
+                        "processor {}: reading schedule failed: {e:?}",
+                        self.instance_id
+                    );
+                    break;
+                }
+            }
+        }
+        match self.sequencer.release_stale(self.clock.now()) {
+            Ok(released) => results.extend(self.process_released(released)),
+            Err(e) => eprintln!(
+                "processor {}: releasing sequenced transactions failed: {e:?}",
+                self.instance_id
+            ),
+        }
+        results
+    }
+
+    /// Processes every buffered sequenced transaction, skipping whatever is still missing.
+    pub fn release_all_sequenced(&mut self) -> Vec<TransactionError> {
+        let mut results = std::mem::take(&mut self.deferred);
+        match self.sequencer.release_all() {
+            Ok(released) => results.extend(self.process_released(released)),
+            Err(e) => eprintln!(
+                "processor {}: releasing sequenced transactions failed: {e:?}",
+                self.instance_id
+            ),
+        }
+        results
+    }
+
+    /// Time left until the earliest scheduled transaction or periodic job is due,
+    /// or a sequence gap times out.
+    pub fn next_due_in(&self) -> Option<Duration> {
+        let next_job = self.jobs.iter().map(|(_, next_run)| *next_run).min();
+        [
+            self.schedule.next_due(),
+            self.sequencer.next_deadline(),
+            next_job,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .map(|at| self.clock.until(at))
+    }
+
+    /// Runs periodic jobs whose time has come, once for every period that has passed.
+    pub fn run_jobs(&mut self) -> Result<(), EngineError> {
+        let now = self.clock.now();
+        for index in 0..self.jobs.len() {
+            loop {
+                let (job, run_at) = self.jobs[index];
+                if run_at > now {
+                    break;
+                }
+                self.run_job(job, run_at)?;
+                self.resolver.set_job_last_run(job.name(), run_at)?;
+                self.jobs[index].1 = job.next_run(run_at);
+            }
+        }
+        Ok(())
+    }
+
+    fn run_job(&mut self, job: PeriodicJob, at: Timestamp) -> Result<(), EngineError> {
+        for mut account in self.accounts.all()? {
+            let before = account.clone();
+            match job.kind {
+                JobKind::Interest { rate } => {
+                    self.resolver.accrue_interest(&mut account, rate)?;
+                    self.record_history(&before, &account, at, None, HistoryEvent::Interest, None)?;
+                }
+                JobKind::MaintenanceFee { amount } => {
+                    self.resolver.charge_maintenance_fee(&mut account, amount)?;
+                    self.record_history(
+                        &before,
+                        &account,
+                        at,
+                        None,
+                        HistoryEvent::MaintenanceFee,
+                        None,
+                    )?;
+                }
+                JobKind::Dormancy { after } => {
+                    if account.dormant || at.saturating_sub(account.last_activity) < after {
+                        continue;
+                    }
+                    account.dormant = true;
+                    eprintln!(
+                        "processor {}: client {} flagged dormant",
+                        self.instance_id, *account.client_id
+                    );
+                }
+            }
+            self.accounts.save(&account)?;
+        }
+        Ok(())
+    }
+
+    /// Sequence numbers skipped so far.
+    pub fn sequence_gaps(&self) -> &[SequenceGap] {
+        &self.sequence_gaps
+    }
+
+    fn process_released(&mut self, released: Released) -> Vec<TransactionError> {
+        for gap in &released.gaps {
+            eprintln!(
+                "processor {}: client {} missing sequence numbers {}..{}",
+                self.instance_id, *gap.client_id, gap.first_missing, gap.last_missing
+            );
+        }
+        self.sequence_gaps.extend(released.gaps);
+        released
+            .ready
+            .into_iter()
+            .map(|tx| (tx.id, self.process_in_order(tx).err()))
+            .collect()
+    }
+
+    /// Releases funds of authorizations older than configured expiry.
+    pub fn expire_authorizations(&mut self) -> Result<(), EngineError> {
+        let Some(expiry) = self.authorization_expiry else {
+            return Ok(());
+        };
+        let now = self.clock.now();
+        if now == self.last_expiry_sweep {
+            return Ok(());
+        }
+        self.last_expiry_sweep = now;
+        for (tx_id, client_id) in self.resolver.expired_authorizations(expiry)? {
+            let mut account = self
+                .accounts
+                .load(&client_id)?
+                .unwrap_or_else(|| Account::new(client_id));
+            let before = account.clone();
+            self.resolver.expire_authorization(&tx_id, &mut account)?;
+            self.accounts.save(&account)?;
+            self.record_history(
+                &before,
+                &account,
+                now,
+                Some(tx_id),
+                HistoryEvent::AuthorizationExpired,
+                None,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Clients whose balances disagree with the double-entry books, none without them.
+    pub fn verify_ledger(&self) -> Vec<(ClientId, EngineError)> {
+        match self.resolver.ledger() {
+            Some(ledger) => ledger.verify(&self.accounts().unwrap_or_default()),
+            None => Vec::new(),
+        }
+    }
+
+    fn print_ledger_check_to_stderr(&self) {
+        let Some(ledger) = self.resolver.ledger() else {
+            return;
+        };
+        let trial_balance = ledger.trial_balance();
+        if !trial_balance.is_balanced() {
+            eprintln!(
+                "processor {}: trial balance failed, debits {} != credits {}",
+                self.instance_id, trial_balance.total_debits, trial_balance.total_credits
+            );
+        }
+        for (client_id, e) in self.verify_ledger() {
+            eprintln!(
+                "processor {}: client {} {:?}",
+                self.instance_id, *client_id, e
+            );
+        }
+    }
+
+    /// Applies all members of a batch or none of them. When any member fails, the rest
+    /// is reported as rolled back.
+    pub fn process_batch(&mut self, members: Vec<PositionedTransaction>) -> Vec<TransactionError> {
+        let mut results = self.prepare_batch(members);
+        match results.iter().all(|(_, e)| e.is_none()) {
+            true => {
+                self.commit_batch();
+            }
+            false => {
+                self.prepared_batch.clear();
+                for (_, e) in &mut results {
+                    e.get_or_insert(EngineError::Batch_RolledBack);
+                }
+            }
+        }
+        results
+    }
+
+    /// Validates batch members one after another against staged copies of their accounts,
+    /// keeping them for [`Self::commit_batch`]. Members applied before restart are skipped.
+    /// Only deposits and withdrawals can be batched, schedule and sequence numbers don't apply.
+    pub fn prepare_batch(&mut self, members: Vec<PositionedTransaction>) -> Vec<TransactionError> {
+        let clients: HashSet<ClientId> = members.iter().map(|(tx, _)| tx.client_id).collect();
+        let mut risk = self.resolver.staged_risk(&clients);
+        let mut accounts: HashMap<ClientId, Account> = HashMap::new();
+        let mut ids = HashSet::new();
+        let mut results = Vec::new();
+        self.prepared_batch.clear();
+        for (tx, position) in members {
+            if position <= self.applied_up_to {
+                results.push((tx.id, None));
+                continue;
+            }
+            let result = self.stage(&tx, &mut accounts, &mut risk, &mut ids);
+            results.push((tx.id, result.err()));
+            self.prepared_batch.push(tx);
+        }
+        results
+    }
+
+    // checks batch member, applying it to staged account and risk copies
+    fn stage(
+        &self,
+        tx: &TransactionDTO,
+        accounts: &mut HashMap<ClientId, Account>,
+        risk: &mut RiskMonitor,
+        ids: &mut HashSet<TransactionId>,
+    ) -> Result<(), EngineError> {
+        if !matches!(tx.kind, TxKind::Deposit | TxKind::Withdrawal) {
+            return Err(EngineError::Batch_UnsupportedTransaction);
+        }
+        if !ids.insert(tx.id) {
+            return Err(EngineError::Resolver_DuplicateTransactionId);
+        }
+        let account = match accounts.entry(tx.client_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                self.accounts
+                    .load(&tx.client_id)?
+                    .unwrap_or_else(|| Account::new(tx.client_id)),
+            ),
+        };
+        if let Some(screen) = &self.screen {
+            match screen.screen(tx, account) {
+                Verdict::Approve => {}
+                Verdict::Reject(reason) => return Err(EngineError::Screening_Rejected(reason)),
+                // review queue can't hold part of a batch
+                Verdict::Review => {
+                    return Err(EngineError::Screening_Rejected("batch member needs review"));
+                }
+            }
+        }
+        self.resolver.stage_adjustment(tx, account, risk)
+    }
+
+    /// Applies members of the prepared batch.
+    pub fn commit_batch(&mut self) -> Vec<TransactionError> {
+        std::mem::take(&mut self.prepared_batch)
+            .into_iter()
+            .map(|tx| (tx.id, self.apply(tx).err()))
+            .collect()
+    }
+
+    /// Processes transaction at `position` of input, unless it was already applied
+    /// before restart, in which case it's only acknowledged.
+    pub fn process_at(&mut self, tx: TransactionDTO, position: u64) -> Result<(), EngineError> {
+        match position <= self.applied_up_to {
+            true => Ok(()),
+            false => self.process(tx),
+        }
+    }
+
+    /// Processes transaction approved by the screen, if there is one.
+    /// Transaction ahead of its sequence number is buffered until its predecessors arrive,
+    /// results of transactions it releases are returned by [`Self::apply_due`].
+    pub fn process(&mut self, tx: TransactionDTO) -> Result<(), EngineError> {
+        // failing sweep or job is reported, it doesn't reject the transaction
+        if let Err(e) = self.expire_authorizations() {
+            eprintln!(
+                "processor {}: expiring authorizations failed: {e:?}",
+                self.instance_id
+            );
+        }
+        // jobs due before the transaction see balances from before it
+        if let Err(e) = self.run_jobs() {
+            eprintln!("processor {}: periodic job failed: {e:?}", self.instance_id);
+        }
+        if tx.sequence_number.is_none() {
+            return self.process_in_order(tx);
+        }
+        let sequence_number = tx.sequence_number;
+        let released = self.sequencer.admit(tx, self.clock.now())?;
+        let in_turn = released
+            .ready
+            .first()
+            .is_some_and(|first| first.sequence_number == sequence_number);
+        let mut results = self.process_released(released).into_iter();
+        let result = match in_turn {
+            true => results.next().and_then(|(_, e)| e).map_or(Ok(()), Err),
+            false => Ok(()),
+        };
+        self.deferred.extend(results);
+        result
+    }
+
+    /// Transaction effective in the future is only scheduled.
+    fn process_in_order(&mut self, tx: TransactionDTO) -> Result<(), EngineError> {
+        if let Some(effective_at) = tx.effective_at
+            && effective_at > self.clock.now()
+        {
+            return self.schedule.schedule(tx);
+        }
+        if let Some(screen) = &self.screen {
+            let account = self
+                .accounts
+                .load(&tx.client_id)?
+                .unwrap_or_else(|| Account::new(tx.client_id));
+            match screen.screen(&tx, &account) {
+                Verdict::Approve => {}
+                Verdict::Reject(reason) => return Err(EngineError::Screening_Rejected(reason)),
+                Verdict::Review => {
+                    self.review_queue.push(tx);
+                    return Err(EngineError::Screening_HeldForReview);
+                }
+            }
+        }
+        self.apply(tx)
+    }
+
+    fn apply(&mut self, tx: TransactionDTO) -> Result<(), EngineError> {
+        let stored = self.accounts.load(&tx.client_id)?;
+        let is_new = stored.is_none();
+        let mut account = stored.unwrap_or_else(|| Account::new(tx.client_id));
+        let before = account.clone();
+        let (tx_id, kind, amount) = (tx.id, tx.kind, tx.amount);
+
+        let result = match tx.kind {
+            TxKind::Deposit | TxKind::Withdrawal => {
+                self.resolver.apply_adjustment(tx, &mut account)
+            }
+            TxKind::Dispute => self.resolver.open_dispute(&tx.id, &mut account),
+            TxKind::Resolve | TxKind::Chargeback => self.resolver.close_dispute(tx, &mut account),
+            TxKind::Authorize => self.resolver.authorize(tx, &mut account),
+            TxKind::Capture => self.resolver.capture(tx, &mut account),
+            TxKind::Void => self.resolver.void(tx, &mut account),
+            TxKind::Cancel => self.schedule.cancel(&tx.id, &tx.client_id),
+            TxKind::Reversal => self.resolver.reverse(tx, &mut account),
+        };
+        match &result {
+            Ok(()) => {
+                account.last_activity = self.clock.now();
+                account.dormant = false;
+                self.accounts.save(&account)?;
+            }
+            // rejected transaction leaves the account as it was, though it's kept
+            // even when its first transaction gets rejected
+            Err(_) => {
+                account = before.clone();
+                if is_new {
+                    self.accounts.save(&account)?;
+                }
+            }
+        }
+        // applied transaction is recorded even when balances stay, e.g. dispute of a withdrawal
+        if result.is_ok() {
+            self.record_history(
+                &before,
+                &account,
+                self.clock.now(),
+                Some(tx_id),
+                HistoryEvent::Transaction(kind),
+                amount,
+            )?;
+        }
+        result
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::mem::discriminant;
+    use std::{mem::discriminant, sync::Arc};
 
-    use tokio::sync::mpsc;
+    use tokio::sync::{mpsc, oneshot};
 
     use crate::engine::{
-        EngineError,
-        objects::{ClientId, TransactionDTO, TransactionId, TxKind},
-        processor::{ProcessorImpl, TransactionError},
+        Account, EngineError,
+        admin::AdminCommand,
+        clock::Clock,
+        objects::{ClientId, TransactionDTO, TransactionId, TxAmount, TxKind},
+        processor::{
+            ProcessorCommand, ProcessorConfig, ProcessorImpl, ProcessorInput, TransactionError,
+        },
+        screening::{TransactionScreen, Verdict},
     };
 
     #[tokio::test]
     async fn processor_returns_results_and_errors() {
-        let (sender, receiver) = mpsc::unbounded_channel::<TransactionDTO>();
+        let (sender, receiver) = mpsc::unbounded_channel::<ProcessorInput>();
         let client_id = 1;
 
         let transactions: Vec<(TransactionDTO, TransactionError)> = [
             (
-                TransactionDTO {
-                    id: TransactionId(1),
-                    client_id: ClientId(client_id),
-                    kind: TxKind::Deposit,
-                    amount: Some(100.0),
-                },
+                TransactionDTO::new(1, client_id, TxKind::Deposit, Some(100.0)),
                 (TransactionId(1), None),
             ),
             (
-                TransactionDTO {
-                    id: TransactionId(2),
-                    client_id: ClientId(client_id),
-                    kind: TxKind::Withdrawal,
-                    amount: Some(50.0),
-                },
+                TransactionDTO::new(2, client_id, TxKind::Withdrawal, Some(50.0)),
                 (TransactionId(2), None),
             ),
             (
-                TransactionDTO {
-                    id: TransactionId(2),
-                    client_id: ClientId(client_id),
-                    kind: TxKind::Dispute,
-                    amount: None,
-                },
+                TransactionDTO::new(2, client_id, TxKind::Dispute, None),
                 (TransactionId(2), None),
             ),
             (
-                TransactionDTO {
-                    id: TransactionId(2),
-                    client_id: ClientId(client_id),
-                    kind: TxKind::Chargeback,
-                    amount: None,
-                },
+                TransactionDTO::new(2, client_id, TxKind::Chargeback, None),
                 (TransactionId(2), None),
             ),
             (
-                TransactionDTO {
-                    id: TransactionId(100),
-                    client_id: ClientId(client_id),
-                    kind: TxKind::Chargeback,
-                    amount: None,
-                },
+                TransactionDTO::new(100, client_id, TxKind::Chargeback, None),
                 (
                     TransactionId(100),
                     Some(EngineError::Resolver_TransactionNotUnderDispute),
                 ),
             ),
             (
-                TransactionDTO {
-                    id: TransactionId(3),
-                    client_id: ClientId(client_id),
-                    kind: TxKind::Withdrawal,
-                    amount: Some(70.0),
-                },
+                TransactionDTO::new(3, client_id, TxKind::Withdrawal, Some(70.0)),
                 (TransactionId(3), None),
             ),
             (
-                TransactionDTO {
-                    id: TransactionId(4),
-                    client_id: ClientId(client_id),
-                    kind: TxKind::Withdrawal,
-                    amount: Some(40.0),
-                },
+                TransactionDTO::new(4, client_id, TxKind::Withdrawal, Some(40.0)),
                 (TransactionId(4), Some(EngineError::Account_NotEnoughFunds)),
             ),
             (
-                TransactionDTO {
-                    id: TransactionId(500),
-                    client_id: ClientId(client_id),
-                    kind: TxKind::Dispute,
-                    amount: Some(40.0),
-                },
+                TransactionDTO::new(500, client_id, TxKind::Dispute, Some(40.0)),
                 (
                     TransactionId(500),
                     Some(EngineError::Resolver_TransactionNotFound),
@@ -172,16 +1312,309 @@ mod tests {
         .into_iter()
         .collect();
 
-        let (mut results, _handle) = ProcessorImpl::run(receiver, 1);
-        for transaction in transactions {
+        let (mut results, _handle) = ProcessorImpl::run(receiver, 1, ProcessorConfig::default());
+        for (position, transaction) in (1..).zip(transactions) {
             let expect_res = transaction.1.1;
             let expect_id = transaction.1.0;
 
-            sender.send(transaction.0).unwrap();
+            sender
+                .send(ProcessorInput::Transaction(transaction.0, position))
+                .unwrap();
             let (id, res) = results.recv().await.unwrap();
 
             assert_eq!(id, expect_id);
             assert_eq!(discriminant(&res), discriminant(&expect_res));
         }
     }
+
+    #[tokio::test]
+    async fn processor_reconciles_on_demand() {
+        let (sender, receiver) = mpsc::unbounded_channel::<ProcessorInput>();
+        let (_results, _handle) = ProcessorImpl::run(receiver, 1, ProcessorConfig::default());
+
+        for (position, (id, kind, amount)) in (1..).zip([
+            (1, TxKind::Deposit, Some(100.0)),
+            (2, TxKind::Withdrawal, Some(40.0)),
+            (1, TxKind::Dispute, None),
+        ]) {
+            let tx = TransactionDTO::new(id, 1, kind, amount);
+            sender
+                .send(ProcessorInput::Transaction(tx, position))
+                .unwrap();
+        }
+        let (reply, discrepancies) = oneshot::channel();
+        sender
+            .send(ProcessorInput::Command(ProcessorCommand::Reconcile(reply)))
+            .unwrap();
+
+        assert!(discrepancies.await.unwrap().unwrap().is_empty());
+    }
+
+    #[derive(Debug)]
+    struct LargeWithdrawalScreen;
+
+    impl TransactionScreen for LargeWithdrawalScreen {
+        fn screen(&self, tx: &TransactionDTO, account: &Account) -> Verdict {
+            match (tx.kind, tx.amount) {
+                (TxKind::Withdrawal, Some(amount)) if amount > account.available => {
+                    Verdict::Reject("overdraft")
+                }
+                (TxKind::Withdrawal, Some(amount)) if amount.to_f64() >= 50.0 => Verdict::Review,
+                _ => Verdict::Approve,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn screened_transactions_wait_for_review() {
+        let config = ProcessorConfig {
+            screen: Some(Arc::new(LargeWithdrawalScreen)),
+            ..Default::default()
+        };
+        let mut processor = ProcessorImpl::new(1, config);
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, Some(amount));
+
+        assert!(processor.process(tx(1, TxKind::Deposit, 100.0)).is_ok());
+        assert!(matches!(
+            processor.process(tx(2, TxKind::Withdrawal, 200.0)),
+            Err(EngineError::Screening_Rejected("overdraft"))
+        ));
+        for id in [3, 4] {
+            assert!(matches!(
+                processor.process(tx(id, TxKind::Withdrawal, 50.0)),
+                Err(EngineError::Screening_HeldForReview)
+            ));
+        }
+
+        let (reply, approved) = oneshot::channel();
+        processor.execute(ProcessorCommand::ApproveReview(TransactionId(3), reply));
+        assert!(approved.await.unwrap().is_ok());
+        let (reply, drained) = oneshot::channel();
+        processor.execute(ProcessorCommand::DrainReviewQueue(reply));
+        assert_eq!(drained.await.unwrap()[0].id, TransactionId(4));
+        let (reply, rejected) = oneshot::channel();
+        processor.execute(ProcessorCommand::RejectReview(TransactionId(4), reply));
+        assert!(rejected.await.unwrap().is_err());
+
+        let account = processor.account(&ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.available, TxAmount::from_f64(50.0));
+    }
+
+    #[test]
+    fn review_queue_is_served_by_admin_commands() {
+        let config = ProcessorConfig {
+            screen: Some(Arc::new(LargeWithdrawalScreen)),
+            ..Default::default()
+        };
+        let mut processor = ProcessorImpl::new(0, config);
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, Some(amount));
+        let admin = |line| AdminCommand::parse(line).unwrap();
+
+        assert!(processor.process(tx(1, TxKind::Deposit, 200.0)).is_ok());
+        for id in [2, 3, 4] {
+            assert!(processor.process(tx(id, TxKind::Withdrawal, 50.0)).is_err());
+        }
+
+        // transaction is approved only on behalf of its own client
+        assert!(matches!(
+            processor.administer(admin("approve, 2, 2, ops")),
+            Err(EngineError::Screening_TransactionNotUnderReview)
+        ));
+        assert!(processor.administer(admin("approve, 1, 2, ops")).is_ok());
+        assert!(processor.administer(admin("reject, 1, 3, ops")).is_ok());
+        assert!(processor.administer(admin("pending, 0")).is_ok());
+        assert!(
+            processor
+                .administer(admin("drain_review, 0, end-of-day"))
+                .is_ok()
+        );
+        assert!(processor.administer(admin("reject, 1, 4, ops")).is_err());
+
+        let account = processor.account(&ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.available, TxAmount::from_f64(150.0));
+        let audit = processor.audit_log().unwrap();
+        assert_eq!(audit[4].command, "drain_review, 0, end-of-day");
+        assert_eq!(audit[5].outcome, "Screening_TransactionNotUnderReview");
+    }
+
+    #[test]
+    fn stale_authorizations_expire() {
+        let clock = Clock::simulated(1);
+        let config = ProcessorConfig {
+            clock: clock.clone(),
+            authorization_expiry: Some(60),
+            ..Default::default()
+        };
+        let mut processor = ProcessorImpl::new(1, config);
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+
+        assert!(
+            processor
+                .process(tx(1, TxKind::Deposit, Some(100.0)))
+                .is_ok()
+        );
+        assert!(
+            processor
+                .process(tx(2, TxKind::Authorize, Some(40.0)))
+                .is_ok()
+        );
+        clock.advance(30);
+        assert!(
+            processor
+                .process(tx(3, TxKind::Authorize, Some(50.0)))
+                .is_ok()
+        );
+        clock.advance(30);
+        assert!(processor.expire_authorizations().is_ok());
+
+        let account = processor.account(&ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.available, TxAmount::from_f64(50.0));
+        assert_eq!(account.reserved, TxAmount::from_f64(50.0));
+        assert!(processor.process(tx(2, TxKind::Capture, None)).is_err());
+        assert!(processor.process(tx(3, TxKind::Capture, None)).is_ok());
+        assert!(processor.reconcile().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn scheduled_transactions_apply_when_due() {
+        let clock = Clock::simulated(0);
+        let config = ProcessorConfig {
+            clock: clock.clone(),
+            ..Default::default()
+        };
+        let (sender, receiver) = mpsc::unbounded_channel::<ProcessorInput>();
+        let (mut results, handle) = ProcessorImpl::run(receiver, 1, config);
+        let tx = |id, kind, amount, effective_at| TransactionDTO {
+            effective_at,
+            ..TransactionDTO::new(id, 1, kind, amount)
+        };
+
+        for (position, tx) in (1..).zip([
+            tx(1, TxKind::Deposit, Some(100.0), Some(50)),
+            tx(2, TxKind::Deposit, Some(10.0), Some(60)),
+            tx(3, TxKind::Withdrawal, Some(30.0), Some(40)),
+            tx(2, TxKind::Cancel, None, None),
+            tx(4, TxKind::Cancel, None, None),
+        ]) {
+            sender
+                .send(ProcessorInput::Transaction(tx, position))
+                .unwrap();
+        }
+        // scheduling is acknowledged right away
+        for _ in 0..4 {
+            assert!(results.recv().await.unwrap().1.is_none());
+        }
+        assert!(matches!(
+            results.recv().await.unwrap(),
+            (_, Some(EngineError::Schedule_TransactionNotScheduled))
+        ));
+
+        clock.advance(100);
+        // withdrawal is due before the deposit
+        assert!(matches!(
+            results.recv().await.unwrap(),
+            (TransactionId(3), Some(EngineError::Account_NotEnoughFunds))
+        ));
+        assert!(matches!(
+            results.recv().await.unwrap(),
+            (TransactionId(1), None)
+        ));
+        drop(sender);
+        let accounts = handle.await.unwrap();
+        assert_eq!(accounts[0].available, TxAmount::from_f64(100.0));
+    }
+
+    #[test]
+    fn sequenced_transactions_are_reordered() {
+        let clock = Clock::simulated(0);
+        let config = ProcessorConfig {
+            clock: clock.clone(),
+            ..Default::default()
+        };
+        let mut processor = ProcessorImpl::new(1, config);
+        let tx = |id, kind, amount, sequence_number| TransactionDTO {
+            sequence_number: Some(sequence_number),
+            ..TransactionDTO::new(id, 1, kind, Some(amount))
+        };
+
+        // withdrawal arrives before the deposit it depends on
+        assert!(
+            processor
+                .process(tx(2, TxKind::Withdrawal, 30.0, 2))
+                .is_ok()
+        );
+        assert!(processor.process(tx(1, TxKind::Deposit, 100.0, 1)).is_ok());
+        assert!(matches!(
+            processor.apply_due().as_slice(),
+            [(TransactionId(2), None)]
+        ));
+        assert!(matches!(
+            processor.process(tx(3, TxKind::Deposit, 10.0, 2)),
+            Err(EngineError::Sequence_DuplicateSequenceNumber)
+        ));
+
+        assert!(processor.process(tx(5, TxKind::Deposit, 5.0, 5)).is_ok());
+        assert!(processor.apply_due().is_empty());
+        clock.advance(5);
+        assert!(matches!(
+            processor.apply_due().as_slice(),
+            [(TransactionId(5), None)]
+        ));
+        assert_eq!(processor.sequence_gaps()[0].first_missing, 3);
+        assert_eq!(processor.sequence_gaps()[0].last_missing, 4);
+
+        let account = processor.account(&ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.available, TxAmount::from_f64(75.0));
+    }
+
+    #[test]
+    fn batch_is_applied_all_or_nothing() {
+        let mut processor = ProcessorImpl::new(1, ProcessorConfig::default());
+        let tx = |id, client_id, kind, amount| {
+            let tx = TransactionDTO {
+                batch_id: Some(1),
+                ..TransactionDTO::new(id, client_id, kind, Some(amount))
+            };
+            let position = tx.id.to_u64();
+            (tx, position)
+        };
+
+        assert!(
+            processor
+                .process(tx(1, 1, TxKind::Deposit, 100.0).0)
+                .is_ok()
+        );
+        let results = processor.process_batch(vec![
+            tx(2, 1, TxKind::Withdrawal, 60.0),
+            tx(3, 2, TxKind::Deposit, 60.0),
+            tx(4, 1, TxKind::Withdrawal, 60.0),
+        ]);
+        assert!(matches!(
+            results.as_slice(),
+            [
+                (_, Some(EngineError::Batch_RolledBack)),
+                (_, Some(EngineError::Batch_RolledBack)),
+                (_, Some(EngineError::Account_NotEnoughFunds)),
+            ]
+        ));
+        assert_eq!(
+            processor.account(&ClientId(1)).unwrap().unwrap().available,
+            TxAmount::from_f64(100.0)
+        );
+        assert!(processor.account(&ClientId(2)).unwrap().is_none());
+
+        let results = processor.process_batch(vec![
+            tx(2, 1, TxKind::Withdrawal, 60.0),
+            tx(3, 2, TxKind::Deposit, 60.0),
+        ]);
+        assert!(results.iter().all(|(_, e)| e.is_none()));
+        assert_eq!(
+            processor.account(&ClientId(1)).unwrap().unwrap().available,
+            TxAmount::from_f64(40.0)
+        );
+        assert_eq!(
+            processor.account(&ClientId(2)).unwrap().unwrap().available,
+            TxAmount::from_f64(60.0)
+        );
+    }
 }
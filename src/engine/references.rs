@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use super::{
+    EngineError,
+    objects::{RawTransactionId, TransactionId},
+};
+
+/// Maps external transaction references, as sent by partners, to internal `TransactionId`s.
+/// Numeric references are used as ids directly. Alphanumeric ones get ids allocated downwards
+/// from the top of id range, skipping the range numeric ids were seen in, so both kinds can be
+/// mixed in one input.
+pub struct TransactionReferences {
+    ids: HashMap<String, TransactionId>,
+    references: HashMap<TransactionId, String>,
+    // lowest and highest numeric id which allocation could still reach, only the range
+    // is kept as numeric ids are countless
+    numeric: Option<(RawTransactionId, RawTransactionId)>,
+    // ids are allocated at or below it
+    next: RawTransactionId,
+}
+
+impl Default for TransactionReferences {
+    fn default() -> Self {
+        Self {
+            ids: HashMap::new(),
+            references: HashMap::new(),
+            numeric: None,
+            next: RawTransactionId::MAX,
+        }
+    }
+}
+
+impl TransactionReferences {
+    pub fn resolve(&mut self, reference: &str) -> Result<TransactionId, EngineError> {
+        if let Ok(id) = reference.parse::<RawTransactionId>() {
+            if self.references.contains_key(&TransactionId(id)) {
+                return Err(EngineError::Parsing_TransactionIdTakenByReference);
+            }
+            // ids above `next` can't be allocated anymore, they needn't be kept apart
+            if id <= self.next {
+                self.numeric = Some(match self.numeric {
+                    Some((lowest, highest)) => (lowest.min(id), highest.max(id)),
+                    None => (id, id),
+                });
+            }
+            return Ok(TransactionId(id));
+        }
+        if let Some(id) = self.ids.get(reference) {
+            return Ok(*id);
+        }
+        // above the numeric range while there's room, below it afterwards
+        let id = match self.numeric {
+            Some((lowest, highest)) if self.next <= highest => lowest
+                .checked_sub(1)
+                .map(|below| below.min(self.next))
+                .ok_or(EngineError::Parsing_NoTransactionIdLeftForReference)?,
+            _ => self.next,
+        };
+        self.next = id
+            .checked_sub(1)
+            .ok_or(EngineError::Parsing_NoTransactionIdLeftForReference)?;
+        let id = TransactionId(id);
+        self.ids.insert(reference.to_string(), id);
+        self.references.insert(id, reference.to_string());
+        Ok(id)
+    }
+
+    /// Reference the id was allocated for, or the id itself.
+    pub fn describe(&self, id: &TransactionId) -> String {
+        match self.references.get(id) {
+            Some(reference) => reference.clone(),
+            None => id.0.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::objects::{RawTransactionId, TransactionId};
+
+    use super::TransactionReferences;
+
+    #[test]
+    fn references_map_to_stable_ids() {
+        let mut references = TransactionReferences::default();
+
+        let first = references.resolve("INV-0001").unwrap();
+        let second = references.resolve("INV-0002").unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(references.resolve("INV-0001").unwrap(), first);
+        assert_eq!(references.resolve("17").unwrap(), TransactionId(17));
+        assert!(references.resolve(&first.0.to_string()).is_err());
+        assert_eq!(references.describe(&second), "INV-0002");
+        assert_eq!(references.describe(&TransactionId(17)), "17");
+    }
+
+    #[test]
+    fn references_skip_ids_already_used_numerically() {
+        let mut references = TransactionReferences::default();
+        let top = TransactionId(RawTransactionId::MAX);
+
+        assert_eq!(references.resolve(&top.0.to_string()).unwrap(), top);
+        let allocated = references.resolve("INV-0001").unwrap();
+
+        assert_ne!(allocated, top);
+        assert_eq!(references.resolve(&top.0.to_string()).unwrap(), top);
+        assert!(references.resolve(&allocated.0.to_string()).is_err());
+    }
+
+    #[test]
+    fn references_are_allocated_around_numeric_ids() {
+        let mut references = TransactionReferences::default();
+        let top = RawTransactionId::MAX;
+
+        for id in [5, 7, top - 1] {
+            references.resolve(&id.to_string()).unwrap();
+        }
+        assert_eq!(references.resolve("INV-0001").unwrap(), TransactionId(top));
+        // range of numeric ids is skipped once allocation reaches it
+        assert_eq!(references.resolve("INV-0002").unwrap(), TransactionId(4));
+        assert!(references.resolve(&top.to_string()).is_err());
+        references.resolve("6").unwrap();
+        assert_eq!(references.resolve("INV-0003").unwrap(), TransactionId(3));
+    }
+}
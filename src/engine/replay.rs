@@ -0,0 +1,419 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+use super::{
+    EngineError,
+    batch::{BatchAssembler, InputUnit},
+    clock::{Clock, Timestamp},
+    objects::{ClientId, RawClientId, TransactionDTO, TransactionId, TxAmount, TxKind},
+    processor::{ProcessorConfig, ProcessorImpl, TransactionError},
+    references::TransactionReferences,
+};
+
+pub const REPORT_HEADER: &str = "# p-engine report";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub kind: TxKind,
+    pub client_id: ClientId,
+    pub tx: String,     // transaction reference as in input
+    pub result: String, // "ok" or rejection reason
+}
+
+/// Final accounts and per-transaction outcomes of a deterministic, single-processor run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunReport {
+    pub accounts: BTreeMap<RawClientId, String>,
+    pub fees: BTreeMap<RawClientId, String>, // fees collected, clients who paid none are left out
+    pub outcomes: Vec<Outcome>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientDiff {
+    pub client_id: RawClientId,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub position: usize, // 1-based index of transaction in input
+    pub expected: Option<Outcome>,
+    pub actual: Option<Outcome>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReportDiff {
+    pub clients: Vec<ClientDiff>,
+    pub fees: Vec<ClientDiff>, // of clients who paid different fees
+    pub first_divergence: Option<Divergence>,
+}
+
+/// Processes transactions one by one on a single processor, so that runs are repeatable.
+pub fn replay(
+    transactions: impl IntoIterator<Item = TransactionDTO>,
+    config: ProcessorConfig,
+    references: &TransactionReferences,
+) -> RunReport {
+    let transactions: Vec<TransactionDTO> = transactions.into_iter().collect();
+    let clock = input_clock(&transactions);
+    let mut processor = ProcessorImpl::new(
+        0,
+        ProcessorConfig {
+            clock: clock.clone(),
+            ..config
+        },
+    );
+    let outcomes = replay_on(&mut processor, transactions, references, &clock);
+    let accounts = processor.accounts().unwrap_or_default();
+    let fees = accounts
+        .iter()
+        .filter(|account| account.fees != TxAmount::ZERO)
+        .map(|account| (*account.client_id, account.fees.to_string()))
+        .collect();
+    let accounts = accounts
+        .into_iter()
+        .map(|account| (*account.client_id, account.to_csv(true)))
+        .collect();
+
+    RunReport {
+        accounts,
+        fees,
+        outcomes,
+    }
+}
+
+/// Simulated clock of a replay, starting at the earliest effective time in input (0 without any).
+/// [`replay_on`] moves it one second ahead with every input transaction, so time based rules
+/// (schedule, sequence gap timeouts, periodic jobs) give the same results in every run.
+pub fn input_clock(transactions: &[TransactionDTO]) -> Clock {
+    let start = transactions
+        .iter()
+        .filter_map(|tx| tx.effective_at)
+        .min()
+        .unwrap_or(0);
+    Clock::simulated(start)
+}
+
+/// Processes transactions in order on `processor`, returning their outcomes. Work the router
+/// does between and after input is done too: due scheduled transactions are applied, buffered
+/// sequenced ones are released at the end, and their results replace the acknowledgement
+/// reported when they arrived. Scheduled transactions still not due at the end are `pending`.
+/// `clock` is the one `processor` runs on, when simulated it's set to `n` seconds past
+/// its start before the `n`-th transaction.
+pub fn replay_on(
+    processor: &mut ProcessorImpl,
+    transactions: impl IntoIterator<Item = TransactionDTO>,
+    references: &TransactionReferences,
+    clock: &Clock,
+) -> Vec<Outcome> {
+    let start = clock.now();
+    let mut batches = BatchAssembler::default();
+    let mut outcomes = Vec::new();
+    // outcomes of transactions which may be held, with their effective time
+    let mut held: HashMap<TransactionId, (usize, Option<Timestamp>)> = HashMap::new();
+    let outcome = |tx: &TransactionDTO, result: Option<EngineError>| Outcome {
+        kind: tx.kind,
+        client_id: tx.client_id,
+        tx: references.describe(&tx.id),
+        result: describe(result),
+    };
+    let units = (1..)
+        .zip(transactions)
+        .flat_map(|(position, tx)| batches.push(tx, position))
+        .collect::<Vec<_>>();
+    for unit in units.into_iter().chain(batches.finish()) {
+        match unit {
+            InputUnit::Transaction(tx, position) => {
+                clock.set(start + position);
+                let result = processor.process(tx.clone()).err();
+                if tx.sequence_number.is_some() || tx.effective_at.is_some() {
+                    held.insert(tx.id, (outcomes.len(), tx.effective_at));
+                }
+                outcomes.push(outcome(&tx, result));
+            }
+            InputUnit::Batch(_, members) => {
+                if let Some((_, position)) = members.first() {
+                    clock.set(start + position);
+                }
+                let transactions = members.iter().map(|(tx, _)| tx.clone()).collect::<Vec<_>>();
+                let results = processor.process_batch(members);
+                for (tx, (_, result)) in transactions.iter().zip(results) {
+                    outcomes.push(outcome(tx, result));
+                }
+            }
+        }
+        let due = processor.apply_due();
+        settle(&mut outcomes, &mut held, due);
+    }
+    let released = processor.finish();
+    settle(&mut outcomes, &mut held, released);
+    let due = processor.apply_due();
+    settle(&mut outcomes, &mut held, due);
+    let now = clock.now();
+    for (index, effective_at) in held.into_values() {
+        if effective_at.is_some_and(|at| at > now) {
+            outcomes[index].result = "pending".to_string();
+        }
+    }
+    outcomes
+}
+
+fn describe(result: Option<EngineError>) -> String {
+    result.map_or("ok".to_string(), |e| format!("{e:?}"))
+}
+
+/// Records results of held transactions in place of their acknowledgement.
+fn settle(
+    outcomes: &mut [Outcome],
+    held: &mut HashMap<TransactionId, (usize, Option<Timestamp>)>,
+    results: Vec<TransactionError>,
+) {
+    for (tx_id, result) in results {
+        if let Some((index, _)) = held.remove(&tx_id) {
+            outcomes[index].result = describe(result);
+        }
+    }
+}
+
+pub fn diff(expected: &RunReport, actual: &RunReport) -> ReportDiff {
+    let clients = differing_clients(&expected.accounts, &actual.accounts);
+    let fees = differing_clients(&expected.fees, &actual.fees);
+
+    let length = expected.outcomes.len().max(actual.outcomes.len());
+    let first_divergence = (0..length)
+        .map(|i| (i, expected.outcomes.get(i), actual.outcomes.get(i)))
+        .find(|(_, expected, actual)| expected != actual)
+        .map(|(i, expected, actual)| Divergence {
+            position: i + 1,
+            expected: expected.cloned(),
+            actual: actual.cloned(),
+        });
+
+    ReportDiff {
+        clients,
+        fees,
+        first_divergence,
+    }
+}
+
+fn differing_clients(
+    expected: &BTreeMap<RawClientId, String>,
+    actual: &BTreeMap<RawClientId, String>,
+) -> Vec<ClientDiff> {
+    let mut client_ids: Vec<RawClientId> = expected.keys().chain(actual.keys()).copied().collect();
+    client_ids.sort();
+    client_ids.dedup();
+
+    client_ids
+        .into_iter()
+        .filter_map(|client_id| {
+            let expected = expected.get(&client_id);
+            let actual = actual.get(&client_id);
+            (expected != actual).then(|| ClientDiff {
+                client_id,
+                expected: expected.cloned(),
+                actual: actual.cloned(),
+            })
+        })
+        .collect()
+}
+
+impl RunReport {
+    pub fn to_lines(&self) -> Vec<String> {
+        let accounts = self.accounts.values().map(|csv| format!("account,{csv}"));
+        let fees = self
+            .fees
+            .iter()
+            .map(|(client_id, fees)| format!("fees,{client_id},{fees}"));
+        let outcomes = self.outcomes.iter().map(|outcome| {
+            format!(
+                "outcome,{},{},{},{}",
+                outcome.kind, *outcome.client_id, outcome.tx, outcome.result
+            )
+        });
+        std::iter::once(REPORT_HEADER.to_string())
+            .chain(accounts)
+            .chain(fees)
+            .chain(outcomes)
+            .collect()
+    }
+
+    /// Reads report written by `to_lines`, `None` if text isn't a report.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        if lines.next()?.trim() != REPORT_HEADER {
+            return None;
+        }
+        let mut report = RunReport::default();
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            let (record, fields) = line.split_once(',')?;
+            match record {
+                "account" => {
+                    let client_id = fields.split(',').next()?.parse().ok()?;
+                    report.accounts.insert(client_id, fields.to_string());
+                }
+                "fees" => {
+                    let (client_id, fees) = fields.split_once(',')?;
+                    report
+                        .fees
+                        .insert(client_id.parse().ok()?, fees.to_string());
+                }
+                "outcome" => {
+                    let fields: Vec<&str> = fields.splitn(4, ',').collect();
+                    report.outcomes.push(Outcome {
+                        kind: TxKind::from_str(fields.first()?).ok()?,
+                        client_id: ClientId(fields.get(1)?.parse().ok()?),
+                        tx: fields.get(2)?.to_string(),
+                        result: fields.get(3)?.to_string(),
+                    });
+                }
+                _ => return None,
+            }
+        }
+        Some(report)
+    }
+}
+
+impl ReportDiff {
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty() && self.fees.is_empty() && self.first_divergence.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        fees::FeeSchedule,
+        objects::{TransactionDTO, TxKind},
+        processor::ProcessorConfig,
+        references::TransactionReferences,
+    };
+
+    use super::{RunReport, diff, replay};
+
+    #[test]
+    fn report_survives_round_trip_through_text() {
+        let report = replay(
+            [
+                TransactionDTO::new(1, 1, TxKind::Deposit, Some(10.0)),
+                TransactionDTO::new(2, 2, TxKind::Withdrawal, Some(5.0)),
+            ],
+            ProcessorConfig {
+                fees: FeeSchedule::parse("fee, default, deposit, 0.5, 0").unwrap(),
+                ..Default::default()
+            },
+            &TransactionReferences::default(),
+        );
+
+        let parsed = RunReport::parse(&report.to_lines().join("\n")).unwrap();
+
+        assert_eq!(parsed, report);
+        assert_eq!(parsed.fees[&1], "0.5000");
+        assert_eq!(parsed.outcomes[1].result, "Account_NotEnoughFunds");
+    }
+
+    #[test]
+    fn held_transactions_are_drained_and_reported() {
+        let tx = |id, kind, amount: f64| TransactionDTO::new(id, 1, kind, Some(amount));
+        let sequenced = |sequence_number, tx| TransactionDTO {
+            sequence_number: Some(sequence_number),
+            ..tx
+        };
+        let scheduled = |effective_at, tx| TransactionDTO {
+            effective_at: Some(effective_at),
+            ..tx
+        };
+        // clock starts at 2 and reaches 7 with the fifth transaction
+        let report = replay(
+            [
+                sequenced(1, tx(1, TxKind::Deposit, 100.0)),
+                sequenced(3, tx(2, TxKind::Deposit, 10.0)),
+                scheduled(2, tx(3, TxKind::Deposit, 1.0)),
+                scheduled(7, tx(4, TxKind::Deposit, 1.0)),
+                sequenced(4, tx(5, TxKind::Withdrawal, 500.0)),
+                scheduled(100, tx(6, TxKind::Deposit, 1.0)),
+            ],
+            ProcessorConfig::default(),
+            &TransactionReferences::default(),
+        );
+
+        // gap at sequence number 2 is skipped once input ends
+        assert!(report.accounts[&1].starts_with("1,112.0000,"));
+        let results: Vec<&str> = report.outcomes.iter().map(|o| o.result.as_str()).collect();
+        assert_eq!(
+            results,
+            ["ok", "ok", "ok", "ok", "Account_NotEnoughFunds", "pending"]
+        );
+    }
+
+    #[test]
+    fn identical_runs_have_no_differences() {
+        let input = [
+            TransactionDTO::new(1, 1, TxKind::Deposit, Some(10.0)),
+            TransactionDTO::new(1, 1, TxKind::Dispute, None),
+        ];
+        let references = TransactionReferences::default();
+
+        let first = replay(input.clone(), ProcessorConfig::default(), &references);
+        let second = replay(input, ProcessorConfig::default(), &references);
+
+        assert!(diff(&first, &second).is_empty());
+    }
+
+    #[test]
+    fn diff_reports_fees_collected_differently() {
+        let input = [TransactionDTO::new(1, 1, TxKind::Deposit, Some(10.0))];
+        let references = TransactionReferences::default();
+
+        let expected = replay(input.clone(), ProcessorConfig::default(), &references);
+        let actual = replay(
+            input,
+            ProcessorConfig {
+                fees: FeeSchedule::parse("fee, default, deposit, 0.5, 0").unwrap(),
+                ..Default::default()
+            },
+            &references,
+        );
+
+        let diff = diff(&expected, &actual);
+
+        assert!(diff.first_divergence.is_none());
+        assert_eq!(diff.fees.len(), 1);
+        assert_eq!(diff.fees[0].client_id, 1);
+        assert_eq!(diff.fees[0].expected, None);
+        assert_eq!(diff.fees[0].actual.as_deref(), Some("0.5000"));
+    }
+
+    #[test]
+    fn diff_reports_clients_and_first_diverging_transaction() {
+        let expected = replay(
+            [
+                TransactionDTO::new(1, 1, TxKind::Deposit, Some(10.0)),
+                TransactionDTO::new(2, 2, TxKind::Deposit, Some(10.0)),
+                TransactionDTO::new(3, 1, TxKind::Withdrawal, Some(8.0)),
+            ],
+            ProcessorConfig::default(),
+            &TransactionReferences::default(),
+        );
+        let actual = replay(
+            [
+                TransactionDTO::new(1, 1, TxKind::Deposit, Some(5.0)),
+                TransactionDTO::new(2, 2, TxKind::Deposit, Some(10.0)),
+                TransactionDTO::new(3, 1, TxKind::Withdrawal, Some(8.0)),
+            ],
+            ProcessorConfig::default(),
+            &TransactionReferences::default(),
+        );
+
+        let diff = diff(&expected, &actual);
+
+        assert_eq!(diff.clients.len(), 1);
+        assert_eq!(diff.clients[0].client_id, 1);
+        let divergence = diff.first_divergence.unwrap();
+        assert_eq!(divergence.position, 3);
+        assert_eq!(divergence.actual.unwrap().result, "Account_NotEnoughFunds");
+    }
+}
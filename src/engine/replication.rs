@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use super::{
+    admin::AdminCommand,
+    batch::PositionedTransaction,
+    clock::Timestamp,
+    core::account::Account,
+    objects::{ClientId, TransactionDTO, TransactionId},
+};
+
+/// Something a processor took in. Processing is deterministic, so a standby processor
+/// following the same events at the same times ends up in the same state.
+#[derive(Clone)]
+pub enum ReplicationEvent {
+    /// Processor started, fresh or restarted from its last checkpoint.
+    Start,
+    /// Input transaction at its position, with outcome on the primary.
+    Transaction {
+        tx: TransactionDTO,
+        position: u64,
+        accepted: bool,
+    },
+    /// Scheduled work done without input: due transactions, periodic jobs, sequence timeouts.
+    Tick,
+    ApproveReview(TransactionId),
+    RejectReview(TransactionId),
+    DrainReviewQueue,
+    PrepareBatch(Vec<PositionedTransaction>),
+    CommitBatch,
+    AbortBatch,
+    Admin(AdminCommand),
+    Checkpoint(u64),
+    /// Fingerprint of input the run is made over, with number of processors.
+    Input(String, u16),
+    /// Input ended, sequenced transactions still buffered were released.
+    Finish,
+    /// Every account of the primary, for the standby to compare with its own.
+    Balances(Vec<Account>),
+}
+
+/// Event of processor `shard`, with time it happened at.
+#[derive(Clone)]
+pub struct Replicated {
+    pub shard: u16,
+    pub at: Timestamp,
+    pub event: ReplicationEvent,
+}
+
+/// Standby disagreeing with its primary.
+#[derive(Clone)]
+pub enum Divergence {
+    /// Transaction accepted by one side and rejected by the other.
+    Outcome {
+        tx_id: TransactionId,
+        accepted_by_primary: bool,
+    },
+    /// Client whose balances differ, or who is missing on one side.
+    Balances {
+        client_id: ClientId,
+        primary: Option<Account>,
+        standby: Option<Account>,
+    },
+}
+
+impl Divergence {
+    pub fn describe(&self) -> String {
+        let account = |account: &Option<Account>| {
+            account
+                .as_ref()
+                .map_or("<missing>".to_string(), |account| account.to_csv(true))
+        };
+        match self {
+            Divergence::Outcome {
+                tx_id,
+                accepted_by_primary,
+            } => {
+                let (primary, standby) = match accepted_by_primary {
+                    true => ("accepted", "rejected"),
+                    false => ("rejected", "accepted"),
+                };
+                format!(
+                    "transaction {} {primary} by primary, {standby} by standby",
+                    tx_id.to_u64()
+                )
+            }
+            Divergence::Balances {
+                client_id,
+                primary,
+                standby,
+            } => format!(
+                "client {}: primary {}, standby {}",
+                **client_id,
+                account(primary),
+                account(standby)
+            ),
+        }
+    }
+}
+
+/// Clients whose balances, lock or collected fees differ between `primary` and `standby`.
+pub fn compare_balances(primary: Vec<Account>, standby: Vec<Account>) -> Vec<Divergence> {
+    let same = |a: &Account, b: &Account| {
+        (a.available, a.held, a.reserved, a.locked, a.fees)
+            == (b.available, b.held, b.reserved, b.locked, b.fees)
+    };
+    let mut standby: HashMap<ClientId, Account> = standby
+        .into_iter()
+        .map(|account| (account.client_id, account))
+        .collect();
+    let mut divergences = Vec::new();
+    for account in primary {
+        let client_id = account.client_id;
+        let theirs = standby.remove(&client_id);
+        if theirs.as_ref().is_none_or(|theirs| !same(&account, theirs)) {
+            divergences.push(Divergence::Balances {
+                client_id,
+                primary: Some(account),
+                standby: theirs,
+            });
+        }
+    }
+    divergences.extend(standby.into_values().map(|account| Divergence::Balances {
+        client_id: account.client_id,
+        primary: None,
+        standby: Some(account),
+    }));
+    divergences
+}
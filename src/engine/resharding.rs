@@ -0,0 +1,60 @@
+use super::{
+    core::account::Account,
+    objects::{
+        Adjustment, Authorization, ClientId, DisputeClaim, TransactionDTO, TransactionId, TxAmount,
+    },
+    risk::ClientActivity,
+    statement::HistoryEntry,
+};
+
+/// Processor (out of `shards`) owning the client.
+pub fn shard_of(client_id: &ClientId, shards: usize) -> usize {
+    **client_id as usize % shards
+}
+
+/// Everything a processor keeps about one client, handed over to another processor
+/// when resharding moves the client.
+pub struct ClientState {
+    pub client_id: ClientId,
+    pub account: Option<Account>,
+    pub adjustments: Vec<MigratedAdjustment>,
+    pub authorizations: Vec<(TransactionId, Authorization)>,
+    /// Net balance change made by periodic jobs and corrections.
+    pub accrual: TxAmount,
+    /// Available, held, reserved and fee legs in the double-entry ledger, if it's kept.
+    pub ledger: Option<[TxAmount; 4]>,
+    pub risk: Option<ClientActivity>,
+    pub scheduled: Vec<TransactionDTO>,
+    pub next_sequence_number: Option<u64>,
+    /// Transactions waiting for their sequence number, their wait starts over.
+    pub sequenced: Vec<TransactionDTO>,
+    pub under_review: Vec<TransactionDTO>,
+    pub history: Vec<HistoryEntry>,
+}
+
+/// Applied adjustment with its open dispute and outcome.
+pub struct MigratedAdjustment {
+    pub adjustment: Adjustment,
+    pub dispute: Option<DisputeClaim>,
+    pub reversed: bool,
+    pub charged_back: bool,
+}
+
+impl ClientState {
+    pub fn new(client_id: ClientId) -> Self {
+        Self {
+            client_id,
+            account: None,
+            adjustments: Vec::new(),
+            authorizations: Vec::new(),
+            accrual: TxAmount::ZERO,
+            ledger: None,
+            risk: None,
+            scheduled: Vec::new(),
+            next_sequence_number: None,
+            sequenced: Vec::new(),
+            under_review: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+};
+
+use super::{
+    EngineError,
+    clock::Timestamp,
+    objects::{Adjustment, AdjustmentKind, ClientId, TransactionDTO, TxAmount, TxKind},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RiskRule {
+    /// Largest amount of a single withdrawal.
+    WithdrawalCap(TxAmount),
+    /// Largest total withdrawn within `window` seconds.
+    WithdrawalVolume { limit: TxAmount, window: u64 },
+    /// Largest number of withdrawals within `window` seconds.
+    WithdrawalCount { limit: usize, window: u64 },
+    /// No withdrawals for `hold` seconds after a deposit of at least `threshold`.
+    LargeDepositHold { threshold: TxAmount, hold: u64 },
+}
+
+/// Rules checked before every withdrawal, in order they were configured.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RiskLimits {
+    pub rules: Vec<RiskRule>,
+}
+
+impl RiskLimits {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Reads rules, one per line: `withdrawal_cap, <amount>`,
+    /// `withdrawal_volume, <amount>, <window seconds>`, `withdrawal_count, <count>, <window seconds>`
+    /// and `large_deposit_hold, <amount>, <hold seconds>`. Empty lines and `#` comments are skipped.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut limits = RiskLimits::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            limits.rules.push(match fields.as_slice() {
+                ["withdrawal_cap", amount] => RiskRule::WithdrawalCap(amount.parse()?),
+                ["withdrawal_volume", limit, window] => RiskRule::WithdrawalVolume {
+                    limit: limit.parse()?,
+                    window: window.parse()?,
+                },
+                ["withdrawal_count", limit, window] => RiskRule::WithdrawalCount {
+                    limit: limit.parse()?,
+                    window: window.parse()?,
+                },
+                ["large_deposit_hold", threshold, hold] => RiskRule::LargeDepositHold {
+                    threshold: threshold.parse()?,
+                    hold: hold.parse()?,
+                },
+                _ => return Err(format!("line {}: unknown risk rule", number + 1).into()),
+            });
+        }
+        Ok(limits)
+    }
+
+    // withdrawals older than that can't violate any rule
+    fn longest_window(&self) -> u64 {
+        self.rules
+            .iter()
+            .map(|rule| match rule {
+                RiskRule::WithdrawalVolume { window, .. }
+                | RiskRule::WithdrawalCount { window, .. } => *window,
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Recent withdrawals and large deposits of a client.
+#[derive(Clone, Default)]
+pub struct ClientActivity {
+    withdrawals: VecDeque<(Timestamp, TxAmount)>,
+    last_large_deposit: Option<Timestamp>,
+}
+
+/// Evaluates risk limits against recent activity of every client.
+pub struct RiskMonitor {
+    limits: RiskLimits,
+    activity: HashMap<ClientId, ClientActivity>,
+}
+
+impl RiskMonitor {
+    pub fn new(limits: RiskLimits) -> Self {
+        Self {
+            limits,
+            activity: Default::default(),
+        }
+    }
+
+    /// Copy of the monitor covering only activity of `clients`, to check transactions
+    /// against it without recording them here.
+    pub fn staged<'a>(&self, clients: impl IntoIterator<Item = &'a ClientId>) -> Self {
+        let activity = clients
+            .into_iter()
+            .filter_map(|client_id| Some((*client_id, self.activity.get(client_id)?.clone())))
+            .collect();
+        Self {
+            limits: self.limits.clone(),
+            activity,
+        }
+    }
+
+    /// Checks transaction against every rule, reporting the first one it violates.
+    pub fn check(&mut self, tx: &TransactionDTO, now: Timestamp) -> Result<(), EngineError> {
+        let (TxKind::Withdrawal, Some(amount)) = (tx.kind, tx.amount) else {
+            return Ok(());
+        };
+        if self.limits.is_empty() {
+            return Ok(());
+        }
+        let longest_window = self.limits.longest_window();
+        let activity = self.activity.entry(tx.client_id).or_default();
+        while let Some((at, _)) = activity.withdrawals.front()
+            && now.saturating_sub(*at) >= longest_window
+        {
+            activity.withdrawals.pop_front();
+        }
+
+        let within = |window: u64| {
+            activity
+                .withdrawals
+                .iter()
+                .filter(move |(at, _)| now.saturating_sub(*at) < window)
+        };
+        let on_hold = |hold: u64| {
+            activity
+                .last_large_deposit
+                .is_some_and(|deposited_at| now.saturating_sub(deposited_at) < hold)
+        };
+        for rule in &self.limits.rules {
+            match *rule {
+                RiskRule::WithdrawalCap(cap) if amount > cap => {
+                    return Err(EngineError::Risk_WithdrawalCapExceeded);
+                }
+                RiskRule::WithdrawalVolume { limit, window }
+                    if within(window).map(|(_, amount)| *amount).sum::<TxAmount>() + amount
+                        > limit =>
+                {
+                    return Err(EngineError::Risk_WithdrawalVolumeExceeded);
+                }
+                RiskRule::WithdrawalCount { limit, window } if within(window).count() >= limit => {
+                    return Err(EngineError::Risk_WithdrawalCountExceeded);
+                }
+                RiskRule::LargeDepositHold { hold, .. } if on_hold(hold) => {
+                    return Err(EngineError::Risk_WithdrawalHeldAfterLargeDeposit);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes recent activity of the client out of the monitor.
+    pub fn take(&mut self, client_id: &ClientId) -> Option<ClientActivity> {
+        self.activity.remove(client_id)
+    }
+
+    pub fn restore(&mut self, client_id: ClientId, activity: ClientActivity) {
+        self.activity.insert(client_id, activity);
+    }
+
+    /// Remembers applied adjustment for rules looking at client's history.
+    pub fn record(&mut self, adjustment: &Adjustment, now: Timestamp) {
+        if self.limits.is_empty() {
+            return;
+        }
+        let amount = adjustment.amount;
+        let activity = self.activity.entry(adjustment.details.client_id);
+        match adjustment.category {
+            AdjustmentKind::Withdrawal => {
+                activity.or_default().withdrawals.push_back((now, amount));
+            }
+            AdjustmentKind::Deposit => {
+                let large = self.limits.rules.iter().any(|rule| {
+                    matches!(rule, RiskRule::LargeDepositHold { threshold, .. } if amount >= *threshold)
+                });
+                if large {
+                    activity.or_default().last_large_deposit = Some(now);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        EngineError,
+        clock::Clock,
+        core::{account::Account, tx_resolver::TxResolver},
+        objects::{ClientId, RawTransactionId, TransactionDTO, TxAmount, TxKind},
+    };
+
+    use super::RiskLimits;
+
+    fn tx(id: RawTransactionId, kind: TxKind, amount: f64) -> TransactionDTO {
+        TransactionDTO::new(id, 1, kind, Some(amount))
+    }
+
+    #[test]
+    fn each_violated_rule_rejects_withdrawal() {
+        let limits = RiskLimits::parse(
+            "withdrawal_cap, 100
+            withdrawal_volume, 110, 86400
+            withdrawal_count, 2, 3600
+            large_deposit_hold, 1000, 600",
+        )
+        .unwrap();
+        let clock = Clock::simulated(0);
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new()
+            .with_clock(clock.clone())
+            .with_risk_limits(limits);
+        let mut apply = |tx| resolver.apply_adjustment(tx, &mut account);
+
+        assert!(apply(tx(1, TxKind::Deposit, 1000.0)).is_ok());
+        assert!(matches!(
+            apply(tx(2, TxKind::Withdrawal, 10.0)),
+            Err(EngineError::Risk_WithdrawalHeldAfterLargeDeposit)
+        ));
+        clock.advance(600);
+        assert!(matches!(
+            apply(tx(3, TxKind::Withdrawal, 101.0)),
+            Err(EngineError::Risk_WithdrawalCapExceeded)
+        ));
+        assert!(apply(tx(4, TxKind::Withdrawal, 10.0)).is_ok());
+        assert!(apply(tx(5, TxKind::Withdrawal, 10.0)).is_ok());
+        assert!(matches!(
+            apply(tx(6, TxKind::Withdrawal, 10.0)),
+            Err(EngineError::Risk_WithdrawalCountExceeded)
+        ));
+        clock.advance(3600);
+        assert!(matches!(
+            apply(tx(7, TxKind::Withdrawal, 100.0)),
+            Err(EngineError::Risk_WithdrawalVolumeExceeded)
+        ));
+        assert!(apply(tx(8, TxKind::Withdrawal, 80.0)).is_ok());
+        assert_eq!(account.available, TxAmount::from_f64(900.0));
+    }
+
+    #[test]
+    fn windows_pass_and_rejected_withdrawals_dont_count() {
+        let limits = RiskLimits::parse(
+            "withdrawal_cap, 100
+            withdrawal_count, 2, 60",
+        )
+        .unwrap();
+        let clock = Clock::simulated(0);
+        let mut account = Account::new(ClientId(1));
+        let mut resolver = TxResolver::new()
+            .with_clock(clock.clone())
+            .with_risk_limits(limits);
+        let mut apply = |tx| resolver.apply_adjustment(tx, &mut account);
+
+        // deposits aren't limited
+        assert!(apply(tx(1, TxKind::Deposit, 1000.0)).is_ok());
+        assert!(matches!(
+            apply(tx(2, TxKind::Withdrawal, 500.0)),
+            Err(EngineError::Risk_WithdrawalCapExceeded)
+        ));
+        assert!(apply(tx(3, TxKind::Withdrawal, 10.0)).is_ok());
+        assert!(apply(tx(4, TxKind::Withdrawal, 10.0)).is_ok());
+        assert!(matches!(
+            apply(tx(5, TxKind::Withdrawal, 10.0)),
+            Err(EngineError::Risk_WithdrawalCountExceeded)
+        ));
+        clock.advance(59);
+        assert!(apply(tx(6, TxKind::Withdrawal, 10.0)).is_err());
+        clock.advance(1);
+        assert!(apply(tx(7, TxKind::Withdrawal, 10.0)).is_ok());
+        assert_eq!(account.available, TxAmount::from_f64(970.0));
+    }
+
+    #[test]
+    fn malformed_rules_are_rejected() {
+        for rules in [
+            "withdrawal_cap",
+            "withdrawal_cap, lots",
+            "withdrawal_volume, 100",
+            "withdrawal_count, -1, 60",
+            "deposit_cap, 100",
+        ] {
+            assert!(RiskLimits::parse(rules).is_err(), "{rules}");
+        }
+    }
+}
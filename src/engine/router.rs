@@ -0,0 +1,704 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use super::{
+    admin::AdminCommand,
+    batch::{BatchAssembler, BatchId, InputUnit, PositionedTransaction},
+    core::account::Account,
+    objects::TransactionDTO,
+    processor::{ProcessorCommand, ProcessorConfig, ProcessorInput},
+    resharding::shard_of,
+    shutdown::Shutdown,
+    supervisor::Supervisor,
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct RouterConfig {
+    /// Check balances of every account against accepted transactions once input ends.
+    pub reconcile: bool,
+    /// Stops intake once requested, processors still finish transactions routed to them.
+    pub shutdown: Shutdown,
+    /// How long processors get to finish once input ends or intake stops, no limit by default.
+    pub drain_timeout: Option<Duration>,
+    /// How many times a failed processor is restarted before its clients are given up on,
+    /// none by default. Restarted processor resumes from its last checkpoint.
+    pub restart_attempts: u32,
+    /// Fingerprint of input, e.g. its path, size and modification time. Resuming from
+    /// checkpoints made over a different input is refused.
+    pub input: Option<String>,
+}
+
+pub struct RunOutcome {
+    /// Final state of accounts of processors which finished, ordered by client id.
+    pub accounts: Vec<Account>,
+    /// Processors which failed for good or didn't finish within drain timeout.
+    pub unfinished: Vec<u16>,
+    /// Intake was stopped by shutdown before input ended.
+    pub interrupted: bool,
+    /// Accounts reconciliation found diverging, or couldn't check, with `reconcile` set.
+    pub diverged: usize,
+    /// Checkpoints were made over a different input, so nothing was processed.
+    pub different_input: bool,
+}
+
+impl RunOutcome {
+    pub fn is_complete(&self) -> bool {
+        self.unfinished.is_empty()
+    }
+
+    /// Every processor finished and no account diverged.
+    pub fn is_successful(&self) -> bool {
+        self.is_complete() && self.diverged == 0 && !self.different_input
+    }
+}
+
+/// Spreads transactions over `instance_count` processors, partitioned by client.
+/// State is checkpointed with position of the last routed transaction, a rerun over
+/// the same input resumes right after it. On shutdown, batch still being assembled is dropped.
+/// Batch members may belong to clients of different processors; they are prepared on every
+/// involved processor first and committed only when all of them succeed.
+/// Admin commands are executed in between transactions, and after input ends until
+/// their channel closes. Failed processor doesn't hold up the others, see [`Supervisor`].
+pub async fn run_scaled(
+    instance_count: u16,
+    mut rx: UnboundedReceiver<TransactionDTO>,
+    mut admin: Option<UnboundedReceiver<AdminCommand>>,
+    config: ProcessorConfig,
+    router: RouterConfig,
+) -> RunOutcome {
+    let checkpoint_interval = config.effective_checkpoint_interval();
+    let mut supervisor = Supervisor::start(instance_count, config, router.restart_attempts);
+
+    if let Some(input) = &router.input
+        && !claim_input(supervisor.senders(), input).await
+    {
+        eprintln!(
+            "checkpoints were made over a different input or with a different number of processors, refusing to resume"
+        );
+        supervisor.abort();
+        return RunOutcome {
+            accounts: Vec::new(),
+            unfinished: Vec::new(),
+            interrupted: false,
+            diverged: 0,
+            different_input: true,
+        };
+    }
+
+    // every processor has applied input up to its last checkpoint, those ahead skip the rest
+    let (resume_after, applied_by_some) = last_checkpoints(supervisor.senders()).await;
+    if resume_after > 0 {
+        eprintln!("resuming after transaction {resume_after}, earlier ones are already applied");
+    }
+
+    let mut position = 0;
+    let mut batches = BatchAssembler::default();
+    let mut checkpoint_due = false;
+    let mut interrupted = false;
+    let shutdown = router.shutdown.requested();
+    tokio::pin!(shutdown);
+    loop {
+        let transaction = tokio::select! {
+            biased;
+            _ = &mut shutdown => {
+                interrupted = true;
+                break;
+            }
+            exit = supervisor.next_exit() => {
+                supervisor.recover(exit).await;
+                continue;
+            }
+            Some(command) = next_admin_command(&mut admin) => {
+                // clients can't move while processors skip input applied before restart
+                let routed = batches.first_pending().map_or(position, |first| first - 1);
+                let routed = (routed >= applied_by_some).then_some(routed);
+                administer(&mut supervisor, command, routed).await;
+                continue;
+            }
+            transaction = rx.recv() => transaction,
+        };
+        let Some(transaction) = transaction else {
+            break;
+        };
+        position += 1;
+        if position <= resume_after {
+            continue;
+        }
+        for unit in batches.push(transaction, position) {
+            route(&mut supervisor, unit).await;
+        }
+        if checkpoint_interval > 0 && position % checkpoint_interval == 0 {
+            checkpoint_due = true;
+        }
+        // checkpoint never splits a batch, so it's either replayed whole or not at all
+        if checkpoint_due && !batches.is_pending() {
+            supervisor.checkpoint(position);
+            checkpoint_due = false;
+        }
+    }
+    match interrupted {
+        true => {
+            // incomplete batch is dropped whole, rerun picks it up from its first member
+            if let Some(first) = batches.first_pending() {
+                position = first - 1;
+            }
+            eprintln!("intake stopped after transaction {position}");
+        }
+        false => {
+            if let Some(unit) = batches.finish() {
+                route(&mut supervisor, unit).await;
+            }
+            // processors are kept running for admin commands until their source closes
+            let routed = (position >= applied_by_some).then_some(position);
+            while let Some(command) = next_admin_command(&mut admin).await {
+                administer(&mut supervisor, command, routed).await;
+            }
+        }
+    }
+    let diverged = match router.reconcile {
+        true => reconcile_instances(supervisor.senders()).await,
+        false => 0,
+    };
+    let (accounts, unfinished) = supervisor
+        .drain(position.max(resume_after), router.drain_timeout)
+        .await;
+    RunOutcome {
+        accounts,
+        unfinished,
+        interrupted,
+        diverged,
+        different_input: false,
+    }
+}
+
+fn bucket(senders: &[UnboundedSender<ProcessorInput>], transaction: &TransactionDTO) -> usize {
+    shard_of(&transaction.client_id, senders.len())
+}
+
+async fn route(supervisor: &mut Supervisor, unit: InputUnit) {
+    match unit {
+        InputUnit::Transaction(transaction, position) => {
+            let bucket = bucket(supervisor.senders(), &transaction);
+            supervisor.send(bucket, transaction, position);
+        }
+        InputUnit::Batch(batch_id, members) => {
+            if route_batch(supervisor.senders(), batch_id, &members).await {
+                supervisor.record(InputUnit::Batch(batch_id, members));
+            }
+        }
+    }
+}
+
+// two-phase commit: every involved processor validates its members, batch is committed
+// only when all of them succeed, otherwise it's aborted everywhere; tells if it was committed
+async fn route_batch(
+    senders: &[UnboundedSender<ProcessorInput>],
+    batch_id: BatchId,
+    members: &[PositionedTransaction],
+) -> bool {
+    let mut parts: BTreeMap<usize, Vec<PositionedTransaction>> = BTreeMap::new();
+    for member in members {
+        parts
+            .entry(bucket(senders, &member.0))
+            .or_default()
+            .push(member.clone());
+    }
+    let mut prepared = Vec::new();
+    for (bucket, part) in parts {
+        let (reply, results) = tokio::sync::oneshot::channel();
+        _ = senders[bucket].send(ProcessorInput::Command(ProcessorCommand::PrepareBatch(
+            part, reply,
+        )));
+        prepared.push((bucket, results));
+    }
+    let mut failures = Vec::new();
+    let mut unprepared = false;
+    for (bucket, results) in &mut prepared {
+        match results.await {
+            Ok(results) => failures.extend(
+                results
+                    .into_iter()
+                    .filter_map(|(tx_id, e)| Some((tx_id, e?))),
+            ),
+            Err(_) => {
+                eprintln!("processor {bucket}: batch {batch_id} wasn't prepared");
+                unprepared = true;
+            }
+        }
+    }
+    if unprepared || !failures.is_empty() {
+        for (bucket, _) in &prepared {
+            _ = senders[*bucket].send(ProcessorInput::Command(ProcessorCommand::AbortBatch));
+        }
+        for (tx_id, e) in failures {
+            eprintln!(
+                "batch {batch_id} rolled back: transaction {} failed with {e:?}",
+                tx_id.to_u64()
+            );
+        }
+        return false;
+    }
+    for (bucket, _) in prepared {
+        let (reply, results) = tokio::sync::oneshot::channel();
+        _ = senders[bucket].send(ProcessorInput::Command(ProcessorCommand::CommitBatch(
+            reply,
+        )));
+        for (tx_id, e) in results.await.unwrap_or_default() {
+            if let Some(e) = e {
+                eprintln!(
+                    "processor {bucket}: batch {batch_id} transaction {} failed on commit with {e:?}",
+                    tx_id.to_u64()
+                );
+            }
+        }
+    }
+    true
+}
+
+async fn next_admin_command(
+    admin: &mut Option<UnboundedReceiver<AdminCommand>>,
+) -> Option<AdminCommand> {
+    match admin {
+        Some(admin) => admin.recv().await,
+        None => None,
+    }
+}
+
+// executes command on processor of its client or the shard it names, dumps are printed on stderr;
+// resharding is done by the supervisor once input up to `routed` is routed
+async fn administer(supervisor: &mut Supervisor, command: AdminCommand, routed: Option<u64>) {
+    if let AdminCommand::Reshard { shards } = command {
+        let previous = supervisor.senders().len();
+        let result = match routed {
+            Some(position) => supervisor.reshard(shards, position).await,
+            None => Err("input applied before restart is still being skipped".to_string()),
+        };
+        match result {
+            Ok(moved) => {
+                eprintln!("resharded from {previous} to {shards} processors, {moved} clients moved")
+            }
+            Err(reason) => eprintln!("admin `{command}` rejected: {reason}"),
+        }
+        return;
+    }
+    let senders = supervisor.senders();
+    let bucket = match command.client_id() {
+        Some(client_id) => shard_of(&client_id, senders.len()),
+        None => match command.shard() {
+            Some(shard) if (shard as usize) < senders.len() => shard as usize,
+            _ => {
+                eprintln!("admin `{command}` rejected: no such shard");
+                return;
+            }
+        },
+    };
+    if let Ok(Ok(Some(dump))) = supervisor.administer(bucket, command).await {
+        for line in dump.to_lines() {
+            eprintln!("{line}");
+        }
+    }
+}
+
+// whether every processor accepted `input`, i.e. none checkpointed a different one
+// or with a different number of processors
+async fn claim_input(senders: &[UnboundedSender<ProcessorInput>], input: &str) -> bool {
+    let mut claimed = true;
+    for (instance_id, sender) in senders.iter().enumerate() {
+        let (reply, outcome) = tokio::sync::oneshot::channel();
+        _ = sender.send(ProcessorInput::Command(ProcessorCommand::ClaimInput(
+            input.to_string(),
+            senders.len() as u16,
+            reply,
+        )));
+        if let Ok(Err(e)) = outcome.await {
+            eprintln!("processor {instance_id}: checking input failed {e:?}");
+            claimed = false;
+        }
+    }
+    claimed
+}
+
+// lowest and highest of last checkpoints of processors
+async fn last_checkpoints(senders: &[UnboundedSender<ProcessorInput>]) -> (u64, u64) {
+    let mut positions = Vec::new();
+    for (instance_id, sender) in senders.iter().enumerate() {
+        let (reply, checkpoint) = tokio::sync::oneshot::channel();
+        _ = sender.send(ProcessorInput::Command(ProcessorCommand::LastCheckpoint(
+            reply,
+        )));
+        positions.push(match checkpoint.await {
+            Ok(Ok(position)) => position.unwrap_or(0),
+            Ok(Err(e)) => {
+                eprintln!("processor {instance_id}: reading checkpoint failed {e:?}");
+                0
+            }
+            Err(_) => 0,
+        });
+    }
+    let lowest = positions.iter().min().copied().unwrap_or(0);
+    (lowest, positions.into_iter().max().unwrap_or(0))
+}
+
+// reports diverging accounts on stderr, returning how many there are; processor which
+// failed to reconcile counts as one
+async fn reconcile_instances(senders: &[UnboundedSender<ProcessorInput>]) -> usize {
+    let mut diverged = 0;
+    for (instance_id, sender) in senders.iter().enumerate() {
+        let (reply, discrepancies) = tokio::sync::oneshot::channel();
+        _ = sender.send(ProcessorInput::Command(ProcessorCommand::Reconcile(reply)));
+        let discrepancies = match discrepancies.await {
+            Ok(Ok(discrepancies)) => discrepancies,
+            Ok(Err(e)) => {
+                eprintln!("processor {instance_id}: reconciliation failed {e:?}");
+                diverged += 1;
+                continue;
+            }
+            Err(_) => continue,
+        };
+        diverged += discrepancies.len();
+        for discrepancy in discrepancies {
+            eprintln!(
+                "processor {}: client {} diverges, expected total {} held {} reserved {}, actual total {} held {} reserved {}",
+                instance_id,
+                *discrepancy.client_id,
+                discrepancy.expected.total,
+                discrepancy.expected.held,
+                discrepancy.expected.reserved,
+                discrepancy.actual.total,
+                discrepancy.actual.held,
+                discrepancy.actual.reserved
+            );
+        }
+    }
+    diverged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    use crate::{
+        engine::{
+            admin::AdminCommand,
+            core::account::Account,
+            objects::{ClientId, TransactionDTO, TransactionId, TxKind},
+            processor::ProcessorConfig,
+            router::{RouterConfig, run_scaled},
+            screening::{TransactionScreen, Verdict},
+        },
+        generator::{WorkloadConfig, WorkloadGenerator},
+    };
+
+    #[tokio::test]
+    async fn shutdown_stops_intake() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        for id in 1..=3 {
+            sender
+                .send(TransactionDTO {
+                    batch_id: Some(1),
+                    ..TransactionDTO::new(id, 1, TxKind::Deposit, Some(10.0))
+                })
+                .unwrap();
+        }
+        let router = RouterConfig::default();
+        let shutdown = router.shutdown.clone();
+        let run = tokio::spawn(run_scaled(
+            2,
+            receiver,
+            None,
+            ProcessorConfig::default(),
+            router,
+        ));
+        // input stays open, only shutdown ends the run
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        shutdown.request();
+        let outcome = run.await.unwrap();
+
+        assert!(outcome.interrupted);
+        assert!(outcome.is_complete());
+        // incomplete batch isn't applied
+        assert!(outcome.accounts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn shutdown_wins_over_queued_input() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        sender
+            .send(TransactionDTO::new(1, 1, TxKind::Deposit, Some(10.0)))
+            .unwrap();
+        drop(sender);
+        let router = RouterConfig::default();
+        router.shutdown.request();
+        let outcome = run_scaled(2, receiver, None, ProcessorConfig::default(), router).await;
+
+        // shutdown wins over input still queued, nothing is taken in
+        assert!(outcome.interrupted);
+        assert!(outcome.is_complete());
+        assert!(outcome.accounts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn generated_fractional_amounts_reconcile() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let workload = WorkloadGenerator::new(WorkloadConfig {
+            clients: 5,
+            transactions: 20_000,
+            seed: 7,
+            ..Default::default()
+        });
+        for tx in workload {
+            sender.send(tx).unwrap();
+        }
+        drop(sender);
+        let config = ProcessorConfig {
+            double_entry: true,
+            ..Default::default()
+        };
+        let router = RouterConfig {
+            reconcile: true,
+            ..Default::default()
+        };
+        let outcome = run_scaled(2, receiver, None, config, router).await;
+
+        assert_eq!(outcome.accounts.len(), 5);
+        assert_eq!(outcome.diverged, 0);
+        assert!(outcome.is_successful());
+    }
+
+    // holds transaction of client 1 until released, or until its sender is dropped
+    #[derive(Debug)]
+    struct BlockingScreen(Mutex<std::sync::mpsc::Receiver<()>>);
+
+    impl TransactionScreen for BlockingScreen {
+        fn screen(&self, tx: &TransactionDTO, _: &Account) -> Verdict {
+            if tx.client_id == ClientId(1) {
+                _ = self.0.lock().unwrap().recv();
+            }
+            Verdict::Approve
+        }
+    }
+
+    // the busy processor blocks a worker thread of its own
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn processor_busy_past_drain_timeout_is_unfinished() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        for (id, client_id) in [(1, 1), (2, 2)] {
+            sender
+                .send(TransactionDTO::new(
+                    id,
+                    client_id,
+                    TxKind::Deposit,
+                    Some(10.0),
+                ))
+                .unwrap();
+        }
+        drop(sender);
+        let (release, released) = std::sync::mpsc::channel();
+        let config = ProcessorConfig {
+            screen: Some(Arc::new(BlockingScreen(Mutex::new(released)))),
+            ..Default::default()
+        };
+        let router = RouterConfig {
+            drain_timeout: Some(std::time::Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let outcome = run_scaled(2, receiver, None, config, router).await;
+        _ = release.send(());
+
+        assert!(!outcome.interrupted);
+        assert_eq!(outcome.unfinished, vec![1]);
+        let clients: Vec<_> = outcome
+            .accounts
+            .iter()
+            .map(|account| account.client_id)
+            .collect();
+        assert_eq!(clients, vec![ClientId(2)]);
+    }
+
+    // panics on the first transaction it sees with id 2
+    #[derive(Debug, Default)]
+    struct PanickingScreen(AtomicBool);
+
+    impl TransactionScreen for PanickingScreen {
+        fn screen(&self, tx: &TransactionDTO, _: &Account) -> Verdict {
+            if tx.id == TransactionId(2) && !self.0.swap(true, Ordering::SeqCst) {
+                panic!("screen failed");
+            }
+            Verdict::Approve
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_processor_is_restarted() {
+        for restart_attempts in [0, 1] {
+            let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+            for (id, client_id) in [(1, 1), (2, 1), (3, 2), (4, 1)] {
+                sender
+                    .send(TransactionDTO::new(
+                        id,
+                        client_id,
+                        TxKind::Deposit,
+                        Some(10.0),
+                    ))
+                    .unwrap();
+            }
+            drop(sender);
+            let config = ProcessorConfig {
+                screen: Some(Arc::new(PanickingScreen::default())),
+                ..Default::default()
+            };
+            let router = RouterConfig {
+                restart_attempts,
+                ..Default::default()
+            };
+            let outcome = run_scaled(2, receiver, None, config, router).await;
+
+            let clients: Vec<_> = outcome
+                .accounts
+                .iter()
+                .map(|account| (account.client_id, account.available.to_f64()))
+                .collect();
+            match restart_attempts {
+                // client 1 is given up on, client 2 is still processed
+                0 => {
+                    assert_eq!(outcome.unfinished, vec![1]);
+                    assert_eq!(clients, vec![(ClientId(2), 10.0)]);
+                }
+                _ => {
+                    assert!(outcome.is_complete());
+                    assert_eq!(clients, vec![(ClientId(1), 30.0), (ClientId(2), 10.0)]);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn restarted_processor_replays_admin_commands() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (admin, commands) = tokio::sync::mpsc::unbounded_channel();
+        // admin commands are taken ahead of queued input
+        admin
+            .send(AdminCommand::parse("correct, 1, 5, goodwill").unwrap())
+            .unwrap();
+        drop(admin);
+        for id in [1, 2] {
+            sender
+                .send(TransactionDTO::new(id, 1, TxKind::Deposit, Some(10.0)))
+                .unwrap();
+        }
+        drop(sender);
+        let config = ProcessorConfig {
+            screen: Some(Arc::new(PanickingScreen::default())),
+            ..Default::default()
+        };
+        let router = RouterConfig {
+            restart_attempts: 1,
+            ..Default::default()
+        };
+        let outcome = run_scaled(2, receiver, Some(commands), config, router).await;
+
+        assert!(outcome.is_complete());
+        assert_eq!(outcome.accounts[0].available.to_f64(), 25.0);
+    }
+
+    // reports every transaction it sees
+    #[derive(Debug)]
+    struct NotifyingScreen(tokio::sync::mpsc::UnboundedSender<TransactionId>);
+
+    impl TransactionScreen for NotifyingScreen {
+        fn screen(&self, tx: &TransactionDTO, _: &Account) -> Verdict {
+            _ = self.0.send(tx.id);
+            Verdict::Approve
+        }
+    }
+
+    #[tokio::test]
+    async fn resharding_moves_clients() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (admin, commands) = tokio::sync::mpsc::unbounded_channel();
+        let (seen, mut screened) = tokio::sync::mpsc::unbounded_channel();
+        let tx = |id, client_id, kind, amount, sequence_number| TransactionDTO {
+            sequence_number,
+            ..TransactionDTO::new(id, client_id, kind, amount)
+        };
+        let run = tokio::spawn(run_scaled(
+            2,
+            receiver,
+            Some(commands),
+            ProcessorConfig {
+                double_entry: true,
+                screen: Some(Arc::new(NotifyingScreen(seen))),
+                ..Default::default()
+            },
+            RouterConfig::default(),
+        ));
+        // waits for its predecessor, which arrives after resharding
+        sender
+            .send(tx(30, 5, TxKind::Withdrawal, Some(20.0), Some(2)))
+            .unwrap();
+        for (id, client_id) in (1..).zip(1..=6) {
+            sender
+                .send(tx(id, client_id, TxKind::Deposit, Some(100.0), None))
+                .unwrap();
+            let next = match client_id % 2 {
+                1 => tx(id + 10, client_id, TxKind::Withdrawal, Some(30.0), None),
+                _ => tx(id, client_id, TxKind::Dispute, None, None),
+            };
+            sender.send(next).unwrap();
+        }
+        // everything sent so far is routed once the last of it is screened
+        for _ in 0..12 {
+            screened.recv().await.unwrap();
+        }
+        // admin commands are taken ahead of queued input, so resharding comes first
+        admin
+            .send(AdminCommand::parse("reshard, 3").unwrap())
+            .unwrap();
+        for (id, client_id, kind, amount, sequence_number) in [
+            (31, 5, TxKind::Deposit, Some(50.0), Some(1)),
+            (2, 2, TxKind::Resolve, None, None),
+            (4, 4, TxKind::Chargeback, None, None),
+        ] {
+            sender
+                .send(tx(id, client_id, kind, amount, sequence_number))
+                .unwrap();
+        }
+        for (id, client_id) in (21..).zip(1..=6) {
+            sender
+                .send(tx(id, client_id, TxKind::Deposit, Some(5.0), None))
+                .unwrap();
+        }
+        drop(sender);
+        drop(admin);
+        let outcome = run.await.unwrap();
+
+        assert!(outcome.is_complete());
+        let clients: Vec<_> = outcome
+            .accounts
+            .iter()
+            .map(|account| {
+                (
+                    *account.client_id,
+                    account.available.to_f64(),
+                    account.held.to_f64(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            clients,
+            vec![
+                (1, 75.0, 0.0),
+                (2, 105.0, 0.0),
+                (3, 75.0, 0.0),
+                (4, 0.0, 0.0),
+                (5, 105.0, 0.0),
+                (6, 5.0, 100.0),
+            ]
+        );
+        assert!(outcome.accounts[3].locked);
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use super::{
+    EngineError,
+    clock::Timestamp,
+    objects::{ClientId, TransactionDTO, TransactionId},
+    storage::ScheduleStore,
+};
+
+/// Future-dated transactions ordered by effective time, then by order they were scheduled in.
+/// Every change is written through to the store, so pending transactions survive restarts.
+pub struct Schedule {
+    store: Box<dyn ScheduleStore>,
+    pending: BTreeMap<(Timestamp, u64), TransactionDTO>,
+    next_sequence: u64,
+}
+
+impl Schedule {
+    /// Picks up transactions already pending in `store`.
+    pub fn new(store: Box<dyn ScheduleStore>) -> Result<Self, EngineError> {
+        let pending: BTreeMap<_, _> = store
+            .pending()?
+            .into_iter()
+            .map(|(sequence, tx)| ((tx.effective_at.unwrap_or_default(), sequence), tx))
+            .collect();
+        let next_sequence = pending
+            .keys()
+            .map(|(_, sequence)| sequence + 1)
+            .max()
+            .unwrap_or(0);
+        Ok(Self {
+            store,
+            pending,
+            next_sequence,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn schedule(&mut self, tx: TransactionDTO) -> Result<(), EngineError> {
+        let sequence = self.next_sequence;
+        self.store.insert(sequence, tx.clone())?;
+        self.pending
+            .insert((tx.effective_at.unwrap_or_default(), sequence), tx);
+        self.next_sequence += 1;
+        Ok(())
+    }
+
+    /// Drops every pending transaction of the client with `tx_id`.
+    pub fn cancel(
+        &mut self,
+        tx_id: &TransactionId,
+        client_id: &ClientId,
+    ) -> Result<(), EngineError> {
+        let cancelled: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, tx)| tx.id == *tx_id && tx.client_id == *client_id)
+            .map(|(key, _)| *key)
+            .collect();
+        if cancelled.is_empty() {
+            return Err(EngineError::Schedule_TransactionNotScheduled);
+        }
+        for key in cancelled {
+            self.store.remove(key.1)?;
+            self.pending.remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Clients with pending transactions.
+    pub fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.pending.values().map(|tx| tx.client_id)
+    }
+
+    /// Takes every pending transaction of the client out of the schedule.
+    pub fn take_client(
+        &mut self,
+        client_id: &ClientId,
+    ) -> Result<Vec<TransactionDTO>, EngineError> {
+        let taken: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, tx)| tx.client_id == *client_id)
+            .map(|(key, _)| *key)
+            .collect();
+        let mut transactions = Vec::new();
+        for key in taken {
+            self.store.remove(key.1)?;
+            transactions.extend(self.pending.remove(&key));
+        }
+        Ok(transactions)
+    }
+
+    pub fn next_due(&self) -> Option<Timestamp> {
+        self.pending
+            .keys()
+            .next()
+            .map(|(effective_at, _)| *effective_at)
+    }
+
+    /// Takes the earliest transaction whose time has come by `now`.
+    pub fn take_due(&mut self, now: Timestamp) -> Result<Option<TransactionDTO>, EngineError> {
+        match self.pending.first_entry() {
+            Some(entry) if entry.key().0 <= now => {
+                self.store.remove(entry.key().1)?;
+                Ok(Some(entry.remove()))
+            }
+            _ => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::{collections::VecDeque, fmt::Debug};
+
+use super::{
+    Account, EngineError,
+    objects::{ClientId, TransactionDTO, TransactionId},
+};
+
+/// Decision of a `TransactionScreen` about a single transaction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    Approve,
+    Reject(&'static str),
+    /// Park transaction until it's approved or rejected by an admin command.
+    Review,
+}
+
+/// Custom risk logic consulted before every transaction is processed.
+/// One screen is shared by all processors, so it can't rely on seeing every client.
+pub trait TransactionScreen: Debug + Send + Sync {
+    fn screen(&self, tx: &TransactionDTO, account: &Account) -> Verdict;
+}
+
+/// Transactions waiting for manual review, in order they arrived.
+#[derive(Default)]
+pub struct ReviewQueue {
+    pending: VecDeque<TransactionDTO>,
+}
+
+impl ReviewQueue {
+    pub fn push(&mut self, tx: TransactionDTO) {
+        self.pending.push_back(tx);
+    }
+
+    pub fn pending(&self) -> Vec<TransactionDTO> {
+        self.pending.iter().cloned().collect()
+    }
+
+    /// Takes the oldest pending transaction with `tx_id` out of the queue.
+    /// Dispute and its resolutions share id with the disputed adjustment.
+    pub fn take(&mut self, tx_id: &TransactionId) -> Result<TransactionDTO, EngineError> {
+        self.take_where(|tx| tx.id == *tx_id)
+    }
+
+    /// Like [`Self::take`], but only a transaction of `client_id`.
+    pub fn take_of(
+        &mut self,
+        client_id: &ClientId,
+        tx_id: &TransactionId,
+    ) -> Result<TransactionDTO, EngineError> {
+        self.take_where(|tx| tx.id == *tx_id && tx.client_id == *client_id)
+    }
+
+    fn take_where(
+        &mut self,
+        matches: impl Fn(&TransactionDTO) -> bool,
+    ) -> Result<TransactionDTO, EngineError> {
+        let index = self
+            .pending
+            .iter()
+            .position(matches)
+            .ok_or(EngineError::Screening_TransactionNotUnderReview)?;
+        Ok(self
+            .pending
+            .remove(index)
+            .expect("index of pending transaction"))
+    }
+
+    /// Takes every pending transaction of the client out of the queue.
+    pub fn take_client(&mut self, client_id: &ClientId) -> Vec<TransactionDTO> {
+        let (taken, kept): (VecDeque<_>, _) = self
+            .pending
+            .drain(..)
+            .partition(|tx| tx.client_id == *client_id);
+        self.pending = kept;
+        taken.into()
+    }
+
+    pub fn drain(&mut self) -> Vec<TransactionDTO> {
+        self.pending.drain(..).collect()
+    }
+}
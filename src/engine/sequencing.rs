@@ -0,0 +1,260 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    EngineError,
+    clock::Timestamp,
+    objects::{ClientId, TransactionDTO},
+    storage::SequenceStore,
+};
+
+#[derive(Clone, Debug)]
+pub struct SequencingConfig {
+    /// Seconds to wait for a missing sequence number before skipping it.
+    pub timeout: u64,
+    /// Most transactions buffered per client, a gap is skipped right away when exceeded.
+    pub window: usize,
+}
+
+/// Sequence numbers of a client which never arrived.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SequenceGap {
+    pub client_id: ClientId,
+    pub first_missing: u64,
+    pub last_missing: u64,
+}
+
+/// Transactions ready to be processed, in sequence order, and gaps skipped to reach them.
+#[derive(Default)]
+pub struct Released {
+    pub ready: Vec<TransactionDTO>,
+    pub gaps: Vec<SequenceGap>,
+}
+
+struct ClientSequence {
+    next: u64,
+    // buffered transactions with time they arrived at
+    buffered: BTreeMap<u64, (TransactionDTO, Timestamp)>,
+}
+
+/// Puts sequenced transactions of every client back in order. Transactions arriving ahead
+/// of their turn wait for the missing ones, until timeout or buffer limit skips the gap.
+/// Transactions without sequence number pass through.
+pub struct Sequencer {
+    store: Box<dyn SequenceStore>,
+    config: SequencingConfig,
+    clients: HashMap<ClientId, ClientSequence>,
+}
+
+impl Default for SequencingConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 5,
+            window: 64,
+        }
+    }
+}
+
+impl ClientSequence {
+    fn take_consecutive(&mut self, released: &mut Released) {
+        while let Some((tx, _)) = self.buffered.remove(&self.next) {
+            released.ready.push(tx);
+            self.next += 1;
+        }
+    }
+
+    fn skip_gap(&mut self, client_id: ClientId, released: &mut Released) {
+        if let Some(first_buffered) = self.buffered.keys().next().copied() {
+            released.gaps.push(SequenceGap {
+                client_id,
+                first_missing: self.next,
+                last_missing: first_buffered - 1,
+            });
+            self.next = first_buffered;
+            self.take_consecutive(released);
+        }
+    }
+
+    fn waiting_since(&self) -> Option<Timestamp> {
+        self.buffered
+            .values()
+            .map(|(_, arrived_at)| *arrived_at)
+            .min()
+    }
+}
+
+impl Sequencer {
+    /// Picks up transactions buffered in `store`, their wait starts over at `now`.
+    pub fn new(
+        store: Box<dyn SequenceStore>,
+        config: SequencingConfig,
+        now: Timestamp,
+    ) -> Result<Self, EngineError> {
+        let mut sequencer = Self {
+            store,
+            config,
+            clients: HashMap::new(),
+        };
+        for tx in sequencer.store.buffered()? {
+            let sequence_number = tx.sequence_number.unwrap_or_default();
+            sequencer
+                .client(&tx.client_id)?
+                .buffered
+                .insert(sequence_number, (tx, now));
+        }
+        Ok(sequencer)
+    }
+
+    /// Admits transaction, releasing it together with buffered ones following it when it's
+    /// its turn. Sequence number already seen is rejected.
+    pub fn admit(&mut self, tx: TransactionDTO, now: Timestamp) -> Result<Released, EngineError> {
+        let Some(sequence_number) = tx.sequence_number else {
+            return Ok(Released {
+                ready: vec![tx],
+                gaps: Vec::new(),
+            });
+        };
+        let window = self.config.window;
+        let client_id = tx.client_id;
+        let client = self.client(&client_id)?;
+        if sequence_number < client.next || client.buffered.contains_key(&sequence_number) {
+            return Err(EngineError::Sequence_DuplicateSequenceNumber);
+        }
+        let mut released = Released::default();
+        if sequence_number == client.next {
+            released.ready.push(tx);
+            client.next += 1;
+        } else {
+            self.store.buffer(tx.clone())?;
+            let client = self.client(&client_id)?;
+            client.buffered.insert(sequence_number, (tx, now));
+            if client.buffered.len() > window {
+                client.skip_gap(client_id, &mut released);
+            }
+        }
+        let client = self.client(&client_id)?;
+        client.take_consecutive(&mut released);
+        self.persist(&released)?;
+        Ok(released)
+    }
+
+    /// Skips gaps which have been waited for longer than timeout.
+    pub fn release_stale(&mut self, now: Timestamp) -> Result<Released, EngineError> {
+        let timeout = self.config.timeout;
+        let mut released = Released::default();
+        for (client_id, client) in self.clients.iter_mut() {
+            if client
+                .waiting_since()
+                .is_some_and(|since| now.saturating_sub(since) >= timeout)
+            {
+                client.skip_gap(*client_id, &mut released);
+            }
+        }
+        self.persist(&released)?;
+        Ok(released)
+    }
+
+    /// Skips every gap, e.g. once input ends and missing transactions can't arrive anymore.
+    pub fn release_all(&mut self) -> Result<Released, EngineError> {
+        let mut released = Released::default();
+        for (client_id, client) in self.clients.iter_mut() {
+            while !client.buffered.is_empty() {
+                client.skip_gap(*client_id, &mut released);
+            }
+        }
+        self.persist(&released)?;
+        Ok(released)
+    }
+
+    /// Time at which the longest waiting gap times out.
+    pub fn next_deadline(&self) -> Option<Timestamp> {
+        self.clients
+            .values()
+            .filter_map(ClientSequence::waiting_since)
+            .min()
+            .map(|since| since + self.config.timeout)
+    }
+
+    /// Clients seen since start, or with buffered transactions.
+    pub fn clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.clients.keys().copied()
+    }
+
+    /// Takes next expected number and buffered transactions of the client out of the sequencer.
+    pub fn take_client(
+        &mut self,
+        client_id: &ClientId,
+    ) -> Result<(Option<u64>, Vec<TransactionDTO>), EngineError> {
+        let taken = match self.clients.remove(client_id) {
+            Some(client) => (
+                Some(client.next),
+                client.buffered.into_values().map(|(tx, _)| tx).collect(),
+            ),
+            None => (self.store.next_expected(client_id)?, Vec::new()),
+        };
+        self.store.remove_client(client_id)?;
+        Ok(taken)
+    }
+
+    /// Takes over client taken out of another sequencer, buffered transactions wait from `now`.
+    pub fn restore_client(
+        &mut self,
+        client_id: ClientId,
+        next: Option<u64>,
+        buffered: Vec<TransactionDTO>,
+        now: Timestamp,
+    ) -> Result<(), EngineError> {
+        let Some(next) = next else {
+            return Ok(());
+        };
+        self.store.set_next_expected(client_id, next)?;
+        for tx in &buffered {
+            self.store.buffer(tx.clone())?;
+        }
+        self.clients.insert(
+            client_id,
+            ClientSequence {
+                next,
+                buffered: buffered
+                    .into_iter()
+                    .map(|tx| (tx.sequence_number.unwrap_or_default(), (tx, now)))
+                    .collect(),
+            },
+        );
+        Ok(())
+    }
+
+    fn client(&mut self, client_id: &ClientId) -> Result<&mut ClientSequence, EngineError> {
+        if !self.clients.contains_key(client_id) {
+            let next = self.store.next_expected(client_id)?.unwrap_or(1);
+            self.clients.insert(
+                *client_id,
+                ClientSequence {
+                    next,
+                    buffered: BTreeMap::new(),
+                },
+            );
+        }
+        Ok(self
+            .clients
+            .get_mut(client_id)
+            .expect("client inserted above"))
+    }
+
+    // writes released transactions and new next expected numbers through to the store
+    fn persist(&mut self, released: &Released) -> Result<(), EngineError> {
+        let mut client_ids = Vec::new();
+        for tx in &released.ready {
+            if let Some(sequence_number) = tx.sequence_number {
+                self.store.unbuffer(&tx.client_id, sequence_number)?;
+            }
+            if !client_ids.contains(&tx.client_id) {
+                client_ids.push(tx.client_id);
+            }
+        }
+        for client_id in client_ids {
+            let next = self.client(&client_id)?.next;
+            self.store.set_next_expected(client_id, next)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Request to stop taking input and finish what's already routed. Clones share the request.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    requested: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            requested: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn request(&self) {
+        self.requested.send_replace(true);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Completes once shutdown is requested.
+    pub async fn requested(&self) {
+        let mut requested = self.requested.subscribe();
+        // sender is kept by `self`, so waiting can't fail
+        _ = requested.wait_for(|requested| *requested).await;
+    }
+
+    /// Requests shutdown on Ctrl-C, or SIGTERM on Unix.
+    pub fn on_signals(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            #[cfg(unix)]
+            {
+                use tokio::signal::unix::{SignalKind, signal};
+                let mut terminate =
+                    signal(SignalKind::terminate()).expect("can't listen for SIGTERM");
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            #[cfg(not(unix))]
+            {
+                _ = tokio::signal::ctrl_c().await;
+            }
+            eprintln!("shutdown requested, draining processors");
+            shutdown.request();
+        });
+    }
+}
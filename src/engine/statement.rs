@@ -0,0 +1,380 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use super::{
+    clock::Timestamp,
+    core::account::Account,
+    objects::{ClientId, TransactionId, TxAmount, TxKind},
+    references::TransactionReferences,
+};
+
+/// What changed balances of a client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HistoryEvent {
+    Transaction(TxKind),
+    AuthorizationExpired,
+    Interest,
+    MaintenanceFee,
+    Correction,
+    Freeze,
+    Unfreeze,
+}
+
+/// Change of client's balances, recorded for statements.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub at: Timestamp,
+    pub client_id: ClientId,
+    pub tx_id: Option<TransactionId>, // none for periodic jobs and corrections
+    pub event: HistoryEvent,
+    pub amount: Option<TxAmount>, // as in input
+    pub available: TxAmount,
+    pub held: TxAmount,
+    pub reserved: TxAmount,
+    pub fee: TxAmount, // charged, negative when refunded
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StatementBalance {
+    pub available: TxAmount,
+    pub held: TxAmount,
+    pub reserved: TxAmount,
+}
+
+/// Balances of a client at the start and end of period `[from, to)` and every change between.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub client_id: ClientId,
+    pub from: Timestamp,
+    pub to: Timestamp,
+    pub opening: StatementBalance,
+    pub entries: Vec<HistoryEntry>,
+    pub closing: StatementBalance,
+}
+
+impl fmt::Display for HistoryEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryEvent::Transaction(kind) => write!(f, "{kind}"),
+            HistoryEvent::AuthorizationExpired => write!(f, "authorizationExpired"),
+            HistoryEvent::Interest => write!(f, "interest"),
+            HistoryEvent::MaintenanceFee => write!(f, "maintenanceFee"),
+            HistoryEvent::Correction => write!(f, "correction"),
+            HistoryEvent::Freeze => write!(f, "freeze"),
+            HistoryEvent::Unfreeze => write!(f, "unfreeze"),
+        }
+    }
+}
+
+impl FromStr for HistoryEvent {
+    type Err = strum::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "authorizationExpired" => Ok(HistoryEvent::AuthorizationExpired),
+            "interest" => Ok(HistoryEvent::Interest),
+            "maintenanceFee" => Ok(HistoryEvent::MaintenanceFee),
+            "correction" => Ok(HistoryEvent::Correction),
+            "freeze" => Ok(HistoryEvent::Freeze),
+            "unfreeze" => Ok(HistoryEvent::Unfreeze),
+            kind => TxKind::from_str(kind).map(HistoryEvent::Transaction),
+        }
+    }
+}
+
+impl HistoryEntry {
+    /// Difference between account states before and after `event`.
+    pub fn between(
+        before: &Account,
+        after: &Account,
+        at: Timestamp,
+        tx_id: Option<TransactionId>,
+        event: HistoryEvent,
+        amount: Option<TxAmount>,
+    ) -> Self {
+        HistoryEntry {
+            at,
+            client_id: after.client_id,
+            tx_id,
+            event,
+            amount,
+            available: after.available - before.available,
+            held: after.held - before.held,
+            reserved: after.reserved - before.reserved,
+            fee: after.fees - before.fees,
+        }
+    }
+
+    pub fn changes_balances(&self) -> bool {
+        [self.available, self.held, self.reserved, self.fee]
+            .iter()
+            .any(|change| *change != TxAmount::ZERO)
+    }
+}
+
+impl StatementBalance {
+    pub fn total(&self) -> TxAmount {
+        self.available + self.held + self.reserved
+    }
+
+    fn apply(&mut self, entry: &HistoryEntry) {
+        self.available += entry.available;
+        self.held += entry.held;
+        self.reserved += entry.reserved;
+    }
+
+    fn to_csv(self) -> String {
+        format!(
+            "{},{},{},{}",
+            self.available,
+            self.held,
+            self.reserved,
+            self.total()
+        )
+    }
+
+    fn to_json(self) -> String {
+        format!(
+            r#"{{"available":{},"held":{},"reserved":{},"total":{}}}"#,
+            self.available,
+            self.held,
+            self.reserved,
+            self.total()
+        )
+    }
+}
+
+impl Statement {
+    /// Builds statement from client's history, given in order it was recorded.
+    pub fn new(
+        client_id: ClientId,
+        from: Timestamp,
+        to: Timestamp,
+        history: impl IntoIterator<Item = HistoryEntry>,
+    ) -> Self {
+        let mut statement = Statement {
+            client_id,
+            from,
+            to,
+            opening: StatementBalance::default(),
+            entries: Vec::new(),
+            closing: StatementBalance::default(),
+        };
+        for entry in history {
+            if entry.at < from {
+                statement.opening.apply(&entry);
+            } else if entry.at < to {
+                statement.entries.push(entry);
+            }
+        }
+        statement.closing = statement.opening;
+        for entry in &statement.entries {
+            statement.closing.apply(entry);
+        }
+        statement
+    }
+
+    /// Fees charged within the period, less refunded ones.
+    pub fn fees(&self) -> TxAmount {
+        self.entries.iter().map(|entry| entry.fee).sum()
+    }
+
+    /// Lines `statement,<client>,<from>,<to>`, `opening,<available>,<held>,<reserved>,<total>`,
+    /// `entry,<at>,<event>,<tx>,<amount>,<available>,<held>,<reserved>,<fee>` with changes
+    /// of balances, `closing` and `fees`.
+    pub fn to_csv(&self, references: &TransactionReferences) -> Vec<String> {
+        let header = format!("statement,{},{},{}", *self.client_id, self.from, self.to);
+        let entries = self.entries.iter().map(|entry| {
+            format!(
+                "entry,{},{},{},{},{},{},{},{}",
+                entry.at,
+                entry.event,
+                describe(entry.tx_id, references),
+                entry.amount.map(|a| a.to_string()).unwrap_or_default(),
+                entry.available,
+                entry.held,
+                entry.reserved,
+                entry.fee
+            )
+        });
+        std::iter::once(header)
+            .chain(std::iter::once(format!(
+                "opening,{}",
+                self.opening.to_csv()
+            )))
+            .chain(entries)
+            .chain(std::iter::once(format!(
+                "closing,{}",
+                self.closing.to_csv()
+            )))
+            .chain(std::iter::once(format!("fees,{}", self.fees())))
+            .collect()
+    }
+
+    pub fn to_json(&self, references: &TransactionReferences) -> String {
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    r#"{{"at":{},"event":"{}","tx":{},"amount":{},"available":{},"held":{},"reserved":{},"fee":{}}}"#,
+                    entry.at,
+                    entry.event,
+                    match entry.tx_id {
+                        Some(tx_id) => json_string(&references.describe(&tx_id)),
+                        None => "null".to_string(),
+                    },
+                    entry
+                        .amount
+                        .map_or("null".to_string(), |amount| amount.to_string()),
+                    entry.available,
+                    entry.held,
+                    entry.reserved,
+                    entry.fee
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            r#"{{"client":{},"from":{},"to":{},"opening":{},"entries":[{}],"closing":{},"fees":{}}}"#,
+            *self.client_id,
+            self.from,
+            self.to,
+            self.opening.to_json(),
+            entries,
+            self.closing.to_json(),
+            self.fees()
+        )
+    }
+}
+
+/// Statements of every client found in `history`, ordered by client id.
+pub fn statements(
+    history: impl IntoIterator<Item = HistoryEntry>,
+    from: Timestamp,
+    to: Timestamp,
+) -> Vec<Statement> {
+    let mut by_client: BTreeMap<_, Vec<HistoryEntry>> = BTreeMap::new();
+    for entry in history {
+        by_client.entry(*entry.client_id).or_default().push(entry);
+    }
+    by_client
+        .into_values()
+        .map(|history| Statement::new(history[0].client_id, from, to, history))
+        .collect()
+}
+
+fn describe(tx_id: Option<TransactionId>, references: &TransactionReferences) -> String {
+    tx_id
+        .map(|tx_id| references.describe(&tx_id))
+        .unwrap_or_default()
+}
+
+fn json_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        admin::AdminCommand,
+        clock::Clock,
+        objects::{TransactionDTO, TxKind},
+        processor::{ProcessorConfig, ProcessorImpl},
+        references::TransactionReferences,
+    };
+
+    #[test]
+    fn statement_covers_period() {
+        let clock = Clock::simulated(100);
+        let config = ProcessorConfig {
+            clock: clock.clone(),
+            history: true,
+            ..Default::default()
+        };
+        let mut processor = ProcessorImpl::new(0, config);
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+
+        assert!(
+            processor
+                .process(tx(1, TxKind::Deposit, Some(100.0)))
+                .is_ok()
+        );
+        clock.advance(100);
+        assert!(
+            processor
+                .process(tx(2, TxKind::Withdrawal, Some(30.0)))
+                .is_ok()
+        );
+        assert!(processor.process(tx(1, TxKind::Dispute, None)).is_ok());
+        assert!(
+            processor
+                .process(tx(3, TxKind::Withdrawal, Some(500.0)))
+                .is_err()
+        );
+        clock.advance(100);
+        assert!(processor.process(tx(1, TxKind::Resolve, None)).is_ok());
+
+        let statements = processor.statements(None, 200, 300).unwrap();
+        let lines = statements[0].to_csv(&TransactionReferences::default());
+        assert_eq!(
+            lines,
+            [
+                "statement,1,200,300",
+                "opening,100.0000,0.0000,0.0000,100.0000",
+                "entry,200,withdrawal,2,30.0000,-30.0000,0.0000,0.0000,0.0000",
+                "entry,200,dispute,1,,-100.0000,100.0000,0.0000,0.0000",
+                "closing,-30.0000,100.0000,0.0000,70.0000",
+                "fees,0.0000",
+            ]
+        );
+        assert!(
+            statements[0]
+                .to_json(&TransactionReferences::default())
+                .starts_with(r#"{"client":1,"from":200,"to":300,"opening":{"available":100.0000"#)
+        );
+    }
+
+    #[test]
+    fn events_leaving_balances_unchanged_are_recorded() {
+        let config = ProcessorConfig {
+            clock: Clock::simulated(100),
+            history: true,
+            ..Default::default()
+        };
+        let mut processor = ProcessorImpl::new(0, config);
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+
+        assert!(
+            processor
+                .process(tx(1, TxKind::Deposit, Some(100.0)))
+                .is_ok()
+        );
+        assert!(
+            processor
+                .process(tx(2, TxKind::Withdrawal, Some(30.0)))
+                .is_ok()
+        );
+        assert!(processor.process(tx(2, TxKind::Dispute, None)).is_ok());
+        assert!(processor.process(tx(2, TxKind::Resolve, None)).is_ok());
+        let scheduled = TransactionDTO {
+            effective_at: Some(500),
+            ..tx(3, TxKind::Deposit, Some(5.0))
+        };
+        assert!(processor.process(scheduled).is_ok());
+        assert!(processor.process(tx(3, TxKind::Cancel, None)).is_ok());
+        let freeze = AdminCommand::parse("freeze, 1, fraud-review").unwrap();
+        assert!(processor.administer(freeze).is_ok());
+
+        let statements = processor.statements(None, 0, 1000).unwrap();
+        let lines = statements[0].to_csv(&TransactionReferences::default());
+        assert_eq!(
+            lines[4..8],
+            [
+                "entry,100,dispute,2,,0.0000,0.0000,0.0000,0.0000",
+                "entry,100,resolve,2,,0.0000,0.0000,0.0000,0.0000",
+                "entry,100,cancel,3,,0.0000,0.0000,0.0000,0.0000",
+                "entry,100,freeze,,,0.0000,0.0000,0.0000,0.0000",
+            ]
+        );
+    }
+}
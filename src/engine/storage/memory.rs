@@ -0,0 +1,301 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::engine::{
+    EngineError,
+    admin::AuditEvent,
+    clock::Timestamp,
+    core::account::Account,
+    objects::{
+        Authorization, ClientId, DisputeClaim, ResolutionKind, TransactionDTO, TransactionId,
+        TxAmount,
+    },
+    statement::HistoryEntry,
+};
+
+use super::{
+    AccountStore, AuditStore, AuthorizationStore, CheckpointStore, DisputeStore, HistoryStore,
+    JobStore, ScheduleStore, SequenceStore,
+};
+
+#[derive(Default)]
+pub struct InMemoryAccountStore {
+    accounts: HashMap<ClientId, Account>,
+}
+
+impl AccountStore for InMemoryAccountStore {
+    fn load(&self, client_id: &ClientId) -> Result<Option<Account>, EngineError> {
+        Ok(self.accounts.get(client_id).cloned())
+    }
+
+    fn save(&mut self, account: &Account) -> Result<(), EngineError> {
+        self.accounts.insert(account.client_id, account.clone());
+        Ok(())
+    }
+
+    fn all(&self) -> Result<Vec<Account>, EngineError> {
+        Ok(self.accounts.values().cloned().collect())
+    }
+
+    fn remove(&mut self, client_id: &ClientId) -> Result<Option<Account>, EngineError> {
+        Ok(self.accounts.remove(client_id))
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryDisputeStore {
+    active: HashMap<TransactionId, DisputeClaim>,
+    charged_back: HashSet<TransactionId>,
+}
+
+impl DisputeStore for InMemoryDisputeStore {
+    fn open(&mut self, tx_id: TransactionId, claim: DisputeClaim) -> Result<(), EngineError> {
+        self.active.insert(tx_id, claim);
+        Ok(())
+    }
+
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<DisputeClaim>, EngineError> {
+        Ok(self.active.get(tx_id).cloned())
+    }
+
+    fn close(
+        &mut self,
+        tx_id: &TransactionId,
+        resolution: ResolutionKind,
+    ) -> Result<(), EngineError> {
+        self.active.remove(tx_id);
+        if let ResolutionKind::Chargeback = resolution {
+            self.charged_back.insert(*tx_id);
+        }
+        Ok(())
+    }
+
+    fn active(&self) -> Result<Vec<DisputeClaim>, EngineError> {
+        Ok(self.active.values().cloned().collect())
+    }
+
+    fn is_charged_back(&self, tx_id: &TransactionId) -> Result<bool, EngineError> {
+        Ok(self.charged_back.contains(tx_id))
+    }
+
+    fn remove(&mut self, tx_id: &TransactionId) -> Result<(), EngineError> {
+        self.active.remove(tx_id);
+        self.charged_back.remove(tx_id);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryAuthorizationStore {
+    active: HashMap<TransactionId, Authorization>,
+}
+
+impl AuthorizationStore for InMemoryAuthorizationStore {
+    fn insert(
+        &mut self,
+        tx_id: TransactionId,
+        authorization: Authorization,
+    ) -> Result<(), EngineError> {
+        self.active.insert(tx_id, authorization);
+        Ok(())
+    }
+
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<Authorization>, EngineError> {
+        Ok(self.active.get(tx_id).cloned())
+    }
+
+    fn remove(&mut self, tx_id: &TransactionId) -> Result<(), EngineError> {
+        self.active.remove(tx_id);
+        Ok(())
+    }
+
+    fn active(&self) -> Result<Vec<(TransactionId, Authorization)>, EngineError> {
+        Ok(self
+            .active
+            .iter()
+            .map(|(tx_id, authorization)| (*tx_id, authorization.clone()))
+            .collect())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryScheduleStore {
+    pending: BTreeMap<u64, TransactionDTO>,
+}
+
+impl ScheduleStore for InMemoryScheduleStore {
+    fn insert(&mut self, sequence: u64, tx: TransactionDTO) -> Result<(), EngineError> {
+        self.pending.insert(sequence, tx);
+        Ok(())
+    }
+
+    fn remove(&mut self, sequence: u64) -> Result<(), EngineError> {
+        self.pending.remove(&sequence);
+        Ok(())
+    }
+
+    fn pending(&self) -> Result<Vec<(u64, TransactionDTO)>, EngineError> {
+        Ok(self
+            .pending
+            .iter()
+            .map(|(sequence, tx)| (*sequence, tx.clone()))
+            .collect())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemorySequenceStore {
+    next_expected: HashMap<ClientId, u64>,
+    buffered: HashMap<(ClientId, u64), TransactionDTO>,
+}
+
+impl SequenceStore for InMemorySequenceStore {
+    fn next_expected(&self, client_id: &ClientId) -> Result<Option<u64>, EngineError> {
+        Ok(self.next_expected.get(client_id).copied())
+    }
+
+    fn set_next_expected(
+        &mut self,
+        client_id: ClientId,
+        sequence_number: u64,
+    ) -> Result<(), EngineError> {
+        self.next_expected.insert(client_id, sequence_number);
+        Ok(())
+    }
+
+    fn buffer(&mut self, tx: TransactionDTO) -> Result<(), EngineError> {
+        let sequence_number = tx.sequence_number.unwrap_or_default();
+        self.buffered.insert((tx.client_id, sequence_number), tx);
+        Ok(())
+    }
+
+    fn unbuffer(&mut self, client_id: &ClientId, sequence_number: u64) -> Result<(), EngineError> {
+        self.buffered.remove(&(*client_id, sequence_number));
+        Ok(())
+    }
+
+    fn buffered(&self) -> Result<Vec<TransactionDTO>, EngineError> {
+        Ok(self.buffered.values().cloned().collect())
+    }
+
+    fn remove_client(&mut self, client_id: &ClientId) -> Result<(), EngineError> {
+        self.next_expected.remove(client_id);
+        self.buffered
+            .retain(|(buffered_for, _), _| buffered_for != client_id);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryJobStore {
+    accruals: HashMap<ClientId, TxAmount>,
+    last_runs: HashMap<String, Timestamp>,
+}
+
+impl JobStore for InMemoryJobStore {
+    fn record_accrual(&mut self, client_id: ClientId, amount: TxAmount) -> Result<(), EngineError> {
+        *self.accruals.entry(client_id).or_default() += amount;
+        Ok(())
+    }
+
+    fn accruals(&self) -> Result<Vec<(ClientId, TxAmount)>, EngineError> {
+        Ok(self
+            .accruals
+            .iter()
+            .map(|(client_id, amount)| (*client_id, *amount))
+            .collect())
+    }
+
+    fn take_accrual(&mut self, client_id: &ClientId) -> Result<TxAmount, EngineError> {
+        Ok(self.accruals.remove(client_id).unwrap_or_default())
+    }
+
+    fn last_run(&self, job: &str) -> Result<Option<Timestamp>, EngineError> {
+        Ok(self.last_runs.get(job).copied())
+    }
+
+    fn set_last_run(&mut self, job: &str, at: Timestamp) -> Result<(), EngineError> {
+        self.last_runs.insert(job.to_string(), at);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryHistoryStore {
+    entries: Vec<HistoryEntry>,
+}
+
+impl HistoryStore for InMemoryHistoryStore {
+    fn record(&mut self, entry: HistoryEntry) -> Result<(), EngineError> {
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn entries(&self, client_id: Option<&ClientId>) -> Result<Vec<HistoryEntry>, EngineError> {
+        Ok(self
+            .entries
+            .iter()
+            .filter(|entry| client_id.is_none_or(|client_id| entry.client_id == *client_id))
+            .cloned()
+            .collect())
+    }
+
+    fn remove_client(&mut self, client_id: &ClientId) -> Result<(), EngineError> {
+        self.entries.retain(|entry| entry.client_id != *client_id);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryAuditStore {
+    events: Vec<AuditEvent>,
+}
+
+impl AuditStore for InMemoryAuditStore {
+    fn record(&mut self, event: AuditEvent) -> Result<(), EngineError> {
+        self.events.push(event);
+        Ok(())
+    }
+
+    fn events(&self) -> Result<Vec<AuditEvent>, EngineError> {
+        Ok(self.events.clone())
+    }
+}
+
+/// Nothing outlives the process, so checkpoint only marks progress of current run.
+#[derive(Default)]
+pub struct InMemoryCheckpointStore {
+    position: Option<u64>,
+    input: Option<String>,
+    shards: Option<u16>,
+}
+
+impl CheckpointStore for InMemoryCheckpointStore {
+    fn last_checkpoint(&self) -> Result<Option<u64>, EngineError> {
+        Ok(self.position)
+    }
+
+    fn checkpoint(&mut self, position: u64) -> Result<(), EngineError> {
+        self.position = Some(position);
+        Ok(())
+    }
+
+    fn input(&self) -> Result<Option<String>, EngineError> {
+        Ok(self.input.clone())
+    }
+
+    fn set_input(&mut self, fingerprint: &str) -> Result<(), EngineError> {
+        self.input = Some(fingerprint.to_string());
+        Ok(())
+    }
+
+    fn shards(&self) -> Result<Option<u16>, EngineError> {
+        Ok(self.shards)
+    }
+
+    fn set_shards(&mut self, shards: u16) -> Result<(), EngineError> {
+        if self.input.is_some() {
+            self.shards = Some(shards);
+        }
+        Ok(())
+    }
+}
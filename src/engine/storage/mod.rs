@@ -0,0 +1,146 @@
+//! Repositories for processor state. Every processor owns its own set of stores,
+//! so implementations don't need to synchronize access.
+
+use super::{
+    EngineError,
+    admin::AuditEvent,
+    clock::Timestamp,
+    core::account::Account,
+    objects::{
+        Adjustment, Authorization, ClientId, DisputeClaim, ResolutionKind, TransactionDTO,
+        TransactionId, TxAmount,
+    },
+    statement::HistoryEntry,
+};
+
+pub mod memory;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+pub trait AccountStore: Send {
+    fn load(&self, client_id: &ClientId) -> Result<Option<Account>, EngineError>;
+    fn save(&mut self, account: &Account) -> Result<(), EngineError>;
+    fn all(&self) -> Result<Vec<Account>, EngineError>;
+    fn remove(&mut self, client_id: &ClientId) -> Result<Option<Account>, EngineError>;
+}
+
+/// Applied adjustments, kept so they can be disputed later.
+pub trait TransactionLogStore: Send {
+    fn insert(&mut self, adjustment: Adjustment) -> Result<(), EngineError>;
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<Adjustment>, EngineError>;
+    /// Passes every adjustment to `visit`, without loading all of them at once.
+    fn for_each(
+        &self,
+        visit: &mut dyn FnMut(Adjustment) -> Result<(), EngineError>,
+    ) -> Result<(), EngineError>;
+    fn mark_reversed(&mut self, tx_id: &TransactionId) -> Result<(), EngineError>;
+    fn is_reversed(&self, tx_id: &TransactionId) -> Result<bool, EngineError>;
+    /// Forgets adjustment together with its reversal.
+    fn remove(&mut self, tx_id: &TransactionId) -> Result<(), EngineError>;
+}
+
+/// Open disputes and outcome of the closed ones.
+pub trait DisputeStore: Send {
+    fn open(&mut self, tx_id: TransactionId, claim: DisputeClaim) -> Result<(), EngineError>;
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<DisputeClaim>, EngineError>;
+    fn close(
+        &mut self,
+        tx_id: &TransactionId,
+        resolution: ResolutionKind,
+    ) -> Result<(), EngineError>;
+    fn active(&self) -> Result<Vec<DisputeClaim>, EngineError>;
+    fn is_charged_back(&self, tx_id: &TransactionId) -> Result<bool, EngineError>;
+    /// Forgets open dispute or outcome of the closed one.
+    fn remove(&mut self, tx_id: &TransactionId) -> Result<(), EngineError>;
+}
+
+/// Authorizations neither captured nor released yet.
+pub trait AuthorizationStore: Send {
+    fn insert(
+        &mut self,
+        tx_id: TransactionId,
+        authorization: Authorization,
+    ) -> Result<(), EngineError>;
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<Authorization>, EngineError>;
+    fn remove(&mut self, tx_id: &TransactionId) -> Result<(), EngineError>;
+    fn active(&self) -> Result<Vec<(TransactionId, Authorization)>, EngineError>;
+}
+
+/// Transactions waiting for their effective time, keyed by order they were scheduled in.
+pub trait ScheduleStore: Send {
+    fn insert(&mut self, sequence: u64, tx: TransactionDTO) -> Result<(), EngineError>;
+    fn remove(&mut self, sequence: u64) -> Result<(), EngineError>;
+    fn pending(&self) -> Result<Vec<(u64, TransactionDTO)>, EngineError>;
+}
+
+/// Next expected sequence number of every client and transactions which arrived ahead of it.
+pub trait SequenceStore: Send {
+    fn next_expected(&self, client_id: &ClientId) -> Result<Option<u64>, EngineError>;
+    fn set_next_expected(
+        &mut self,
+        client_id: ClientId,
+        sequence_number: u64,
+    ) -> Result<(), EngineError>;
+    fn buffer(&mut self, tx: TransactionDTO) -> Result<(), EngineError>;
+    fn unbuffer(&mut self, client_id: &ClientId, sequence_number: u64) -> Result<(), EngineError>;
+    fn buffered(&self) -> Result<Vec<TransactionDTO>, EngineError>;
+    /// Forgets next expected number and buffered transactions of the client.
+    fn remove_client(&mut self, client_id: &ClientId) -> Result<(), EngineError>;
+}
+
+/// Net balance change periodic jobs, balance corrections and chargeback fees made on every client,
+/// and time each job last ran.
+pub trait JobStore: Send {
+    fn record_accrual(&mut self, client_id: ClientId, amount: TxAmount) -> Result<(), EngineError>;
+    fn accruals(&self) -> Result<Vec<(ClientId, TxAmount)>, EngineError>;
+    /// Removes net balance change of the client, returning it.
+    fn take_accrual(&mut self, client_id: &ClientId) -> Result<TxAmount, EngineError>;
+    fn last_run(&self, job: &str) -> Result<Option<Timestamp>, EngineError>;
+    fn set_last_run(&mut self, job: &str, at: Timestamp) -> Result<(), EngineError>;
+}
+
+/// Balance changes of every client in order they were made, kept for statements.
+pub trait HistoryStore: Send {
+    fn record(&mut self, entry: HistoryEntry) -> Result<(), EngineError>;
+    /// Entries of `client_id`, or of all clients.
+    fn entries(&self, client_id: Option<&ClientId>) -> Result<Vec<HistoryEntry>, EngineError>;
+    fn remove_client(&mut self, client_id: &ClientId) -> Result<(), EngineError>;
+}
+
+/// Admin commands executed by a processor, in order of execution.
+pub trait AuditStore: Send {
+    fn record(&mut self, event: AuditEvent) -> Result<(), EngineError>;
+    fn events(&self) -> Result<Vec<AuditEvent>, EngineError>;
+}
+
+/// Input position (ordinal of transaction in input stream) up to which effects are persisted.
+pub trait CheckpointStore: Send {
+    fn last_checkpoint(&self) -> Result<Option<u64>, EngineError>;
+    /// Persists everything written since previous checkpoint, atomically with `position`.
+    fn checkpoint(&mut self, position: u64) -> Result<(), EngineError>;
+    /// Fingerprint of input the checkpoints were made over.
+    fn input(&self) -> Result<Option<String>, EngineError>;
+    /// Persisted with the next checkpoint.
+    fn set_input(&mut self, fingerprint: &str) -> Result<(), EngineError>;
+    /// Number of processors running when the checkpoints were made, kept with input fingerprint.
+    fn shards(&self) -> Result<Option<u16>, EngineError>;
+    /// Persisted with the next checkpoint, ignored unless input fingerprint is set.
+    fn set_shards(&mut self, shards: u16) -> Result<(), EngineError>;
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum StorageConfig {
+    #[default]
+    Memory,
+    /// Database file per processor, `<directory>/shard-<n>.sqlite`.
+    /// State found in existing files is picked up on start.
+    #[cfg(feature = "sqlite")]
+    Sqlite { directory: std::path::PathBuf },
+}
+
+impl StorageConfig {
+    /// State outlives the processor, so a restarted one picks it up from its last checkpoint.
+    pub fn is_persistent(&self) -> bool {
+        !matches!(self, StorageConfig::Memory)
+    }
+}
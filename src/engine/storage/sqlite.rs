@@ -0,0 +1,1131 @@
+use std::{
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use rusqlite::{
+    Connection, OptionalExtension, Row, ToSql, params,
+    types::{FromSql, FromSqlResult, ToSqlOutput, Type, ValueRef},
+};
+
+use crate::engine::{
+    EngineError,
+    admin::AuditEvent,
+    clock::Timestamp,
+    core::account::Account,
+    objects::{
+        Adjustment, AdjustmentKind, Authorization, ClientId, DisputeClaim, ResolutionKind,
+        TransactionDTO, TransactionId, TxAmount, TxDetails, TxKind,
+    },
+    statement::{HistoryEntry, HistoryEvent},
+};
+
+use super::{
+    AccountStore, AuditStore, AuthorizationStore, CheckpointStore, DisputeStore, HistoryStore,
+    JobStore, ScheduleStore, SequenceStore, TransactionLogStore,
+};
+
+// adjustments read from database at once when visiting transaction log
+const LOG_PAGE_SIZE: i64 = 1024;
+
+// amounts are kept in ten-thousandths, as `TxAmount` holds them
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+    CREATE TABLE IF NOT EXISTS accounts (
+        client_id INTEGER PRIMARY KEY,
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        locked INTEGER NOT NULL,
+        fees INTEGER NOT NULL,
+        reserved INTEGER NOT NULL,
+        last_activity INTEGER NOT NULL,
+        dormant INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        tx_id INTEGER PRIMARY KEY,
+        client_id INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        fee INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS disputes (
+        tx_id INTEGER PRIMARY KEY,
+        client_id INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        fee INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS chargebacks (
+        tx_id INTEGER PRIMARY KEY
+    );
+    CREATE TABLE IF NOT EXISTS reversals (
+        tx_id INTEGER PRIMARY KEY
+    );
+    CREATE TABLE IF NOT EXISTS authorizations (
+        tx_id INTEGER PRIMARY KEY,
+        client_id INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        authorized_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS scheduled (
+        sequence INTEGER PRIMARY KEY,
+        tx_id INTEGER NOT NULL,
+        client_id INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        amount INTEGER,
+        effective_at INTEGER NOT NULL,
+        sequence_number INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS accruals (
+        client_id INTEGER PRIMARY KEY,
+        amount INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS job_runs (
+        job TEXT PRIMARY KEY,
+        last_run INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sequences (
+        client_id INTEGER PRIMARY KEY,
+        next_expected INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sequence_buffer (
+        client_id INTEGER NOT NULL,
+        sequence_number INTEGER NOT NULL,
+        tx_id INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        amount INTEGER,
+        effective_at INTEGER,
+        PRIMARY KEY (client_id, sequence_number)
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        at INTEGER NOT NULL,
+        client_id INTEGER NOT NULL,
+        tx_id INTEGER,
+        event TEXT NOT NULL,
+        amount INTEGER,
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        reserved INTEGER NOT NULL,
+        fee INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS audit (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        at INTEGER NOT NULL,
+        command TEXT NOT NULL,
+        outcome TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS checkpoint (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        position INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS input (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        fingerprint TEXT NOT NULL,
+        shards INTEGER
+    );
+";
+
+/// Stores of a single processor, sharing one database connection.
+/// Writes are grouped in a database transaction committed on every checkpoint,
+/// so state in the file always matches its recorded checkpoint. The router checkpoints
+/// persistent storage at least every [`DEFAULT_PERSISTENT_CHECKPOINT_INTERVAL`] transactions.
+///
+/// [`DEFAULT_PERSISTENT_CHECKPOINT_INTERVAL`]: crate::engine::processor::DEFAULT_PERSISTENT_CHECKPOINT_INTERVAL
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+pub struct SqliteAccountStore(SqliteStorage);
+pub struct SqliteTransactionLog(SqliteStorage);
+pub struct SqliteDisputeStore(SqliteStorage);
+pub struct SqliteAuthorizationStore(SqliteStorage);
+pub struct SqliteScheduleStore(SqliteStorage);
+pub struct SqliteSequenceStore(SqliteStorage);
+pub struct SqliteJobStore(SqliteStorage);
+pub struct SqliteHistoryStore(SqliteStorage);
+pub struct SqliteAuditStore(SqliteStorage);
+pub struct SqliteCheckpointStore(SqliteStorage);
+
+impl SqliteStorage {
+    /// Opens (or creates) database of processor `instance_id` in `directory`.
+    pub fn open_shard(directory: &Path, instance_id: u16) -> rusqlite::Result<Self> {
+        let connection = Connection::open(directory.join(format!("shard-{instance_id}.sqlite")))?;
+        connection.execute_batch(SCHEMA)?;
+        connection.execute_batch("BEGIN")?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    pub fn account_store(&self) -> SqliteAccountStore {
+        SqliteAccountStore(self.share())
+    }
+
+    pub fn transaction_log(&self) -> SqliteTransactionLog {
+        SqliteTransactionLog(self.share())
+    }
+
+    pub fn dispute_store(&self) -> SqliteDisputeStore {
+        SqliteDisputeStore(self.share())
+    }
+
+    pub fn authorization_store(&self) -> SqliteAuthorizationStore {
+        SqliteAuthorizationStore(self.share())
+    }
+
+    pub fn schedule_store(&self) -> SqliteScheduleStore {
+        SqliteScheduleStore(self.share())
+    }
+
+    pub fn sequence_store(&self) -> SqliteSequenceStore {
+        SqliteSequenceStore(self.share())
+    }
+
+    pub fn job_store(&self) -> SqliteJobStore {
+        SqliteJobStore(self.share())
+    }
+
+    pub fn history_store(&self) -> SqliteHistoryStore {
+        SqliteHistoryStore(self.share())
+    }
+
+    pub fn audit_store(&self) -> SqliteAuditStore {
+        SqliteAuditStore(self.share())
+    }
+
+    pub fn checkpoint_store(&self) -> SqliteCheckpointStore {
+        SqliteCheckpointStore(self.share())
+    }
+
+    fn share(&self) -> Self {
+        Self {
+            connection: self.connection.clone(),
+        }
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        // connection is used by one processor only, poisoning means processor has already failed
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // runs every statement with `key` as its only parameter
+    fn delete(&self, statements: &[&str], key: i64) -> Result<(), EngineError> {
+        let connection = self.connection();
+        for statement in statements {
+            connection
+                .prepare_cached(statement)
+                .and_then(|mut stmt| stmt.execute([key]))
+                .map_err(|_| EngineError::Storage_WriteFailed)?;
+        }
+        Ok(())
+    }
+}
+
+impl AccountStore for SqliteAccountStore {
+    fn load(&self, client_id: &ClientId) -> Result<Option<Account>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "SELECT client_id, available, held, locked, fees, reserved, last_activity, dormant
+                 FROM accounts
+                 WHERE client_id = ?1",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_row([sql_integer(client_id.0)], read_account)
+                    .optional()
+            })
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn save(&mut self, account: &Account) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "INSERT OR REPLACE INTO accounts
+                 (client_id, available, held, locked, fees, reserved, last_activity, dormant)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    sql_integer(account.client_id.0),
+                    account.available,
+                    account.held,
+                    account.locked,
+                    account.fees,
+                    account.reserved,
+                    sql_integer(account.last_activity),
+                    account.dormant
+                ])
+            })
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn all(&self) -> Result<Vec<Account>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "SELECT client_id, available, held, locked, fees, reserved, last_activity, dormant
+                 FROM accounts",
+            )
+            .and_then(|mut stmt| stmt.query_map([], read_account)?.collect())
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn remove(&mut self, client_id: &ClientId) -> Result<Option<Account>, EngineError> {
+        let account = self.load(client_id)?;
+        self.0.delete(
+            &["DELETE FROM accounts WHERE client_id = ?1"],
+            sql_integer(client_id.0),
+        )?;
+        Ok(account)
+    }
+}
+
+impl TransactionLogStore for SqliteTransactionLog {
+    fn insert(&mut self, adjustment: Adjustment) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "INSERT OR REPLACE INTO transactions (tx_id, client_id, kind, amount, fee)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    sql_integer(adjustment.details.id.0),
+                    sql_integer(adjustment.details.client_id.0),
+                    kind_name(adjustment.category),
+                    adjustment.amount.0,
+                    adjustment.fee.0
+                ])
+            })
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<Adjustment>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "SELECT tx_id, client_id, kind, amount, fee FROM transactions WHERE tx_id = ?1",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_row([sql_integer(tx_id.0)], read_adjustment)
+                    .optional()
+            })
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn for_each(
+        &self,
+        visit: &mut dyn FnMut(Adjustment) -> Result<(), EngineError>,
+    ) -> Result<(), EngineError> {
+        // reads a page at a time and releases connection before visiting,
+        // since visitor may query other stores sharing it
+        let mut from = i64::MIN;
+        loop {
+            let page: Vec<Adjustment> = self
+                .0
+                .connection()
+                .prepare_cached(
+                    "SELECT tx_id, client_id, kind, amount, fee FROM transactions
+                     WHERE tx_id >= ?1 ORDER BY tx_id LIMIT ?2",
+                )
+                .and_then(|mut stmt| {
+                    stmt.query_map(params![from, LOG_PAGE_SIZE], read_adjustment)?
+                        .collect()
+                })
+                .map_err(|_| EngineError::Storage_ReadFailed)?;
+            let next = page
+                .last()
+                .and_then(|adjustment| sql_integer(adjustment.details.id.0).checked_add(1));
+            let last_page = page.len() < LOG_PAGE_SIZE as usize;
+            page.into_iter().try_for_each(&mut *visit)?;
+            match next {
+                Some(next) if !last_page => from = next,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn mark_reversed(&mut self, tx_id: &TransactionId) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("INSERT OR IGNORE INTO reversals (tx_id) VALUES (?1)")
+            .and_then(|mut stmt| stmt.execute([sql_integer(tx_id.0)]))
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn is_reversed(&self, tx_id: &TransactionId) -> Result<bool, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("SELECT 1 FROM reversals WHERE tx_id = ?1")
+            .and_then(|mut stmt| stmt.exists([sql_integer(tx_id.0)]))
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn remove(&mut self, tx_id: &TransactionId) -> Result<(), EngineError> {
+        self.0.delete(
+            &[
+                "DELETE FROM transactions WHERE tx_id = ?1",
+                "DELETE FROM reversals WHERE tx_id = ?1",
+            ],
+            sql_integer(tx_id.0),
+        )
+    }
+}
+
+impl DisputeStore for SqliteDisputeStore {
+    fn open(&mut self, tx_id: TransactionId, claim: DisputeClaim) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "INSERT OR REPLACE INTO disputes (tx_id, client_id, kind, amount, fee)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    sql_integer(tx_id.0),
+                    sql_integer(claim.client_id.0),
+                    kind_name(claim.kind),
+                    claim.amount.0,
+                    claim.fee.0
+                ])
+            })
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<DisputeClaim>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("SELECT client_id, kind, amount, fee FROM disputes WHERE tx_id = ?1")
+            .and_then(|mut stmt| {
+                stmt.query_row([sql_integer(tx_id.0)], read_claim)
+                    .optional()
+            })
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn close(
+        &mut self,
+        tx_id: &TransactionId,
+        resolution: ResolutionKind,
+    ) -> Result<(), EngineError> {
+        let mut connection = self.0.connection();
+        let transaction = connection
+            .savepoint()
+            .map_err(|_| EngineError::Storage_WriteFailed)?;
+        transaction
+            .execute(
+                "DELETE FROM disputes WHERE tx_id = ?1",
+                [sql_integer(tx_id.0)],
+            )
+            .and_then(|_| match resolution {
+                ResolutionKind::Chargeback => transaction.execute(
+                    "INSERT OR IGNORE INTO chargebacks (tx_id) VALUES (?1)",
+                    [sql_integer(tx_id.0)],
+                ),
+                ResolutionKind::Resolve => Ok(0),
+            })
+            .and_then(|_| transaction.commit())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn active(&self) -> Result<Vec<DisputeClaim>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("SELECT client_id, kind, amount, fee FROM disputes")
+            .and_then(|mut stmt| stmt.query_map([], read_claim)?.collect())
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn is_charged_back(&self, tx_id: &TransactionId) -> Result<bool, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("SELECT 1 FROM chargebacks WHERE tx_id = ?1")
+            .and_then(|mut stmt| stmt.exists([sql_integer(tx_id.0)]))
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn remove(&mut self, tx_id: &TransactionId) -> Result<(), EngineError> {
+        self.0.delete(
+            &[
+                "DELETE FROM disputes WHERE tx_id = ?1",
+                "DELETE FROM chargebacks WHERE tx_id = ?1",
+            ],
+            sql_integer(tx_id.0),
+        )
+    }
+}
+
+impl AuthorizationStore for SqliteAuthorizationStore {
+    fn insert(
+        &mut self,
+        tx_id: TransactionId,
+        authorization: Authorization,
+    ) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "INSERT OR REPLACE INTO authorizations (tx_id, client_id, amount, authorized_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    sql_integer(tx_id.0),
+                    sql_integer(authorization.client_id.0),
+                    authorization.amount.0,
+                    sql_integer(authorization.authorized_at)
+                ])
+            })
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn get(&self, tx_id: &TransactionId) -> Result<Option<Authorization>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "SELECT tx_id, client_id, amount, authorized_at FROM authorizations WHERE tx_id = ?1",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_row([sql_integer(tx_id.0)], read_authorization)
+                    .optional()
+            })
+            .map(|authorization| authorization.map(|(_, authorization)| authorization))
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn remove(&mut self, tx_id: &TransactionId) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .execute(
+                "DELETE FROM authorizations WHERE tx_id = ?1",
+                [sql_integer(tx_id.0)],
+            )
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn active(&self) -> Result<Vec<(TransactionId, Authorization)>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("SELECT tx_id, client_id, amount, authorized_at FROM authorizations")
+            .and_then(|mut stmt| stmt.query_map([], read_authorization)?.collect())
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+}
+
+impl ScheduleStore for SqliteScheduleStore {
+    fn insert(&mut self, sequence: u64, tx: TransactionDTO) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "INSERT OR REPLACE INTO scheduled
+                 (sequence, tx_id, client_id, kind, amount, effective_at, sequence_number)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    sql_integer(sequence),
+                    sql_integer(tx.id.0),
+                    sql_integer(tx.client_id.0),
+                    tx.kind.to_string(),
+                    tx.amount,
+                    sql_integer(tx.effective_at.unwrap_or_default()),
+                    tx.sequence_number.map(sql_integer)
+                ])
+            })
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn remove(&mut self, sequence: u64) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .execute(
+                "DELETE FROM scheduled WHERE sequence = ?1",
+                [sql_integer(sequence)],
+            )
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn pending(&self) -> Result<Vec<(u64, TransactionDTO)>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "SELECT sequence, tx_id, client_id, kind, amount, effective_at, sequence_number
+                 FROM scheduled",
+            )
+            .and_then(|mut stmt| stmt.query_map([], read_scheduled)?.collect())
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+}
+
+impl JobStore for SqliteJobStore {
+    fn record_accrual(&mut self, client_id: ClientId, amount: TxAmount) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "INSERT INTO accruals (client_id, amount) VALUES (?1, ?2)
+                 ON CONFLICT (client_id) DO UPDATE SET amount = amount + excluded.amount",
+            )
+            .and_then(|mut stmt| stmt.execute(params![sql_integer(client_id.0), amount]))
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn accruals(&self) -> Result<Vec<(ClientId, TxAmount)>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("SELECT client_id, amount FROM accruals")
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| Ok((ClientId(read_integer(row, 0)?), row.get(1)?)))?
+                    .collect()
+            })
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn take_accrual(&mut self, client_id: &ClientId) -> Result<TxAmount, EngineError> {
+        let accrual = self
+            .0
+            .connection()
+            .prepare_cached("SELECT amount FROM accruals WHERE client_id = ?1")
+            .and_then(|mut stmt| {
+                stmt.query_row([sql_integer(client_id.0)], |row| row.get(0))
+                    .optional()
+            })
+            .map_err(|_| EngineError::Storage_ReadFailed)?;
+        self.0.delete(
+            &["DELETE FROM accruals WHERE client_id = ?1"],
+            sql_integer(client_id.0),
+        )?;
+        Ok(accrual.unwrap_or_default())
+    }
+
+    fn last_run(&self, job: &str) -> Result<Option<Timestamp>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("SELECT last_run FROM job_runs WHERE job = ?1")
+            .and_then(|mut stmt| stmt.query_row([job], |row| read_integer(row, 0)).optional())
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn set_last_run(&mut self, job: &str, at: Timestamp) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("INSERT OR REPLACE INTO job_runs (job, last_run) VALUES (?1, ?2)")
+            .and_then(|mut stmt| stmt.execute(params![job, sql_integer(at)]))
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+}
+
+impl HistoryStore for SqliteHistoryStore {
+    fn record(&mut self, entry: HistoryEntry) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "INSERT INTO history
+                 (at, client_id, tx_id, event, amount, available, held, reserved, fee)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    sql_integer(entry.at),
+                    sql_integer(entry.client_id.0),
+                    entry.tx_id.map(|tx_id| sql_integer(tx_id.0)),
+                    entry.event.to_string(),
+                    entry.amount,
+                    entry.available,
+                    entry.held,
+                    entry.reserved,
+                    entry.fee
+                ])
+            })
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn entries(&self, client_id: Option<&ClientId>) -> Result<Vec<HistoryEntry>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "SELECT at, client_id, tx_id, event, amount, available, held, reserved, fee
+                 FROM history WHERE ?1 IS NULL OR client_id = ?1 ORDER BY id",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_map(
+                    [client_id.map(|client_id| sql_integer(client_id.0))],
+                    read_history,
+                )?
+                .collect()
+            })
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn remove_client(&mut self, client_id: &ClientId) -> Result<(), EngineError> {
+        self.0.delete(
+            &["DELETE FROM history WHERE client_id = ?1"],
+            sql_integer(client_id.0),
+        )
+    }
+}
+
+impl AuditStore for SqliteAuditStore {
+    fn record(&mut self, event: AuditEvent) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("INSERT INTO audit (at, command, outcome) VALUES (?1, ?2, ?3)")
+            .and_then(|mut stmt| {
+                stmt.execute(params![sql_integer(event.at), event.command, event.outcome])
+            })
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn events(&self) -> Result<Vec<AuditEvent>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("SELECT at, command, outcome FROM audit ORDER BY id")
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| {
+                    Ok(AuditEvent {
+                        at: read_integer(row, 0)?,
+                        command: row.get(1)?,
+                        outcome: row.get(2)?,
+                    })
+                })?
+                .collect()
+            })
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+}
+
+impl SequenceStore for SqliteSequenceStore {
+    fn next_expected(&self, client_id: &ClientId) -> Result<Option<u64>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached("SELECT next_expected FROM sequences WHERE client_id = ?1")
+            .and_then(|mut stmt| {
+                stmt.query_row([sql_integer(client_id.0)], |row| read_integer(row, 0))
+                    .optional()
+            })
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn set_next_expected(
+        &mut self,
+        client_id: ClientId,
+        sequence_number: u64,
+    ) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "INSERT OR REPLACE INTO sequences (client_id, next_expected) VALUES (?1, ?2)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute([sql_integer(client_id.0), sql_integer(sequence_number)])
+            })
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn buffer(&mut self, tx: TransactionDTO) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "INSERT OR REPLACE INTO sequence_buffer
+                 (client_id, sequence_number, tx_id, kind, amount, effective_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    sql_integer(tx.client_id.0),
+                    sql_integer(tx.sequence_number.unwrap_or_default()),
+                    sql_integer(tx.id.0),
+                    tx.kind.to_string(),
+                    tx.amount,
+                    tx.effective_at.map(sql_integer)
+                ])
+            })
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn unbuffer(&mut self, client_id: &ClientId, sequence_number: u64) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "DELETE FROM sequence_buffer WHERE client_id = ?1 AND sequence_number = ?2",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute([sql_integer(client_id.0), sql_integer(sequence_number)])
+            })
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn buffered(&self) -> Result<Vec<TransactionDTO>, EngineError> {
+        self.0
+            .connection()
+            .prepare_cached(
+                "SELECT client_id, sequence_number, tx_id, kind, amount, effective_at
+                 FROM sequence_buffer",
+            )
+            .and_then(|mut stmt| stmt.query_map([], read_buffered)?.collect())
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn remove_client(&mut self, client_id: &ClientId) -> Result<(), EngineError> {
+        self.0.delete(
+            &[
+                "DELETE FROM sequences WHERE client_id = ?1",
+                "DELETE FROM sequence_buffer WHERE client_id = ?1",
+            ],
+            sql_integer(client_id.0),
+        )
+    }
+}
+
+impl CheckpointStore for SqliteCheckpointStore {
+    fn last_checkpoint(&self) -> Result<Option<u64>, EngineError> {
+        self.0
+            .connection()
+            .query_row("SELECT position FROM checkpoint WHERE id = 0", [], |row| {
+                read_integer(row, 0)
+            })
+            .optional()
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn checkpoint(&mut self, position: u64) -> Result<(), EngineError> {
+        let connection = self.0.connection();
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO checkpoint (id, position) VALUES (0, ?1)",
+                [sql_integer(position)],
+            )
+            .and_then(|_| connection.execute_batch("COMMIT; BEGIN"))
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn input(&self) -> Result<Option<String>, EngineError> {
+        self.0
+            .connection()
+            .query_row("SELECT fingerprint FROM input WHERE id = 0", [], |row| {
+                row.get(0)
+            })
+            .optional()
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn set_input(&mut self, fingerprint: &str) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .execute(
+                "INSERT INTO input (id, fingerprint) VALUES (0, ?1)
+                 ON CONFLICT (id) DO UPDATE SET fingerprint = excluded.fingerprint",
+                [fingerprint],
+            )
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+
+    fn shards(&self) -> Result<Option<u16>, EngineError> {
+        self.0
+            .connection()
+            .query_row("SELECT shards FROM input WHERE id = 0", [], |row| {
+                row.get(0)
+            })
+            .optional()
+            .map(Option::flatten)
+            .map_err(|_| EngineError::Storage_ReadFailed)
+    }
+
+    fn set_shards(&mut self, shards: u16) -> Result<(), EngineError> {
+        self.0
+            .connection()
+            .execute("UPDATE input SET shards = ?1 WHERE id = 0", [shards])
+            .map(|_| ())
+            .map_err(|_| EngineError::Storage_WriteFailed)
+    }
+}
+
+fn kind_name(kind: AdjustmentKind) -> &'static str {
+    match kind {
+        AdjustmentKind::Deposit => "deposit",
+        AdjustmentKind::Withdrawal => "withdrawal",
+    }
+}
+
+fn kind_from_name(name: String) -> AdjustmentKind {
+    match name.as_str() {
+        "deposit" => AdjustmentKind::Deposit,
+        _ => AdjustmentKind::Withdrawal,
+    }
+}
+
+// SQLite integers are signed, values are stored bit for bit so full u64 range fits
+fn sql_integer(value: impl Into<u64>) -> i64 {
+    value.into() as i64
+}
+
+fn read_integer<T: TryFrom<u64>>(row: &Row, index: usize) -> rusqlite::Result<T> {
+    let value: i64 = row.get(index)?;
+    T::try_from(value as u64).map_err(|_| rusqlite::Error::IntegralValueOutOfRange(index, value))
+}
+
+impl ToSql for TxAmount {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0))
+    }
+}
+
+impl FromSql for TxAmount {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        i64::column_result(value).map(TxAmount)
+    }
+}
+
+fn read_account(row: &Row) -> rusqlite::Result<Account> {
+    Ok(Account {
+        available: row.get(1)?,
+        held: row.get(2)?,
+        locked: row.get(3)?,
+        fees: row.get(4)?,
+        reserved: row.get(5)?,
+        last_activity: read_integer(row, 6)?,
+        dormant: row.get(7)?,
+        ..Account::new(ClientId(read_integer(row, 0)?))
+    })
+}
+
+fn read_adjustment(row: &Row) -> rusqlite::Result<Adjustment> {
+    Ok(Adjustment {
+        category: kind_from_name(row.get(2)?),
+        details: TxDetails {
+            id: TransactionId(read_integer(row, 0)?),
+            client_id: ClientId(read_integer(row, 1)?),
+        },
+        amount: row.get(3)?,
+        fee: row.get(4)?,
+    })
+}
+
+fn read_claim(row: &Row) -> rusqlite::Result<DisputeClaim> {
+    Ok(DisputeClaim {
+        client_id: ClientId(read_integer(row, 0)?),
+        kind: kind_from_name(row.get(1)?),
+        amount: row.get(2)?,
+        fee: row.get(3)?,
+    })
+}
+
+fn read_authorization(row: &Row) -> rusqlite::Result<(TransactionId, Authorization)> {
+    Ok((
+        TransactionId(read_integer(row, 0)?),
+        Authorization {
+            client_id: ClientId(read_integer(row, 1)?),
+            amount: row.get(2)?,
+            authorized_at: read_integer(row, 3)?,
+        },
+    ))
+}
+
+fn read_scheduled(row: &Row) -> rusqlite::Result<(u64, TransactionDTO)> {
+    Ok((
+        read_integer(row, 0)?,
+        TransactionDTO {
+            id: TransactionId(read_integer(row, 1)?),
+            client_id: ClientId(read_integer(row, 2)?),
+            kind: read_kind(row, 3)?,
+            amount: row.get(4)?,
+            effective_at: Some(read_integer(row, 5)?),
+            sequence_number: row
+                .get::<_, Option<i64>>(6)?
+                .map(|sequence_number| sequence_number as u64),
+            batch_id: None,
+        },
+    ))
+}
+
+fn read_buffered(row: &Row) -> rusqlite::Result<TransactionDTO> {
+    Ok(TransactionDTO {
+        id: TransactionId(read_integer(row, 2)?),
+        client_id: ClientId(read_integer(row, 0)?),
+        kind: read_kind(row, 3)?,
+        amount: row.get(4)?,
+        effective_at: row
+            .get::<_, Option<i64>>(5)?
+            .map(|effective_at| effective_at as u64),
+        sequence_number: Some(read_integer(row, 1)?),
+        batch_id: None,
+    })
+}
+
+fn read_history(row: &Row) -> rusqlite::Result<HistoryEntry> {
+    let event: String = row.get(3)?;
+    Ok(HistoryEntry {
+        at: read_integer(row, 0)?,
+        client_id: ClientId(read_integer(row, 1)?),
+        tx_id: match row.get::<_, Option<i64>>(2)? {
+            Some(_) => Some(TransactionId(read_integer(row, 2)?)),
+            None => None,
+        },
+        event: HistoryEvent::from_str(&event)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(3, Type::Text, e.into()))?,
+        amount: row.get(4)?,
+        available: row.get(5)?,
+        held: row.get(6)?,
+        reserved: row.get(7)?,
+        fee: row.get(8)?,
+    })
+}
+
+fn read_kind(row: &Row, index: usize) -> rusqlite::Result<TxKind> {
+    let kind: String = row.get(index)?;
+    TxKind::from_str(&kind)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, e.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{
+        EngineError,
+        objects::{ClientId, TransactionDTO, TxAmount, TxKind},
+        processor::{ProcessorConfig, ProcessorImpl},
+        storage::StorageConfig,
+        temp_path::TempPath,
+    };
+
+    #[test]
+    fn restart_resumes_from_last_checkpoint() {
+        let directory = TempPath::new("sqlite");
+        std::fs::create_dir_all(&directory).unwrap();
+        let config = ProcessorConfig {
+            storage: StorageConfig::Sqlite {
+                directory: directory.to_path_buf(),
+            },
+            ..Default::default()
+        };
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+
+        let mut processor = ProcessorImpl::new(0, config.clone());
+        assert!(
+            processor
+                .process_at(tx(1, TxKind::Deposit, Some(10.0)), 1)
+                .is_ok()
+        );
+        assert!(
+            processor
+                .process_at(tx(2, TxKind::Deposit, Some(5.0)), 2)
+                .is_ok()
+        );
+        assert!(
+            processor
+                .process_at(tx(1, TxKind::Dispute, None), 3)
+                .is_ok()
+        );
+        processor.checkpoint(3).unwrap();
+        // lost with the crash, as it isn't covered by a checkpoint
+        assert!(
+            processor
+                .process_at(tx(3, TxKind::Deposit, Some(1.0)), 4)
+                .is_ok()
+        );
+        drop(processor);
+
+        let mut restarted = ProcessorImpl::new(0, config);
+        assert_eq!(restarted.last_checkpoint().unwrap(), Some(3));
+        // resubmitted input is acknowledged, not applied again
+        assert!(
+            restarted
+                .process_at(tx(2, TxKind::Deposit, Some(5.0)), 2)
+                .is_ok()
+        );
+        assert!(
+            restarted
+                .process_at(tx(3, TxKind::Deposit, Some(1.0)), 4)
+                .is_ok()
+        );
+        assert!(
+            restarted
+                .process_at(tx(1, TxKind::Chargeback, None), 5)
+                .is_ok()
+        );
+        let account = restarted.account(&ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.available, TxAmount::from_f64(6.0));
+        assert_eq!(account.held, TxAmount::ZERO);
+        assert!(account.locked);
+        assert!(restarted.reconcile().unwrap().is_empty());
+    }
+
+    #[test]
+    fn double_entry_books_open_with_stored_balances() {
+        let directory = TempPath::new("sqlite-ledger");
+        std::fs::create_dir_all(&directory).unwrap();
+        let config = ProcessorConfig {
+            storage: StorageConfig::Sqlite {
+                directory: directory.to_path_buf(),
+            },
+            double_entry: true,
+            ..Default::default()
+        };
+        let tx = |id, kind, amount| TransactionDTO::new(id, 1, kind, amount);
+
+        let mut processor = ProcessorImpl::new(0, config.clone());
+        for (position, (id, kind, amount)) in (1..).zip([
+            (1, TxKind::Deposit, Some(10.0)),
+            (2, TxKind::Deposit, Some(5.0)),
+            (1, TxKind::Dispute, None),
+        ]) {
+            assert!(processor.process_at(tx(id, kind, amount), position).is_ok());
+        }
+        processor.checkpoint(3).unwrap();
+        drop(processor);
+
+        let mut restarted = ProcessorImpl::new(0, config);
+        assert!(restarted.verify_ledger().is_empty());
+        assert!(
+            restarted
+                .process_at(tx(1, TxKind::Resolve, None), 4)
+                .is_ok()
+        );
+        assert!(restarted.verify_ledger().is_empty());
+    }
+
+    #[test]
+    fn restart_over_different_input_is_refused() {
+        let directory = TempPath::new("sqlite-input");
+        std::fs::create_dir_all(&directory).unwrap();
+        let config = ProcessorConfig {
+            storage: StorageConfig::Sqlite {
+                directory: directory.to_path_buf(),
+            },
+            ..Default::default()
+        };
+
+        let mut processor = ProcessorImpl::new(0, config.clone());
+        processor.claim_input("first.csv", 2).unwrap();
+        processor.checkpoint(1).unwrap();
+        drop(processor);
+
+        let mut restarted = ProcessorImpl::new(0, config);
+        assert!(matches!(
+            restarted.claim_input("second.csv", 2),
+            Err(EngineError::Checkpoint_DifferentInput)
+        ));
+        assert!(matches!(
+            restarted.claim_input("first.csv", 3),
+            Err(EngineError::Checkpoint_DifferentShards)
+        ));
+        assert!(restarted.claim_input("first.csv", 2).is_ok());
+    }
+}
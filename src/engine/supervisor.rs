@@ -0,0 +1,556 @@
+use std::{collections::VecDeque, time::Duration};
+
+use tokio::{
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot::{self, error::TryRecvError},
+    },
+    task::{AbortHandle, JoinError},
+    time::Instant,
+};
+
+use super::{
+    Account, EngineError,
+    admin::{AdminCommand, ShardDump},
+    batch::InputUnit,
+    objects::TransactionDTO,
+    processor::{ProcessorCommand, ProcessorConfig, ProcessorImpl, ProcessorInput},
+    resharding::{ClientState, shard_of},
+};
+
+// routed input kept for replay without persistent storage, where restarted processor
+// replays everything from the start
+const MEMORY_REPLAY_LIMIT: usize = 1_000_000;
+
+// what processors were sent, kept for replay in order it was sent
+enum Routed {
+    Input(InputUnit),
+    // command changing state of processor `bucket`
+    Admin(usize, AdminCommand),
+    Checkpoint(u64),
+}
+
+/// Processor task which ended, with accounts it returned or the reason it failed.
+pub struct Exit {
+    instance_id: u16,
+    generation: u64,
+    result: Result<Vec<Account>, JoinError>,
+}
+
+// replies of every processor to a checkpoint, input up to the lowest one can be forgotten
+struct Confirmation {
+    replies: Vec<oneshot::Receiver<Result<Option<u64>, EngineError>>>,
+    position: u64,
+}
+
+/// Runs processors and watches over them. Processor failing (e.g. panicking) is reported
+/// and restarted from its last checkpoint, up to `restart_attempts` times, with input and
+/// admin commands routed to it since then replayed. Processor which can't be restarted is given up on: transactions
+/// of its clients are dropped with a report, other processors keep going.
+pub struct Supervisor {
+    config: ProcessorConfig,
+    restart_attempts: u32,
+    senders: Vec<UnboundedSender<ProcessorInput>>,
+    aborts: Vec<AbortHandle>,
+    restarts: Vec<u32>,
+    down: Vec<bool>,
+    // which spawn of every processor is current, exits of earlier ones are stale
+    generations: Vec<u64>,
+    spawned: u64,
+    exit_sender: UnboundedSender<Exit>,
+    exits: UnboundedReceiver<Exit>,
+    // input and admin commands routed since the oldest checkpoint not confirmed by every
+    // processor, only kept while processors can be restarted; everything is kept without
+    // persistent storage, up to `MEMORY_REPLAY_LIMIT` entries
+    routed: VecDeque<Routed>,
+    replayable: bool,
+    confirmations: VecDeque<Confirmation>,
+}
+
+impl Supervisor {
+    pub fn start(instance_count: u16, config: ProcessorConfig, restart_attempts: u32) -> Self {
+        let (exit_sender, exits) = mpsc::unbounded_channel();
+        let mut supervisor = Self {
+            config,
+            restart_attempts,
+            senders: Vec::new(),
+            aborts: Vec::new(),
+            restarts: vec![0; instance_count as usize],
+            down: vec![false; instance_count as usize],
+            generations: Vec::new(),
+            spawned: 0,
+            exit_sender,
+            exits,
+            routed: VecDeque::new(),
+            replayable: restart_attempts > 0,
+            confirmations: VecDeque::new(),
+        };
+        supervisor.grow(instance_count);
+        supervisor
+    }
+
+    // starts processors up to `instance_count`
+    fn grow(&mut self, instance_count: u16) {
+        for instance_id in self.senders.len() as u16..instance_count {
+            let (sender, abort) = self.spawn(instance_id);
+            self.senders.push(sender);
+            self.aborts.push(abort);
+        }
+        self.restarts.resize(instance_count as usize, 0);
+        self.down.resize(instance_count as usize, false);
+    }
+
+    fn spawn(&mut self, instance_id: u16) -> (UnboundedSender<ProcessorInput>, AbortHandle) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (_, handle) = ProcessorImpl::run(receiver, instance_id, self.config.clone());
+        let abort = handle.abort_handle();
+        let exits = self.exit_sender.clone();
+        self.spawned += 1;
+        let generation = self.spawned;
+        match self.generations.get_mut(instance_id as usize) {
+            Some(current) => *current = generation,
+            None => self.generations.push(generation),
+        }
+        tokio::spawn(async move {
+            _ = exits.send(Exit {
+                instance_id,
+                generation,
+                result: handle.await,
+            });
+        });
+        (sender, abort)
+    }
+
+    // exit of a processor retired by resharding, or replaced since
+    fn is_stale(&self, exit: &Exit) -> bool {
+        self.generations.get(exit.instance_id as usize) != Some(&exit.generation)
+    }
+
+    pub fn senders(&self) -> &[UnboundedSender<ProcessorInput>] {
+        &self.senders
+    }
+
+    /// Routes transaction to processor `bucket`, reporting it when the processor is given up on.
+    pub fn send(&mut self, bucket: usize, transaction: TransactionDTO, position: u64) {
+        if self.replayable {
+            self.record(InputUnit::Transaction(transaction.clone(), position));
+        }
+        let (tx_id, client_id) = (transaction.id, transaction.client_id);
+        let sent = self.senders[bucket].send(ProcessorInput::Transaction(transaction, position));
+        // transaction sent to a processor which will be restarted is replayed by the restart
+        if sent.is_err()
+            && (self.down[bucket]
+                || !self.replayable
+                || self.restarts[bucket] >= self.restart_attempts)
+        {
+            eprintln!(
+                "processor {bucket} is down, transaction {} of client {} dropped",
+                tx_id.to_u64(),
+                *client_id
+            );
+        }
+    }
+
+    /// Sends admin command to processor `bucket`, keeping it for replay when it changes state.
+    pub fn administer(
+        &mut self,
+        bucket: usize,
+        command: AdminCommand,
+    ) -> oneshot::Receiver<Result<Option<ShardDump>, EngineError>> {
+        let reads_only = matches!(
+            command,
+            AdminCommand::DumpShard { .. } | AdminCommand::PendingReview { .. }
+        );
+        if !reads_only {
+            self.keep(Routed::Admin(bucket, command.clone()));
+        }
+        let (reply, result) = oneshot::channel();
+        _ = self.senders[bucket].send(ProcessorInput::Command(ProcessorCommand::Admin(
+            command, reply,
+        )));
+        result
+    }
+
+    /// Keeps routed input for replay on restart. Without persistent storage, restarts are
+    /// given up once more than `MEMORY_REPLAY_LIMIT` entries would have to be replayed.
+    pub fn record(&mut self, unit: InputUnit) {
+        self.keep(Routed::Input(unit));
+    }
+
+    fn keep(&mut self, routed: Routed) {
+        if !self.replayable {
+            return;
+        }
+        self.routed.push_back(routed);
+        if !self.config.storage.is_persistent() && self.routed.len() > MEMORY_REPLAY_LIMIT {
+            eprintln!(
+                "more than {MEMORY_REPLAY_LIMIT} transactions routed without persistent storage, failed processors won't be restarted"
+            );
+            self.routed = VecDeque::new();
+            self.replayable = false;
+        }
+    }
+
+    /// Checkpoints every processor at `position`.
+    pub fn checkpoint(&mut self, position: u64) {
+        self.keep(Routed::Checkpoint(position));
+        let mut replies = Vec::new();
+        for sender in &self.senders {
+            _ = sender.send(ProcessorInput::Command(ProcessorCommand::Checkpoint(
+                position,
+            )));
+            if self.restart_attempts > 0 && self.config.storage.is_persistent() {
+                let (reply, checkpoint) = oneshot::channel();
+                _ = sender.send(ProcessorInput::Command(ProcessorCommand::LastCheckpoint(
+                    reply,
+                )));
+                replies.push(checkpoint);
+            }
+        }
+        if !replies.is_empty() {
+            self.confirmations
+                .push_back(Confirmation { replies, position });
+        }
+        self.forget_checkpointed();
+    }
+
+    // drops routed input which every processor has persisted
+    fn forget_checkpointed(&mut self) {
+        while let Some(confirmation) = self.confirmations.front_mut() {
+            let mut position = confirmation.position;
+            confirmation
+                .replies
+                .retain_mut(|reply| match reply.try_recv() {
+                    Err(TryRecvError::Empty) => true,
+                    Ok(checkpoint) => {
+                        position = position.min(checkpoint.ok().flatten().unwrap_or(0));
+                        false
+                    }
+                    Err(TryRecvError::Closed) => false,
+                });
+            confirmation.position = position;
+            if !confirmation.replies.is_empty() {
+                return;
+            }
+            // checkpoint itself is kept for restarts to find where to replay from
+            if let Some(index) = self
+                .routed
+                .iter()
+                .position(|routed| matches!(routed, Routed::Checkpoint(at) if *at == position))
+            {
+                self.routed.drain(..index);
+            }
+            self.confirmations.pop_front();
+        }
+    }
+
+    /// Waits for a processor to end. While input is routed, that's always a failure.
+    pub async fn next_exit(&mut self) -> Exit {
+        loop {
+            // sender is kept by `self`, so the channel stays open
+            let exit = self.exits.recv().await.expect("exit channel closed");
+            if !self.is_stale(&exit) {
+                return exit;
+            }
+        }
+    }
+
+    /// Reports failed processor and restarts it, or gives up on it.
+    pub async fn recover(&mut self, exit: Exit) {
+        let instance_id = exit.instance_id;
+        eprintln!("processor {instance_id} failed: {}", describe(exit.result));
+        match self.restart(instance_id).await {
+            Some((sender, abort)) => {
+                self.senders[instance_id as usize] = sender;
+                self.aborts[instance_id as usize] = abort;
+            }
+            None => {
+                self.down[instance_id as usize] = true;
+                eprintln!("processor {instance_id} is down, its clients are no longer processed");
+            }
+        }
+    }
+
+    // starts processor again and replays input routed to it after its last checkpoint
+    async fn restart(
+        &mut self,
+        instance_id: u16,
+    ) -> Option<(UnboundedSender<ProcessorInput>, AbortHandle)> {
+        let restarts = &mut self.restarts[instance_id as usize];
+        if !self.replayable || *restarts >= self.restart_attempts {
+            return None;
+        }
+        *restarts += 1;
+        let (sender, abort) = self.spawn(instance_id);
+        let (reply, checkpoint) = oneshot::channel();
+        _ = sender.send(ProcessorInput::Command(ProcessorCommand::LastCheckpoint(
+            reply,
+        )));
+        let checkpoint = match checkpoint.await {
+            Ok(Ok(position)) => position,
+            _ => None,
+        };
+        let resume_after = checkpoint.unwrap_or(0);
+        // admin commands sent before the checkpoint are covered by it
+        let start = checkpoint
+            .and_then(|position| {
+                self.routed
+                    .iter()
+                    .rposition(|routed| matches!(routed, Routed::Checkpoint(at) if *at == position))
+            })
+            .map_or(0, |index| index + 1);
+        let count = self.down.len();
+        let ours = |tx: &TransactionDTO| shard_of(&tx.client_id, count) == instance_id as usize;
+        let mut replayed = 0;
+        let mut commands = 0;
+        for routed in self.routed.iter().skip(start) {
+            let unit = match routed {
+                Routed::Input(unit) => unit,
+                Routed::Admin(bucket, command) if *bucket == instance_id as usize => {
+                    let (reply, _) = oneshot::channel();
+                    _ = sender.send(ProcessorInput::Command(ProcessorCommand::Admin(
+                        command.clone(),
+                        reply,
+                    )));
+                    commands += 1;
+                    continue;
+                }
+                Routed::Admin(..) | Routed::Checkpoint(_) => continue,
+            };
+            match unit {
+                InputUnit::Transaction(tx, position) if ours(tx) && *position > resume_after => {
+                    _ = sender.send(ProcessorInput::Transaction(tx.clone(), *position));
+                    replayed += 1;
+                }
+                InputUnit::Transaction(..) => {}
+                // batch was committed on every processor, so its part is committed right away
+                InputUnit::Batch(batch_id, members) => {
+                    let part: Vec<_> = members
+                        .iter()
+                        .filter(|(tx, position)| ours(tx) && *position > resume_after)
+                        .cloned()
+                        .collect();
+                    if part.is_empty() {
+                        continue;
+                    }
+                    replayed += part.len();
+                    let (reply, prepared) = oneshot::channel();
+                    _ = sender.send(ProcessorInput::Command(ProcessorCommand::PrepareBatch(
+                        part, reply,
+                    )));
+                    let (reply, committed) = oneshot::channel();
+                    _ = sender.send(ProcessorInput::Command(ProcessorCommand::CommitBatch(
+                        reply,
+                    )));
+                    let results = prepared.await.unwrap_or_default().into_iter();
+                    for (tx_id, e) in results.chain(committed.await.unwrap_or_default()) {
+                        if let Some(e) = e {
+                            eprintln!(
+                                "processor {instance_id}: replayed batch {batch_id} transaction {} failed with {e:?}",
+                                tx_id.to_u64()
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        eprintln!(
+            "processor {instance_id} restarted after checkpoint {resume_after}, {replayed} transactions and {commands} admin commands replayed"
+        );
+        Some((sender, abort))
+    }
+
+    /// Changes number of processors to `shards`, with input up to `position` routed.
+    /// Clients are moved to processors they now belong to, which pick up where the previous
+    /// ones stopped; processors past the new count are stopped once they hand over their
+    /// clients. Every processor is then checkpointed at `position`.
+    /// Returns number of clients moved.
+    pub async fn reshard(&mut self, shards: u16, position: u64) -> Result<usize, String> {
+        if shards == 0 {
+            return Err("at least one processor is needed".to_string());
+        }
+        if shards as usize == self.senders.len() {
+            return Err(format!("{shards} processors are running already"));
+        }
+        if let Some(down) = self.down.iter().position(|down| *down) {
+            return Err(format!("processor {down} is down"));
+        }
+        // standby follows processors by their input, moved clients would be lost on it
+        if self.config.replica.is_some() {
+            return Err("processors are replicated to a standby".to_string());
+        }
+        let previous = self.senders.len();
+        self.grow(shards);
+        // everything routed before is processed before clients are handed over
+        let mut exports = Vec::new();
+        for (instance_id, sender) in self.senders.iter().enumerate().take(previous) {
+            let (reply, states) = oneshot::channel();
+            _ = sender.send(ProcessorInput::Command(ProcessorCommand::MigrateOut(
+                shards as usize,
+                reply,
+            )));
+            exports.push((instance_id, states));
+        }
+        let mut exported = Vec::new();
+        let mut failure = None;
+        for (instance_id, states) in exports {
+            match states.await {
+                Ok(Ok(states)) => exported.push((instance_id, states)),
+                Ok(Err(e)) => {
+                    failure = Some(format!(
+                        "processor {instance_id}: migrating clients out failed with {e:?}"
+                    ))
+                }
+                Err(_) => {
+                    failure = Some(format!(
+                        "processor {instance_id} failed while migrating clients out"
+                    ))
+                }
+            }
+        }
+        if let Some(failure) = failure {
+            // clients stay where they were, those taken out are handed back
+            for (instance_id, states) in exported {
+                if states.is_empty() {
+                    continue;
+                }
+                let (reply, result) = oneshot::channel();
+                _ = self.senders[instance_id].send(ProcessorInput::Command(
+                    ProcessorCommand::MigrateIn(states, reply),
+                ));
+                if !matches!(result.await, Ok(Ok(()))) {
+                    eprintln!("processor {instance_id}: taking its clients back failed");
+                }
+            }
+            self.shrink(previous);
+            return Err(failure);
+        }
+        let mut parts: Vec<Vec<ClientState>> = (0..shards).map(|_| Vec::new()).collect();
+        for state in exported.into_iter().flat_map(|(_, states)| states) {
+            parts[shard_of(&state.client_id, shards as usize)].push(state);
+        }
+        let moved = parts.iter().map(Vec::len).sum();
+        let mut imports = Vec::new();
+        for (instance_id, states) in parts.into_iter().enumerate() {
+            if states.is_empty() {
+                continue;
+            }
+            let (reply, result) = oneshot::channel();
+            _ = self.senders[instance_id].send(ProcessorInput::Command(
+                ProcessorCommand::MigrateIn(states, reply),
+            ));
+            imports.push((instance_id, result));
+        }
+        for (instance_id, result) in imports {
+            match result.await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    eprintln!("processor {instance_id}: migrating clients in failed with {e:?}")
+                }
+                Err(_) => eprintln!("processor {instance_id} failed while migrating clients in"),
+            }
+        }
+        for sender in self.senders.iter().take(shards as usize) {
+            _ = sender.send(ProcessorInput::Command(ProcessorCommand::Resharded(shards)));
+        }
+        // retired processors persist handing over their clients too
+        self.checkpoint(position);
+        self.shrink(shards as usize);
+        Ok(moved)
+    }
+
+    // stops processors past `instance_count` once they finish what was routed to them
+    fn shrink(&mut self, instance_count: usize) {
+        if instance_count < self.senders.len() {
+            self.senders.truncate(instance_count);
+            self.aborts.truncate(instance_count);
+            self.restarts.truncate(instance_count);
+            self.down.truncate(instance_count);
+            self.generations.truncate(instance_count);
+        }
+    }
+
+    /// Stops every processor right away, discarding state since its last checkpoint.
+    pub fn abort(self) {
+        for abort in &self.aborts {
+            abort.abort();
+        }
+    }
+
+    /// Checkpoints processors at `position` and waits till they finish work, restarting
+    /// those which fail. Processors still running after `timeout` are aborted.
+    /// Returns final accounts ordered by client id, and processors which didn't finish.
+    pub async fn drain(
+        mut self,
+        position: u64,
+        timeout: Option<Duration>,
+    ) -> (Vec<Account>, Vec<u16>) {
+        self.checkpoint(position);
+        // notify instances that all inputs are processed by closing channels' tx end
+        self.senders.clear();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut running: Vec<u16> = (0..self.down.len() as u16)
+            .filter(|instance_id| !self.down[*instance_id as usize])
+            .collect();
+        let mut unfinished: Vec<u16> = (0..self.down.len() as u16)
+            .filter(|instance_id| self.down[*instance_id as usize])
+            .collect();
+        let mut accounts = Vec::new();
+        while !running.is_empty() {
+            let exit = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, self.exits.recv())
+                    .await
+                    .ok()
+                    .flatten(),
+                None => self.exits.recv().await,
+            };
+            let Some(exit) = exit else {
+                break;
+            };
+            if self.is_stale(&exit) {
+                continue;
+            }
+            let instance_id = exit.instance_id;
+            match exit.result {
+                Ok(shard_accounts) => accounts.extend(shard_accounts),
+                Err(e) => {
+                    eprintln!("processor {instance_id} failed: {}", describe(Err(e)));
+                    if let Some((sender, abort)) = self.restart(instance_id).await {
+                        _ = sender.send(ProcessorInput::Command(ProcessorCommand::Checkpoint(
+                            position,
+                        )));
+                        self.aborts[instance_id as usize] = abort;
+                        continue;
+                    }
+                    unfinished.push(instance_id);
+                }
+            }
+            running.retain(|running| *running != instance_id);
+        }
+        for instance_id in running {
+            self.aborts[instance_id as usize].abort();
+            eprintln!(
+                "processor {instance_id} didn't finish within drain timeout, state since its last checkpoint is lost"
+            );
+            unfinished.push(instance_id);
+        }
+        accounts.sort_by_key(|account| account.client_id.0);
+        unfinished.sort();
+        (accounts, unfinished)
+    }
+}
+
+fn describe(result: Result<Vec<Account>, JoinError>) -> String {
+    match result {
+        Ok(_) => "stopped while input was routed".to_string(),
+        Err(e) if e.is_panic() => {
+            let panic = e.into_panic();
+            let message = match panic.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => panic.downcast_ref::<String>().cloned().unwrap_or_default(),
+            };
+            format!("panicked: {message}")
+        }
+        Err(e) => e.to_string(),
+    }
+}
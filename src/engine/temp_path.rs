@@ -0,0 +1,48 @@
+//! Scratch files and directories for tests, unique per test and per run.
+
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Path in the temp directory no other test uses, whatever gets created there is removed on drop.
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let unique = format!("p-engine-{name}-{}-{nanos}-{id}", std::process::id());
+        Self(std::env::temp_dir().join(unique))
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        match self.0.is_dir() {
+            true => _ = std::fs::remove_dir_all(&self.0),
+            false => _ = std::fs::remove_file(&self.0),
+        }
+    }
+}
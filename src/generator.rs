@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use crate::engine::objects::{
+    RawClientId, RawTransactionId, TransactionDTO, TransactionId, TxKind,
+};
+
+// number of recent adjustments per client which can get disputed
+const DISPUTABLE_HISTORY: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct WorkloadConfig {
+    pub clients: RawClientId,
+    pub transactions: u64,
+    /// Share of transactions opening a dispute, roughly the same share closes them.
+    pub dispute_rate: f64,
+    /// 0.0 spreads activity evenly, higher values concentrate it on low client ids.
+    pub skew: f64,
+    pub seed: u64,
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        Self {
+            clients: 1000,
+            transactions: 100_000,
+            dispute_rate: 0.01,
+            skew: 1.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Produces a reproducible stream of transactions resembling real traffic:
+/// mostly deposits and withdrawals, disputes referencing client's recent deposits
+/// or withdrawals, followed later by their resolution or chargeback.
+pub struct WorkloadGenerator {
+    config: WorkloadConfig,
+    rng: SplitMix64,
+    emitted: u64,
+    next_tx_id: RawTransactionId,
+    history: HashMap<RawClientId, Vec<TransactionId>>,
+    open_disputes: Vec<(RawClientId, TransactionId)>,
+}
+
+impl WorkloadGenerator {
+    pub fn new(config: WorkloadConfig) -> Self {
+        Self {
+            rng: SplitMix64(config.seed),
+            config,
+            emitted: 0,
+            next_tx_id: 1,
+            history: Default::default(),
+            open_disputes: Default::default(),
+        }
+    }
+
+    fn pick_client(&mut self) -> RawClientId {
+        let exponent = 1.0 + self.config.skew.max(0.0);
+        let index =
+            (self.rng.next_f64().powf(exponent) * self.config.clients as f64) as RawClientId;
+        index.min(self.config.clients.saturating_sub(1))
+    }
+
+    fn close_dispute(&mut self) -> Option<TransactionDTO> {
+        if self.open_disputes.is_empty() || self.rng.next_f64() >= self.config.dispute_rate {
+            return None;
+        }
+        let index = self.rng.next_below(self.open_disputes.len() as u64) as usize;
+        let (client_id, tx_id) = self.open_disputes.swap_remove(index);
+        let kind = match self.rng.next_f64() < 0.8 {
+            true => TxKind::Resolve,
+            false => TxKind::Chargeback,
+        };
+        Some(TransactionDTO::new(tx_id.0, client_id, kind, None))
+    }
+
+    fn open_dispute(&mut self, client_id: RawClientId) -> Option<TransactionDTO> {
+        if self.rng.next_f64() >= self.config.dispute_rate {
+            return None;
+        }
+        let history = self
+            .history
+            .get_mut(&client_id)
+            .filter(|history| !history.is_empty())?;
+        let index = self.rng.next_below(history.len() as u64) as usize;
+        let tx_id = history.swap_remove(index);
+        self.open_disputes.push((client_id, tx_id));
+        Some(TransactionDTO::new(
+            tx_id.0,
+            client_id,
+            TxKind::Dispute,
+            None,
+        ))
+    }
+
+    fn adjust(&mut self, client_id: RawClientId) -> TransactionDTO {
+        let tx_id = TransactionId(self.next_tx_id);
+        self.next_tx_id += 1;
+        let kind = match self.rng.next_f64() < 0.6 {
+            true => TxKind::Deposit,
+            false => TxKind::Withdrawal,
+        };
+        let amount = (1 + self.rng.next_below(100_000)) as f64 / 100.0;
+
+        let history = self.history.entry(client_id).or_default();
+        if history.len() == DISPUTABLE_HISTORY {
+            history.remove(0);
+        }
+        history.push(tx_id);
+        TransactionDTO::new(tx_id.0, client_id, kind, Some(amount))
+    }
+}
+
+impl Iterator for WorkloadGenerator {
+    type Item = TransactionDTO;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.emitted == self.config.transactions || self.config.clients == 0 {
+            return None;
+        }
+        self.emitted += 1;
+        if let Some(tx) = self.close_dispute() {
+            return Some(tx);
+        }
+        let client_id = self.pick_client();
+        self.open_dispute(client_id)
+            .or_else(|| Some(self.adjust(client_id)))
+    }
+}
+
+/// Small, seedable PRNG (splitmix64) so workloads are reproducible without extra dependencies.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn next_below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::engine::objects::TxKind;
+
+    use super::{WorkloadConfig, WorkloadGenerator};
+
+    #[test]
+    fn same_seed_produces_same_workload() {
+        let config = WorkloadConfig {
+            transactions: 500,
+            ..Default::default()
+        };
+        let first: Vec<String> = WorkloadGenerator::new(config.clone())
+            .map(|tx| format!("{tx:?}"))
+            .collect();
+        let second: Vec<String> = WorkloadGenerator::new(config)
+            .map(|tx| format!("{tx:?}"))
+            .collect();
+
+        assert_eq!(first.len(), 500);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn disputes_reference_clients_own_adjustments() {
+        let config = WorkloadConfig {
+            clients: 10,
+            transactions: 5000,
+            dispute_rate: 0.2,
+            ..Default::default()
+        };
+        let mut owners = HashMap::new();
+        let mut disputes = 0;
+
+        for tx in WorkloadGenerator::new(config) {
+            match tx.kind {
+                TxKind::Deposit | TxKind::Withdrawal => {
+                    assert!(owners.insert(tx.id, tx.client_id).is_none());
+                }
+                _ => {
+                    disputes += 1;
+                    assert_eq!(owners.get(&tx.id), Some(&tx.client_id));
+                }
+            }
+        }
+        assert!(disputes > 0);
+    }
+}
@@ -0,0 +1,85 @@
+use std::{error::Error, str::FromStr};
+
+use crate::engine::{
+    objects::{ClientId, TransactionDTO, TransactionId, TxKind},
+    references::TransactionReferences,
+};
+
+/// Parses line with numeric transaction id.
+pub fn parse_input_line(line: String) -> Result<TransactionDTO, Box<dyn Error>> {
+    parse_fields(&line, |tx| Ok(TransactionId(tx.parse()?)))
+}
+
+/// Parses line whose transaction may be an alphanumeric reference, mapped to internal id.
+pub fn parse_referenced_input_line(
+    line: String,
+    references: &mut TransactionReferences,
+) -> Result<TransactionDTO, Box<dyn Error>> {
+    parse_fields(&line, |tx| {
+        references.resolve(tx).map_err(|e| format!("{e:?}").into())
+    })
+}
+
+fn parse_fields(
+    line: &str,
+    transaction_id: impl FnOnce(&str) -> Result<TransactionId, Box<dyn Error>>,
+) -> Result<TransactionDTO, Box<dyn Error>> {
+    let linesplit: Vec<&str> = line.split(',').collect();
+    // kind and client are checked first, so header or malformed lines don't allocate references
+    let kind = {
+        let kind_str = linesplit[0].trim();
+        TxKind::from_str(kind_str)?
+    };
+    let client_id = {
+        let client_id_str = linesplit.get(1).ok_or("client is missing")?.trim();
+        ClientId(client_id_str.parse()?)
+    };
+    Ok(TransactionDTO {
+        id: {
+            let id_str = linesplit.get(2).ok_or("tx is missing")?.trim();
+            if id_str.is_empty() {
+                return Err("tx is empty".into());
+            }
+            transaction_id(id_str)?
+        },
+        client_id,
+        kind,
+        amount: {
+            let amount_str = linesplit.get(3);
+            amount_str.and_then(|e| e.trim().parse().ok())
+        },
+        effective_at: {
+            let effective_at_str = linesplit.get(4);
+            effective_at_str.and_then(|e| e.trim().parse().ok())
+        },
+        sequence_number: {
+            let sequence_number_str = linesplit.get(5);
+            sequence_number_str.and_then(|e| e.trim().parse().ok())
+        },
+        batch_id: {
+            let batch_id_str = linesplit.get(6);
+            batch_id_str.and_then(|e| e.trim().parse().ok())
+        },
+    })
+}
+
+pub const INPUT_HEADER: &str = "type, client, tx, amount";
+
+pub fn format_input_line(tx: &TransactionDTO) -> String {
+    let line = match tx.amount {
+        Some(amount) => format!("{}, {}, {}, {}", tx.kind, *tx.client_id, *tx.id, amount),
+        None => format!("{}, {}, {},", tx.kind, *tx.client_id, *tx.id),
+    };
+    // optional columns are written up to the last one set
+    let optional = [tx.effective_at, tx.sequence_number, tx.batch_id];
+    let columns = optional
+        .iter()
+        .rposition(Option::is_some)
+        .map_or(0, |last| last + 1);
+    optional[..columns]
+        .iter()
+        .fold(line, |line, column| match column {
+            Some(value) => format!("{line}, {value}"),
+            None => format!("{line},"),
+        })
+}
@@ -0,0 +1,5 @@
+pub mod cluster;
+pub mod engine;
+pub mod generator;
+pub mod input;
+pub mod replication;
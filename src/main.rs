@@ -1,83 +1,655 @@
-use std::{env, error::Error, ops::Deref, str::FromStr};
+use std::{env, net::SocketAddr, path::PathBuf, process::ExitCode, time::Duration};
 
-use engine::{
-    objects::{ClientId, TransactionDTO, TransactionId, TxKind},
-    processor::ProcessorImpl,
-};
-use futures::{StreamExt, stream::FuturesUnordered};
-use tokio::{
-    io::AsyncBufReadExt,
-    sync::mpsc::{UnboundedReceiver, UnboundedSender},
-    task::JoinHandle,
+use p_engine::{
+    cluster::{self, NodeConfig, run_cluster, serve_node},
+    engine::{
+        Account,
+        admin::AdminCommand,
+        clock::Timestamp,
+        fees::FeeSchedule,
+        jobs::JobSchedule,
+        objects::{ClientId, RawClientId, TransactionDTO, TxAmount},
+        processor::{ProcessorConfig, ProcessorImpl, TieredLogConfig},
+        references::TransactionReferences,
+        replay::{self, RunReport},
+        risk::RiskLimits,
+        router::{RouterConfig, run_scaled},
+        shutdown::Shutdown,
+    },
+    generator::{WorkloadConfig, WorkloadGenerator},
+    input::{INPUT_HEADER, format_input_line, parse_referenced_input_line},
+    replication::{Replication, Standby},
 };
+use tokio::{io::AsyncBufReadExt, net::TcpListener, sync::mpsc::UnboundedReceiver};
 
-mod engine;
+const DEFAULT_HOT_LOG_CAPACITY: usize = 100_000;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     let args = env::args().collect::<Vec<String>>();
-    let file = tokio::fs::File::open(&args[1]).await.unwrap();
+    let options = match parse_cli_options(&args[1..]) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match &options.command {
+        CliCommand::Process { input } => process(input, &options).await,
+        CliCommand::Node { address } => serve(*address, options.processors, &options).await,
+        CliCommand::Standby { address } => stand_by(*address, &options).await,
+        CliCommand::Replay { input } => {
+            let report = replay_file(input, &options.processor).await;
+            for line in report.to_lines() {
+                println!("{line}");
+            }
+            ExitCode::SUCCESS
+        }
+        CliCommand::Diff { input, against } => diff_runs(input, against, &options).await,
+        CliCommand::Statement {
+            input,
+            client_id,
+            from,
+            to,
+            json,
+        } => {
+            print_statements(input, *client_id, *from, *to, *json, &options.processor).await;
+            ExitCode::SUCCESS
+        }
+        CliCommand::Generate(workload) => {
+            println!("{INPUT_HEADER}");
+            for tx in WorkloadGenerator::new(workload.clone()) {
+                println!("{}", format_input_line(&tx));
+            }
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+/// Fails when any processor didn't finish, e.g. within drain timeout after shutdown signal,
+/// or when reconciliation finds diverging accounts.
+async fn process(input: &str, options: &CliOptions) -> ExitCode {
+    let file = tokio::fs::File::open(input).await.unwrap();
 
     let mut reader = tokio::io::BufReader::new(file).lines();
     let (t_sender, t_receiver) = tokio::sync::mpsc::unbounded_channel::<TransactionDTO>();
+    let shutdown = Shutdown::default();
+    shutdown.on_signals();
 
-    while let Ok(Some(line)) = reader.next_line().await {
-        if let Ok(res) = parse_input_line(line) {
-            _ = t_sender.send(res)
+    // input is read while it's processed, so that shutdown stops intake part way
+    let intake = shutdown.clone();
+    tokio::spawn(async move {
+        let mut references = TransactionReferences::default();
+        while let Ok(Some(line)) = reader.next_line().await {
+            if intake.is_requested() {
+                break;
+            }
+            if let Ok(res) = parse_referenced_input_line(line, &mut references) {
+                _ = t_sender.send(res)
+            }
+        }
+        // dropping the sender lets processors finish
+    });
+
+    let admin = options
+        .admin
+        .as_ref()
+        .map(|path| read_admin_commands(path.clone()));
+    // with nodes given, processors run in their processes and this one only routes
+    let (accounts, complete) = match options.nodes.is_empty() {
+        true => {
+            let (config, replication) = replicate(options).await;
+            let outcome = run_scaled(
+                options.processors,
+                t_receiver,
+                admin,
+                config,
+                RouterConfig {
+                    input: input_fingerprint(input),
+                    ..router_config(options, shutdown)
+                },
+            )
+            .await;
+            if let Some(replication) = replication {
+                replication.finish().await;
+            }
+            let complete = outcome.is_successful();
+            (outcome.accounts, complete)
+        }
+        false => {
+            if admin.is_some() {
+                eprintln!("admin commands are taken by nodes, `--admin` of router is ignored");
+            }
+            if options.standby.is_some() {
+                eprintln!(
+                    "nodes are replicated to standbys of their own, `--standby` of router is ignored"
+                );
+            }
+            let outcome = run_cluster(t_receiver, &options.nodes).await;
+            let complete = outcome.is_complete();
+            (outcome.accounts, complete)
+        }
+    };
+    print_accounts(&accounts);
+    if !options.processor.fees.is_empty() {
+        let collected: TxAmount = accounts.iter().map(|account| account.fees).sum();
+        eprintln!("fees collected: {collected}");
+    }
+    match complete {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
+
+/// Runs `processors` of a cluster node for a single router connection.
+/// Fails when any processor didn't finish or reconciliation finds diverging accounts.
+async fn serve(address: SocketAddr, processors: u16, options: &CliOptions) -> ExitCode {
+    let listener = TcpListener::bind(address)
+        .await
+        .expect("can't listen on node address");
+    let shutdown = Shutdown::default();
+    shutdown.on_signals();
+    let admin = options
+        .admin
+        .as_ref()
+        .map(|path| read_admin_commands(path.clone()));
+    let router = router_config(options, shutdown);
+    let (config, replication) = replicate(options).await;
+    let outcome = serve_node(listener, processors, admin, config, router).await;
+    if let Some(replication) = replication {
+        replication.finish().await;
+    }
+    match outcome {
+        Ok(outcome) if outcome.is_successful() => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("serving router failed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Connects to standby given with `--standby`, processors configured with the returned
+/// config stream their events to it.
+async fn replicate(options: &CliOptions) -> (ProcessorConfig, Option<Replication>) {
+    let mut config = options.processor.clone();
+    let Some(address) = options.standby else {
+        return (config, None);
+    };
+    let replication = Replication::connect(address)
+        .await
+        .expect("can't connect to standby");
+    config.replica = Some(replication.sender());
+    (config, Some(replication))
+}
+
+/// Follows processors of a primary and prints their accounts once it's gone. Lost primary
+/// is taken over when `--promote` is given, otherwise standby fails, as it does on divergence.
+async fn stand_by(address: SocketAddr, options: &CliOptions) -> ExitCode {
+    let listener = TcpListener::bind(address)
+        .await
+        .expect("can't listen on standby address");
+    let mut standby = Standby::new(options.processor.clone());
+    let finished = standby
+        .follow(&listener)
+        .await
+        .expect("can't accept primary");
+    drop(listener);
+    print_accounts(&standby.accounts());
+    let divergences = standby.divergences();
+    if divergences > 0 {
+        eprintln!("standby diverged from primary {divergences} times");
+    }
+    match (finished, options.promote) {
+        (true, _) if divergences == 0 => ExitCode::SUCCESS,
+        (true, _) | (false, None) => ExitCode::FAILURE,
+        (false, Some(node)) => {
+            let processors = standby.promote().expect("can't checkpoint standby");
+            eprintln!("standby promoted, serving router at {node}");
+            serve(node, processors, options).await
+        }
+    }
+}
+
+/// Prints final balances, with column of reserved funds only when some are reserved.
+fn print_accounts(accounts: &[Account]) {
+    let with_reserved = accounts
+        .iter()
+        .any(|account| account.reserved != TxAmount::ZERO);
+    for account in accounts {
+        println!("{}", account.to_csv(with_reserved));
+    }
+}
+
+fn router_config(options: &CliOptions, shutdown: Shutdown) -> RouterConfig {
+    RouterConfig {
+        reconcile: options.reconcile,
+        shutdown,
+        drain_timeout: options.drain_timeout,
+        restart_attempts: options.restart_attempts,
+        input: None,
+    }
+}
+
+/// Path, size and modification time of input file, telling it apart from other inputs
+/// checkpoints could have been made over.
+fn input_fingerprint(input: &str) -> Option<String> {
+    let path = std::fs::canonicalize(input).ok()?;
+    let metadata = std::fs::metadata(&path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?;
+    Some(format!(
+        "{}:{}:{}",
+        path.display(),
+        metadata.len(),
+        modified.as_nanos()
+    ))
+}
+
+/// Reads admin commands from a file or named pipe while input is processed.
+fn read_admin_commands(path: PathBuf) -> UnboundedReceiver<AdminCommand> {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let file = match tokio::fs::File::open(&path).await {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("can't open admin commands {}: {e}", path.display());
+                return;
+            }
+        };
+        let mut reader = tokio::io::BufReader::new(file).lines();
+        while let Ok(Some(line)) = reader.next_line().await {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match AdminCommand::parse(line) {
+                Ok(command) => _ = sender.send(command),
+                Err(e) => eprintln!("admin `{line}` rejected: {e}"),
+            }
         }
+    });
+    receiver
+}
+
+/// Runs input through the engine and compares the outcome with a golden report or another input.
+async fn diff_runs(input: &str, against: &str, options: &CliOptions) -> ExitCode {
+    let actual = replay_file(input, &options.processor).await;
+    let against_text = tokio::fs::read_to_string(against).await.unwrap();
+    let expected = match RunReport::parse(&against_text) {
+        Some(golden) => golden,
+        None => replay_file(against, &options.processor).await,
+    };
+
+    let diff = replay::diff(&expected, &actual);
+    for client in &diff.clients {
+        println!(
+            "client {}: expected {}, actual {}",
+            client.client_id,
+            client.expected.as_deref().unwrap_or("<missing>"),
+            client.actual.as_deref().unwrap_or("<missing>")
+        );
+    }
+    for client in &diff.fees {
+        println!(
+            "client {}: expected fees {}, actual fees {}",
+            client.client_id,
+            client.expected.as_deref().unwrap_or("<none>"),
+            client.actual.as_deref().unwrap_or("<none>")
+        );
+    }
+    if let Some(divergence) = &diff.first_divergence {
+        let describe = |outcome: &Option<replay::Outcome>| match outcome {
+            Some(o) => format!(
+                "{} client {} tx {}: {}",
+                o.kind, *o.client_id, o.tx, o.result
+            ),
+            None => "<missing>".to_string(),
+        };
+        println!(
+            "first divergence at transaction #{}: expected {}, actual {}",
+            divergence.position,
+            describe(&divergence.expected),
+            describe(&divergence.actual)
+        );
     }
-    // In streaming input scenario (not file) this should be dropped when service receives shutdown signal
-    // It would provide graceful shutdown to all processing units
-    drop(t_sender);
 
-    run_scaled(2, t_receiver).await;
+    match diff.is_empty() {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
 }
 
-async fn run_scaled(instance_count: u16, mut rx: UnboundedReceiver<TransactionDTO>) {
-    let mut senders: Vec<UnboundedSender<TransactionDTO>> = Vec::new();
-    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+async fn replay_file(path: &str, config: &ProcessorConfig) -> RunReport {
+    let (transactions, references) = read_input(path).await;
+    replay::replay(transactions, config.clone(), &references)
+}
 
-    for i in 0..instance_count {
-        let (t_sender, t_receiver) = tokio::sync::mpsc::unbounded_channel::<TransactionDTO>();
-        let proc_handle = ProcessorImpl::run(t_receiver, i);
-        senders.push(t_sender);
-        handles.push(proc_handle.1);
+/// Replays input with history kept, then prints statements of one or every client.
+async fn print_statements(
+    path: &str,
+    client_id: Option<RawClientId>,
+    from: Timestamp,
+    to: Timestamp,
+    json: bool,
+    config: &ProcessorConfig,
+) {
+    let (transactions, references) = read_input(path).await;
+    // entries are timed by the replay clock, so statements are the same in every run
+    let clock = replay::input_clock(&transactions);
+    let config = ProcessorConfig {
+        history: true,
+        clock: clock.clone(),
+        ..config.clone()
+    };
+    let mut processor = ProcessorImpl::new(0, config);
+    replay::replay_on(&mut processor, transactions, &references, &clock);
+    let statements = processor
+        .statements(client_id.map(ClientId).as_ref(), from, to)
+        .expect("can't read history");
+    match json {
+        true => {
+            let statements: Vec<String> = statements
+                .iter()
+                .map(|statement| statement.to_json(&references))
+                .collect();
+            match client_id {
+                Some(_) => println!("{}", statements.join("\n")),
+                None => println!("[{}]", statements.join(",")),
+            }
+        }
+        false => {
+            for line in statements
+                .iter()
+                .flat_map(|statement| statement.to_csv(&references))
+            {
+                println!("{line}");
+            }
+        }
     }
+}
 
-    while let Some(transaction) = rx.recv().await {
-        let bucket = transaction.client_id.deref() % instance_count;
-        _ = senders[bucket as usize].send(transaction);
+async fn read_input(path: &str) -> (Vec<TransactionDTO>, TransactionReferences) {
+    let file = tokio::fs::File::open(path).await.unwrap();
+    let mut reader = tokio::io::BufReader::new(file).lines();
+    let mut transactions = Vec::new();
+    let mut references = TransactionReferences::default();
+    while let Ok(Some(line)) = reader.next_line().await {
+        if let Ok(tx) = parse_referenced_input_line(line, &mut references) {
+            transactions.push(tx);
+        }
     }
-    // notify instances that all inputs are processed by closing channels' tx end
-    senders.clear();
+    (transactions, references)
+}
+
+enum CliCommand {
+    Process {
+        input: String,
+    },
+    Replay {
+        input: String,
+    },
+    Diff {
+        input: String,
+        against: String,
+    },
+    Generate(WorkloadConfig),
+    Node {
+        address: SocketAddr,
+    },
+    Standby {
+        address: SocketAddr,
+    },
+    Statement {
+        input: String,
+        client_id: Option<RawClientId>,
+        from: Timestamp,
+        to: Timestamp,
+        json: bool,
+    },
+}
 
-    // wait till instances finsh work
-    handles
-        .into_iter()
-        .map(async |jh| jh.await)
-        .collect::<FuturesUnordered<_>>()
-        .collect::<Vec<_>>()
-        .await;
+struct CliOptions {
+    command: CliCommand,
+    processor: ProcessorConfig,
+    reconcile: bool,
+    admin: Option<PathBuf>,
+    drain_timeout: Option<Duration>,
+    restart_attempts: u32,
+    processors: u16,
+    nodes: Vec<NodeConfig>,
+    standby: Option<SocketAddr>,
+    promote: Option<SocketAddr>,
 }
 
-fn parse_input_line(line: String) -> Result<TransactionDTO, Box<dyn Error>> {
-    let linesplit: Vec<&str> = line.split(',').collect();
-    Ok(TransactionDTO {
-        id: {
-            let id_str = linesplit[2].trim();
-            TransactionId(id_str.parse()?)
+fn parse_cli_options(args: &[String]) -> Result<CliOptions, String> {
+    let mut positional = Vec::new();
+    let mut processor = ProcessorConfig::default();
+    let mut reconcile = false;
+    let mut admin = None;
+    let mut drain_timeout = None;
+    let mut restart_attempts = 0;
+    let mut processors = 2;
+    let mut nodes = Vec::new();
+    let mut standby = None;
+    let mut promote = None;
+    let mut workload = WorkloadConfig::default();
+    let mut log_directory = None;
+    let mut hot_log_capacity = DEFAULT_HOT_LOG_CAPACITY;
+    let mut statement_client = None;
+    let mut statement_from = 0;
+    let mut statement_to = Timestamp::MAX;
+    let mut json = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| "option value is missing".to_string())
+        };
+        match arg.as_str() {
+            "--double-entry" => processor.double_entry = true,
+            "--reconcile" => reconcile = true,
+            "--admin" => admin = Some(PathBuf::from(value()?)),
+            "--drain-timeout" => {
+                drain_timeout = Some(Duration::from_secs(
+                    value()?
+                        .parse()
+                        .map_err(|e| format!("invalid drain timeout: {e}"))?,
+                ))
+            }
+            "--restart-attempts" => {
+                restart_attempts = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid restart attempts: {e}"))?
+            }
+            "--node" => {
+                nodes.push(NodeConfig::parse(&value()?).map_err(|e| format!("invalid node: {e}"))?)
+            }
+            "--standby" => {
+                standby = Some(
+                    value()?
+                        .parse()
+                        .map_err(|e| format!("invalid standby address: {e}"))?,
+                )
+            }
+            "--promote" => {
+                promote = Some(
+                    value()?
+                        .parse()
+                        .map_err(|e| format!("invalid node address: {e}"))?,
+                )
+            }
+            "--processors" => {
+                processors = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid processor count: {e}"))?;
+                if processors == 0 {
+                    return Err("at least one processor is needed".to_string());
+                }
+            }
+            "--log-dir" => log_directory = Some(PathBuf::from(value()?)),
+            "--hot-log-capacity" => {
+                hot_log_capacity = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid hot log capacity: {e}"))?
+            }
+            "--fees" => {
+                let schedule = std::fs::read_to_string(value()?)
+                    .map_err(|e| format!("can't read fee schedule: {e}"))?;
+                processor.fees = FeeSchedule::parse(&schedule)
+                    .map_err(|e| format!("invalid fee schedule: {e}"))?
+            }
+            "--risk-limits" => {
+                let limits = std::fs::read_to_string(value()?)
+                    .map_err(|e| format!("can't read risk limits: {e}"))?;
+                processor.risk_limits =
+                    RiskLimits::parse(&limits).map_err(|e| format!("invalid risk limits: {e}"))?
+            }
+            "--authorization-expiry" => {
+                processor.authorization_expiry = Some(
+                    value()?
+                        .parse()
+                        .map_err(|e| format!("invalid authorization expiry: {e}"))?,
+                )
+            }
+            "--jobs" => {
+                let jobs = std::fs::read_to_string(value()?)
+                    .map_err(|e| format!("can't read job schedule: {e}"))?;
+                processor.jobs =
+                    JobSchedule::parse(&jobs).map_err(|e| format!("invalid job schedule: {e}"))?
+            }
+            "--sequence-timeout" => {
+                processor.sequencing.timeout = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid sequence timeout: {e}"))?
+            }
+            "--sequence-window" => {
+                processor.sequencing.window = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid sequence window: {e}"))?
+            }
+            "--checkpoint-interval" => {
+                processor.checkpoint_interval = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid checkpoint interval: {e}"))?
+            }
+            #[cfg(feature = "sqlite")]
+            "--sqlite-dir" => {
+                processor.storage = p_engine::engine::storage::StorageConfig::Sqlite {
+                    directory: PathBuf::from(value()?),
+                }
+            }
+            "--clients" => {
+                workload.clients = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid client count: {e}"))?
+            }
+            "--transactions" => {
+                workload.transactions = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid transaction count: {e}"))?
+            }
+            "--dispute-rate" => {
+                workload.dispute_rate = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid dispute rate: {e}"))?
+            }
+            "--skew" => {
+                workload.skew = value()?.parse().map_err(|e| format!("invalid skew: {e}"))?
+            }
+            "--seed" => {
+                workload.seed = value()?.parse().map_err(|e| format!("invalid seed: {e}"))?
+            }
+            "--client" => {
+                statement_client = Some(
+                    value()?
+                        .parse()
+                        .map_err(|e| format!("invalid client: {e}"))?,
+                )
+            }
+            "--from" => {
+                statement_from = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid statement start: {e}"))?
+            }
+            "--to" => {
+                statement_to = value()?
+                    .parse()
+                    .map_err(|e| format!("invalid statement end: {e}"))?
+            }
+            "--json" => json = true,
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    if let Some((a, b)) = cluster::overlapping(&nodes) {
+        return Err(format!(
+            "client ranges of nodes {} and {} overlap",
+            nodes[a].address, nodes[b].address
+        ));
+    }
+
+    // promoted standby resumes from checkpoints it leaves in its storage
+    if promote.is_some() && !processor.storage.is_persistent() {
+        return Err("--promote requires --sqlite-dir".to_string());
+    }
+
+    processor.tiered_log = log_directory.map(|directory| TieredLogConfig {
+        directory,
+        hot_capacity: hot_log_capacity,
+    });
+
+    let mut positional = positional.into_iter();
+    let mut input = |what: &str| {
+        positional
+            .next()
+            .ok_or_else(|| format!("{what} is required"))
+    };
+    let command = match input("input file path")?.as_str() {
+        "replay" => CliCommand::Replay {
+            input: input("input file path")?,
+        },
+        "diff" => CliCommand::Diff {
+            input: input("input file path")?,
+            against: input("golden report or second input")?,
+        },
+        "generate" => CliCommand::Generate(workload),
+        "node" => CliCommand::Node {
+            address: input("node address")?
+                .parse()
+                .map_err(|e| format!("invalid node address: {e}"))?,
         },
-        client_id: {
-            let client_id_str = linesplit[1].trim();
-            ClientId(client_id_str.parse()?)
+        "standby" => CliCommand::Standby {
+            address: input("standby address")?
+                .parse()
+                .map_err(|e| format!("invalid standby address: {e}"))?,
         },
-        kind: {
-            let kind_str = linesplit[0].trim();
-            TxKind::from_str(kind_str)?
+        "statement" => CliCommand::Statement {
+            input: input("input file path")?,
+            client_id: statement_client,
+            from: statement_from,
+            to: statement_to,
+            json,
         },
-        amount: {
-            let amount_str = linesplit.get(3);
-            amount_str.and_then(|e| e.trim().parse::<f32>().ok())
+        path => CliCommand::Process {
+            input: path.to_string(),
         },
+    };
+    Ok(CliOptions {
+        command,
+        processor,
+        reconcile,
+        admin,
+        drain_timeout,
+        restart_attempts,
+        processors,
+        nodes,
+        standby,
+        promote,
     })
 }
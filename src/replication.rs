@@ -0,0 +1,454 @@
+//! Processors replicated to a hot standby process over TCP. Every processor of the primary
+//! streams what it takes in, with time of it, one event per line (batches and balances are
+//! followed by a line per member or account). Standby follows each processor on one of its
+//! own, compares balances at every checkpoint, and can take over once the primary is lost.
+
+use std::{collections::BTreeMap, error::Error, io, net::SocketAddr};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter, Lines},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::JoinHandle,
+};
+
+use crate::{
+    cluster::{format_account, parse_account},
+    engine::{
+        Account, EngineError,
+        admin::AdminCommand,
+        clock::Clock,
+        objects::TransactionId,
+        processor::{ProcessorConfig, ProcessorImpl},
+        replication::{Replicated, ReplicationEvent},
+    },
+    input::{format_input_line, parse_input_line},
+};
+
+/// Stream of events of every processor to the standby.
+pub struct Replication {
+    sender: UnboundedSender<Replicated>,
+    done: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Replication {
+    /// Connects to standby listening at `address`.
+    pub async fn connect(address: SocketAddr) -> io::Result<Self> {
+        let stream = TcpStream::connect(address).await?;
+        let (sender, events) = mpsc::unbounded_channel();
+        let (done, finished) = oneshot::channel();
+        let task = tokio::spawn(stream_events(stream, events, finished));
+        Ok(Self { sender, done, task })
+    }
+
+    /// Sender for `ProcessorConfig::replica`.
+    pub fn sender(&self) -> UnboundedSender<Replicated> {
+        self.sender.clone()
+    }
+
+    /// Sends events still queued, then tells the standby that the primary finished.
+    pub async fn finish(self) {
+        _ = self.done.send(());
+        _ = self.task.await;
+    }
+}
+
+async fn stream_events(
+    stream: TcpStream,
+    mut events: UnboundedReceiver<Replicated>,
+    mut finished: oneshot::Receiver<()>,
+) {
+    let mut writer = BufWriter::new(stream);
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = &mut finished => None,
+        };
+        let Some(event) = event else {
+            break;
+        };
+        let mut sent = writer.write_all(encode(&event).as_bytes()).await;
+        // buffered events go out whenever processors have nothing more at the moment
+        if sent.is_ok() && events.is_empty() {
+            sent = writer.flush().await;
+        }
+        if let Err(e) = sent {
+            eprintln!("standby connection failed: {e}, replication stopped");
+            return;
+        }
+    }
+    let mut end = String::new();
+    while let Ok(event) = events.try_recv() {
+        end.push_str(&encode(&event));
+    }
+    end.push_str("end\n");
+    let sent = match writer.write_all(end.as_bytes()).await {
+        Ok(()) => writer.shutdown().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = sent {
+        eprintln!("standby connection failed: {e}, its last events are lost");
+    }
+}
+
+// `<shard>,<at>,<event>`, followed by lines of batch members or accounts
+fn encode(replicated: &Replicated) -> String {
+    let Replicated { shard, at, event } = replicated;
+    let mut lines = Vec::new();
+    let event = match event {
+        ReplicationEvent::Start => "start".to_string(),
+        ReplicationEvent::Transaction {
+            tx,
+            position,
+            accepted,
+        } => format!(
+            "transaction,{position},{accepted},{}",
+            format_input_line(tx)
+        ),
+        ReplicationEvent::Tick => "tick".to_string(),
+        ReplicationEvent::ApproveReview(tx_id) => format!("approve,{}", **tx_id),
+        ReplicationEvent::RejectReview(tx_id) => format!("reject,{}", **tx_id),
+        ReplicationEvent::DrainReviewQueue => "drain".to_string(),
+        ReplicationEvent::PrepareBatch(members) => {
+            lines.extend(
+                members
+                    .iter()
+                    .map(|(tx, position)| format!("{position},{}", format_input_line(tx))),
+            );
+            format!("prepare,{}", members.len())
+        }
+        ReplicationEvent::CommitBatch => "commit".to_string(),
+        ReplicationEvent::AbortBatch => "abort".to_string(),
+        ReplicationEvent::Admin(command) => format!("admin,{command}"),
+        ReplicationEvent::Checkpoint(position) => format!("checkpoint,{position}"),
+        ReplicationEvent::Input(fingerprint, shards) => format!("input,{shards},{fingerprint}"),
+        ReplicationEvent::Finish => "finish".to_string(),
+        ReplicationEvent::Balances(accounts) => {
+            lines.extend(accounts.iter().map(format_account));
+            format!("balances,{}", accounts.len())
+        }
+    };
+    let mut text = format!("{shard},{at},{event}\n");
+    for line in lines {
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text
+}
+
+enum Received {
+    Event(Replicated),
+    /// Primary finished.
+    End,
+    /// Connection ended or broke without the primary finishing.
+    Lost(String),
+}
+
+async fn receive(lines: &mut Lines<BufReader<TcpStream>>) -> Received {
+    let line = match lines.next_line().await {
+        Ok(Some(line)) => line,
+        Ok(None) => return Received::Lost("connection closed".to_string()),
+        Err(e) => return Received::Lost(e.to_string()),
+    };
+    if line == "end" {
+        return Received::End;
+    }
+    match decode(&line, lines).await {
+        Ok(event) => Received::Event(event),
+        Err(e) => Received::Lost(format!("invalid event `{line}`: {e}")),
+    }
+}
+
+async fn decode(
+    line: &str,
+    lines: &mut Lines<BufReader<TcpStream>>,
+) -> Result<Replicated, Box<dyn Error>> {
+    let mut fields = line.splitn(3, ',');
+    let mut field = || fields.next().ok_or("field is missing");
+    let shard = field()?.parse()?;
+    let at = field()?.parse()?;
+    let event = field()?;
+    let (kind, arguments) = event.split_once(',').unwrap_or((event, ""));
+    let mut following = async |count: &str| -> Result<Vec<String>, Box<dyn Error>> {
+        let mut following = Vec::new();
+        for _ in 0..count.parse::<usize>()? {
+            following.push(lines.next_line().await?.ok_or("event is cut short")?);
+        }
+        Ok(following)
+    };
+    let event = match kind {
+        "start" => ReplicationEvent::Start,
+        "transaction" => {
+            let mut arguments = arguments.splitn(3, ',');
+            let mut argument = || arguments.next().ok_or("argument is missing");
+            ReplicationEvent::Transaction {
+                position: argument()?.parse()?,
+                accepted: argument()?.parse()?,
+                tx: parse_input_line(argument()?.to_string())?,
+            }
+        }
+        "tick" => ReplicationEvent::Tick,
+        "approve" => ReplicationEvent::ApproveReview(TransactionId(arguments.parse()?)),
+        "reject" => ReplicationEvent::RejectReview(TransactionId(arguments.parse()?)),
+        "drain" => ReplicationEvent::DrainReviewQueue,
+        "prepare" => {
+            let mut members = Vec::new();
+            for member in following(arguments).await? {
+                let (position, tx) = member.split_once(',').ok_or("position is missing")?;
+                members.push((parse_input_line(tx.to_string())?, position.parse()?));
+            }
+            ReplicationEvent::PrepareBatch(members)
+        }
+        "commit" => ReplicationEvent::CommitBatch,
+        "abort" => ReplicationEvent::AbortBatch,
+        "admin" => ReplicationEvent::Admin(AdminCommand::parse(arguments)?),
+        "checkpoint" => ReplicationEvent::Checkpoint(arguments.parse()?),
+        "input" => {
+            let (shards, fingerprint) = arguments
+                .split_once(',')
+                .ok_or("number of processors is missing")?;
+            ReplicationEvent::Input(fingerprint.to_string(), shards.parse()?)
+        }
+        "finish" => ReplicationEvent::Finish,
+        "balances" => {
+            let mut accounts = Vec::new();
+            for account in following(arguments).await? {
+                accounts.push(parse_account(&account).ok_or("invalid account")?);
+            }
+            ReplicationEvent::Balances(accounts)
+        }
+        _ => return Err("unknown event".into()),
+    };
+    Ok(Replicated { shard, at, event })
+}
+
+// standby processor with input position it has followed up to
+struct Follower {
+    processor: ProcessorImpl,
+    position: u64,
+    // last position of batch prepared and not committed yet
+    batch: u64,
+}
+
+/// Follows processors of a primary, each on a processor of its own.
+pub struct Standby {
+    config: ProcessorConfig,
+    shards: BTreeMap<u16, Follower>,
+    divergences: usize,
+}
+
+impl Standby {
+    /// Processors are configured like the primary's with `config`, except for the clock,
+    /// which follows time of the primary.
+    pub fn new(config: ProcessorConfig) -> Self {
+        Self {
+            config: ProcessorConfig {
+                clock: Clock::simulated(0),
+                replica: None,
+                ..config
+            },
+            shards: BTreeMap::new(),
+            divergences: 0,
+        }
+    }
+
+    /// Follows a primary connecting to `listener` until it finishes, or the connection
+    /// is lost. Returns whether the primary finished.
+    pub async fn follow(&mut self, listener: &TcpListener) -> io::Result<bool> {
+        let (stream, peer) = listener.accept().await?;
+        eprintln!("primary {peer} connected");
+        let mut lines = BufReader::new(stream).lines();
+        loop {
+            match receive(&mut lines).await {
+                Received::Event(event) => self.apply(event),
+                Received::End => return Ok(true),
+                Received::Lost(reason) => {
+                    eprintln!("primary lost: {reason}");
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, Replicated { shard, at, event }: Replicated) {
+        if let ReplicationEvent::Start = event {
+            // restarted processor of the primary picks up from its last checkpoint, so does
+            // the standby's, its state since then is dropped first
+            self.shards.remove(&shard);
+            self.config.clock.set(at);
+            let processor = ProcessorImpl::new(shard, self.config.clone());
+            let position = processor.last_checkpoint().ok().flatten().unwrap_or(0);
+            let follower = Follower {
+                processor,
+                position,
+                batch: 0,
+            };
+            self.shards.insert(shard, follower);
+            return;
+        }
+        let Some(follower) = self.shards.get_mut(&shard) else {
+            eprintln!("standby: event of processor {shard} before it started");
+            return;
+        };
+        match &event {
+            ReplicationEvent::Transaction { position, .. }
+            | ReplicationEvent::Checkpoint(position) => {
+                follower.position = follower.position.max(*position)
+            }
+            ReplicationEvent::PrepareBatch(members) => {
+                follower.batch = members
+                    .iter()
+                    .map(|(_, position)| *position)
+                    .max()
+                    .unwrap_or(0)
+            }
+            ReplicationEvent::CommitBatch => {
+                follower.position = follower.position.max(follower.batch)
+            }
+            _ => {}
+        }
+        for divergence in follower.processor.follow(at, event) {
+            eprintln!(
+                "standby of processor {shard} diverged: {}",
+                divergence.describe()
+            );
+            self.divergences += 1;
+        }
+    }
+
+    /// Times standby disagreed with the primary so far.
+    pub fn divergences(&self) -> usize {
+        self.divergences
+    }
+
+    /// Accounts of every followed processor, ordered by client id.
+    pub fn accounts(&self) -> Vec<Account> {
+        let mut accounts: Vec<Account> = self
+            .shards
+            .values()
+            .flat_map(|follower| follower.processor.accounts().unwrap_or_default())
+            .collect();
+        accounts.sort_by_key(|account| account.client_id.0);
+        accounts
+    }
+
+    /// Checkpoints every processor at the last input it followed, so processors started
+    /// on the same storage pick up where the primary stopped. Returns number of processors.
+    pub fn promote(self) -> Result<u16, EngineError> {
+        let mut count = 0;
+        for (shard, mut follower) in self.shards {
+            follower.processor.checkpoint(follower.position)?;
+            count = count.max(shard + 1);
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use crate::engine::{
+        admin::AdminCommand,
+        objects::{ClientId, TransactionDTO, TxKind},
+        processor::ProcessorConfig,
+        replication::{Replicated, ReplicationEvent},
+        router::{RouterConfig, run_scaled},
+    };
+
+    use super::{Replication, Standby};
+
+    #[tokio::test]
+    async fn standby_follows_primary() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let standby = tokio::spawn(async move {
+            let mut standby = Standby::new(ProcessorConfig::default());
+            let finished = standby.follow(&listener).await.unwrap();
+            (standby, finished)
+        });
+
+        let replication = Replication::connect(address).await.unwrap();
+        let config = ProcessorConfig {
+            checkpoint_interval: 2,
+            replica: Some(replication.sender()),
+            ..Default::default()
+        };
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (admin, commands) = tokio::sync::mpsc::unbounded_channel();
+        for transaction in [
+            TransactionDTO::new(1, 1, TxKind::Deposit, Some(100.0)),
+            TransactionDTO::new(2, 2, TxKind::Deposit, Some(50.0)),
+            TransactionDTO::new(3, 1, TxKind::Withdrawal, Some(30.0)),
+            TransactionDTO::new(4, 2, TxKind::Withdrawal, Some(80.0)),
+            TransactionDTO::new(2, 2, TxKind::Dispute, None),
+        ] {
+            sender.send(transaction).unwrap();
+        }
+        drop(sender);
+        admin
+            .send(AdminCommand::parse("correct, 1, 2.5, goodwill").unwrap())
+            .unwrap();
+        drop(admin);
+        let outcome =
+            run_scaled(2, receiver, Some(commands), config, RouterConfig::default()).await;
+        replication.finish().await;
+        let (standby, finished) = standby.await.unwrap();
+
+        assert!(finished);
+        assert_eq!(standby.divergences(), 0);
+        let balances = |accounts: Vec<_>| -> Vec<(ClientId, f64, f64)> {
+            accounts
+                .into_iter()
+                .map(|account: super::Account| {
+                    (
+                        account.client_id,
+                        account.available.to_f64(),
+                        account.held.to_f64(),
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(balances(standby.accounts()), balances(outcome.accounts));
+        assert_eq!(
+            balances(standby.accounts()),
+            vec![(ClientId(1), 72.5, 0.0), (ClientId(2), 0.0, 50.0)]
+        );
+    }
+
+    #[test]
+    fn standby_reports_divergence() {
+        let mut standby = Standby::new(ProcessorConfig::default());
+        let event = |at, event| Replicated {
+            shard: 0,
+            at,
+            event,
+        };
+        standby.apply(event(10, ReplicationEvent::Start));
+        standby.apply(event(
+            11,
+            ReplicationEvent::Transaction {
+                tx: TransactionDTO::new(1, 1, TxKind::Deposit, Some(10.0)),
+                position: 1,
+                accepted: true,
+            },
+        ));
+        assert_eq!(standby.divergences(), 0);
+        // primary claims a withdrawal over available funds went through
+        standby.apply(event(
+            12,
+            ReplicationEvent::Transaction {
+                tx: TransactionDTO::new(2, 1, TxKind::Withdrawal, Some(20.0)),
+                position: 2,
+                accepted: true,
+            },
+        ));
+        assert_eq!(standby.divergences(), 1);
+        standby.apply(event(13, ReplicationEvent::Balances(Vec::new())));
+        assert_eq!(standby.divergences(), 2);
+    }
+}